@@ -1 +1,185 @@
-// TODO
\ No newline at end of file
+// Run with `cargo bench`, a plain binary timed with `Instant` so it works on stable
+use szimg::checksum::{Adler32, Checksum, Crc32};
+use szimg::color::Rgb;
+use szimg::jpg::{decode_jpg, DctMethod, JpegOptions, JPEG};
+use szimg::{Image, ImageBuffer};
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Checksums as the PNG encoder computed them before `szimg::checksum`
+mod baseline {
+    const CRC_TABLE: [u32; 16] = [
+        0, 0x1db71064, 0x3b6e20c8, 0x26d930ac, 0x76dc4190, 0x6b6b51f4, 0x4db26158, 0x5005713c,
+        0xedb88320, 0xf00f9344, 0xd6d6a3e8, 0xcb61b38c, 0x9b64c2b0, 0x86d3d2d4, 0xa00ae278,
+        0xbdbdf21c,
+    ];
+
+    // Two lookups in a 16-entry table per byte
+    pub fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0_u32;
+        for byte in bytes.iter() {
+            crc ^= *byte as u32;
+            crc = (crc >> 4) ^ CRC_TABLE[(crc & 15) as usize];
+            crc = (crc >> 4) ^ CRC_TABLE[(crc & 15) as usize];
+        }
+        !crc
+    }
+
+    // Two modulos per byte
+    pub fn adler32(bytes: &[u8]) -> u32 {
+        let (mut a, mut b) = (1_u32, 0_u32);
+        for byte in bytes.iter() {
+            a = (a + *byte as u32) % 65521;
+            b = (a + b) % 65521;
+        }
+        (b << 16) | a
+    }
+
+    // The JPEG encoder's DCT before the fast transforms, a cosine per term
+    pub fn dct(g: &[i32; 64]) -> [f64; 64] {
+        let mut result = [0.; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let a_u = if u == 0 { 1. / 2_f64.sqrt() } else { 1. };
+                let a_v = if v == 0 { 1. / 2_f64.sqrt() } else { 1. };
+                let mut sum = 0.;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let cos2 =
+                            f64::cos((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.);
+                        let cos1 =
+                            f64::cos((2 * y + 1) as f64 * v as f64 * std::f64::consts::PI / 16.);
+                        sum += (g[y * 8 + x] - 128) as f64 * cos1 * cos2;
+                    }
+                }
+                result[v * 8 + u] = 0.25 * a_u * a_v * sum;
+            }
+        }
+        result
+    }
+}
+
+const DATA_SIZE: usize = 16 << 20;
+const ROUNDS: usize = 5;
+
+// Best throughput of a few rounds, in MiB/s
+fn bench<F: Fn(&[u8]) -> u32>(name: &str, data: &[u8], checksum: F) -> f64 {
+    let mut best = Duration::MAX;
+    let mut value = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        value = black_box(checksum(black_box(data)));
+        best = best.min(start.elapsed());
+    }
+    let throughput = data.len() as f64 / (1 << 20) as f64 / best.as_secs_f64();
+    println!("{:<26} {:>10.1} MiB/s  {:08x}", name, throughput, value);
+    throughput
+}
+
+// Best time of a few rounds, along with the result
+fn best_time<T, F: FnMut() -> T>(rounds: usize, mut run: F) -> (Duration, T) {
+    let mut best = Duration::MAX;
+    let mut value = None;
+    for _ in 0..rounds {
+        let start = Instant::now();
+        value = Some(black_box(run()));
+        best = best.min(start.elapsed());
+    }
+    (best, value.unwrap())
+}
+
+const PLOT_SIZE: u32 = 1000;
+
+// A 1000x1000 plot: gradient background with a few thin colored curves
+fn plot() -> ImageBuffer<Rgb<u8>> {
+    ImageBuffer::from_fn(PLOT_SIZE, PLOT_SIZE, |x, y| {
+        let (fx, fy) = (x as f64 / PLOT_SIZE as f64, y as f64 / PLOT_SIZE as f64);
+        let curve = |phase: f64| ((fx * 12. + phase).sin() * 0.3 + 0.5 - fy).abs() < 0.003;
+        if curve(0.) {
+            Rgb([220, 40, 40])
+        } else if curve(2.) {
+            Rgb([40, 80, 220])
+        } else {
+            Rgb([255 - (fy * 40.) as u8, 255 - (fx * 40.) as u8, 250])
+        }
+    })
+}
+
+fn bench_jpeg() {
+    let image = plot();
+    let megapixels = (PLOT_SIZE * PLOT_SIZE) as f64 / 1e6;
+
+    // The old encoder spent nearly all of its time here, so the DCT alone
+    // bounds how fast it could be: 4 luma and 2 chroma blocks per 16x16
+    let blocks = (PLOT_SIZE as usize).div_ceil(16).pow(2) * 6;
+    let block: [i32; 64] =
+        std::array::from_fn(|i| image.get_pixel(i as u32 % 8, i as u32 / 8).0[0] as i32);
+    let (elapsed, _) = best_time(1, || {
+        (0..blocks)
+            .map(|_| baseline::dct(black_box(&block))[0])
+            .sum::<f64>()
+    });
+    let old = megapixels / elapsed.as_secs_f64();
+    println!("{:<26} {:>10.2} MP/s", "jpeg dct alone (textbook)", old);
+
+    let mut decoded = Vec::new();
+    for (name, method) in [
+        ("jpeg (float dct)", DctMethod::Float),
+        ("jpeg (integer dct)", DctMethod::Integer),
+    ] {
+        let options = JpegOptions {
+            dct_method: method,
+            ..JpegOptions::default()
+        };
+        let (elapsed, bytes) = best_time(ROUNDS, || {
+            JPEG::from_buffer_with_options(&image, &options)
+                .unwrap()
+                .encode_to_vec()
+                .unwrap()
+        });
+        let new = megapixels / elapsed.as_secs_f64();
+        println!(
+            "{:<26} {:>10.2} MP/s  {} bytes, {:.0}x",
+            name,
+            new,
+            bytes.len(),
+            new / old
+        );
+        decoded.push(decode_jpg(&mut &bytes[..]).unwrap().to_rgb8());
+    }
+
+    // Both variants round the same coefficients, only ties may come out apart.
+    // The unit tests in `dct.rs` check each of them against the textbook DCT
+    let samples = decoded[0]
+        .pixels()
+        .zip(decoded[1].pixels())
+        .flat_map(|(a, b)| (0..3).map(move |c| (a.0[c] as i32 - b.0[c] as i32).abs()));
+    let (total, largest) = samples.fold((0, 0), |(total, largest), difference| {
+        (total + difference, largest.max(difference))
+    });
+    let mean = total as f64 / (PLOT_SIZE * PLOT_SIZE * 3) as f64;
+    println!(
+        "{:<26} {:>10.4} mean, {} largest",
+        "float vs integer", mean, largest
+    );
+    assert!(mean < 0.1);
+}
+
+fn main() {
+    let data: Vec<u8> = (0..DATA_SIZE as u64)
+        .map(|i| (i * i * 31 + i / 7) as u8)
+        .collect();
+
+    let old = bench("crc32 (nibble table)", &data, baseline::crc32);
+    let new = bench("crc32 (slice-by-8)", &data, Crc32::checksum);
+    println!("{:<26} {:>10.1}x", "speedup", new / old);
+    assert_eq!(baseline::crc32(&data), Crc32::checksum(&data));
+
+    let old = bench("adler32 (modulo per byte)", &data, baseline::adler32);
+    let new = bench("adler32 (deferred modulo)", &data, Adler32::checksum);
+    println!("{:<26} {:>10.1}x", "speedup", new / old);
+    assert_eq!(baseline::adler32(&data), Adler32::checksum(&data));
+
+    bench_jpeg();
+}
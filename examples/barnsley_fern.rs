@@ -1,8 +1,10 @@
 use rand::Rng;
-use szimg::netpbm::{save_ppm, Mode};
+use szimg::netpbm::{save_ppm_buffer, Mode};
+use szimg::color::Rgb;
+use szimg::ImageBuffer;
 
-const WIDTH: usize = 600;
-const HEIGHT: usize = 600;
+const WIDTH: u32 = 600;
+const HEIGHT: u32 = 600;
 const ITER_TIME: usize = 100000;
 
 // See https://en.wikipedia.org/wiki/Barnsley_fern for more details
@@ -15,8 +17,8 @@ fn iter(rng: &mut rand::prelude::ThreadRng, (x, y): &(f32, f32)) -> (f32, f32) {
     }
 }
 
-fn create_barnsley_fern() -> [[[u8; 3]; WIDTH]; HEIGHT] {
-    let mut data = [[[0; 3]; WIDTH]; HEIGHT];
+fn create_barnsley_fern() -> ImageBuffer<Rgb<u8>> {
+    let mut data = ImageBuffer::<Rgb<u8>>::new(WIDTH, HEIGHT);
     let mut rng = rand::thread_rng();
 
     let mut pair = (0., 0.);
@@ -26,7 +28,7 @@ fn create_barnsley_fern() -> [[[u8; 3]; WIDTH]; HEIGHT] {
             80. * pair.0 + WIDTH as f32 / 2.,
             HEIGHT as f32 - (50. * pair.1 + 50.),
         );
-        data[pos.1 as usize][pos.0 as usize][1] = 255;
+        data.get_pixel_mut(pos.0 as u32, pos.1 as u32)[1] = 255;
 
         pair = iter(&mut rng, &pair);
     }
@@ -36,5 +38,5 @@ fn create_barnsley_fern() -> [[[u8; 3]; WIDTH]; HEIGHT] {
 
 fn main() {
     let data = create_barnsley_fern();
-    save_ppm("./image/barnsley_fern.ppm", data, 255, Mode::Binary).unwrap();
+    save_ppm_buffer("./image/barnsley_fern.ppm", &data, 255, Mode::Binary).unwrap();
 }
@@ -55,6 +55,7 @@ impl Div for Complex {
 }
 
 impl Complex {
+    #[allow(dead_code)]
     pub fn conjugate(&self) -> Self {
         Self {
             real: self.real,
@@ -68,4 +69,5 @@ impl Complex {
 }
 
 // Only to make cargo happy
+#[allow(dead_code)]
 fn main() {}
\ No newline at end of file
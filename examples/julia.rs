@@ -1,10 +1,12 @@
 mod complex;
 
 use complex::Complex;
-use szimg::netpbm::{save_ppm, Mode};
+use szimg::netpbm::{save_ppm_buffer, Mode};
+use szimg::color::Rgb;
+use szimg::ImageBuffer;
 
-const WIDTH: usize = 1000;
-const HEIGHT: usize = 1000;
+const WIDTH: u32 = 1000;
+const HEIGHT: u32 = 1000;
 const SCALED_FACTOR: f64 = 1.;
 const MAX_ITER_TIME: u8 = 255;
 const THRESHOLD: f64 = 10000.;
@@ -35,32 +37,26 @@ fn iter(init: Complex) -> u8 {
 }
 
 // See https://en.wikipedia.org/wiki/Julia_set for more details
-fn create_julia() -> [[[u8; 3]; WIDTH]; HEIGHT] {
-    let mut res = [[[0; 3]; WIDTH]; HEIGHT];
-
-    // Cache friendly loop
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-            let (zx, zy) = scale(
-                x as f64,
-                y as f64,
-                SCALED_FACTOR,
-                WIDTH as isize,
-                HEIGHT as isize,
-            );
-            let zc = Complex {
-                real: zx as f64,
-                imaginary: zy as f64,
-            };
-            let iter_time = iter(zc);
-            res[y][x][0] = if iter_time == 200 { 0 } else { iter_time };
-        }
-    }
-
-    res
+// The image is too large to live on the stack
+fn create_julia() -> ImageBuffer<Rgb<u8>> {
+    ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+        let (zx, zy) = scale(
+            x as f64,
+            y as f64,
+            SCALED_FACTOR,
+            WIDTH as isize,
+            HEIGHT as isize,
+        );
+        let zc = Complex {
+            real: zx,
+            imaginary: zy,
+        };
+        let iter_time = iter(zc);
+        Rgb([if iter_time == 200 { 0 } else { iter_time }, 0, 0])
+    })
 }
 
 fn main() {
     let data = create_julia();
-    save_ppm("./image/julia_set.ppm", data, 255, Mode::Binary).unwrap();
+    save_ppm_buffer("./image/julia_set.ppm", &data, 255, Mode::Binary).unwrap();
 }
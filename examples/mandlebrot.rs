@@ -1,10 +1,12 @@
 mod complex;
 
 use complex::Complex;
-use szimg::netpbm::{save_ppm, Mode};
+use szimg::netpbm::{save_ppm_buffer, Mode};
+use szimg::color::Rgb;
+use szimg::ImageBuffer;
 
-const WIDTH: usize = 1000;
-const HEIGHT: usize = 1000;
+const WIDTH: u32 = 1000;
+const HEIGHT: u32 = 1000;
 
 const SCALED_X: f64 = 2.48 / 999.;
 const SCALED_Y: f64 = 2.26 / 999.;
@@ -27,27 +29,21 @@ fn iter(c: Complex) -> u8 {
 }
 
 // See https://en.wikipedia.org/wiki/Mandelbrot_set for more details
-fn create_mandlebrot() -> [[[u8; 3]; WIDTH]; HEIGHT] {
-    let mut res = [[[0; 3]; WIDTH]; HEIGHT];
-
-    // Cache friendly loop
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-            let temp = Complex {
-                real: SCALED_X * x as f64 + OFFSET_X,
-                imaginary: SCALED_Y * y as f64 + OFFSET_Y,
-            };
-            let iter_time = iter(temp);
-            // Magic number 5 is to map [0, 50] to [0, 250](almost 255)
-            // That's to reduce CPU's budern
-            res[y][x][2] = iter_time * 5;
-        }
-    }
-
-    res
+// The image is too large to live on the stack
+fn create_mandlebrot() -> ImageBuffer<Rgb<u8>> {
+    ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+        let temp = Complex {
+            real: SCALED_X * x as f64 + OFFSET_X,
+            imaginary: SCALED_Y * y as f64 + OFFSET_Y,
+        };
+        let iter_time = iter(temp);
+        // Magic number 5 is to map [0, 50] to [0, 250](almost 255)
+        // That's to reduce CPU's budern
+        Rgb([0, 0, iter_time * 5])
+    })
 }
 
 fn main() {
     let data = create_mandlebrot();
-    save_ppm("./image/mandlebrot.ppm", data, 255, Mode::Binary).unwrap();
+    save_ppm_buffer("./image/mandlebrot.ppm", &data, 255, Mode::Binary).unwrap();
 }
@@ -0,0 +1,468 @@
+# Baseline and progressive JPEG fixtures for the decoder tests, written with the standard tables.
+# Run from the repository root: python3 script/jpeg_fixtures.py [output directory]
+import math, os, sys
+
+OUT = sys.argv[1] if len(sys.argv) > 1 else "tests/templates/jpg"
+os.makedirs(OUT, exist_ok=True)
+
+ZIGZAG = [0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34,
+          27, 20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+          58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63]
+LUMA_Q = [16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+          14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92,
+          49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99]
+CHROMA_Q = [17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+            47, 66, 99, 99, 99, 99, 99, 99] + [99] * 32
+DC_L = ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], list(range(12)))
+DC_C = ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], list(range(12)))
+AC_L = ([0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d], bytes.fromhex(
+    "01020300041105122131410613516107227114328191a1082342b1c11552d1f02433627282090a161718191a25262728292a3435363738393a434445464748494a535455565758595a636465666768696a737475767778797a838485868788898a92939495969798999aa2a3a4a5a6a7a8a9aab2b3b4b5b6b7b8b9bac2c3c4c5c6c7c8c9cad2d3d4d5d6d7d8d9dae1e2e3e4e5e6e7e8e9eaf1f2f3f4f5f6f7f8f9fa"))
+AC_C = ([0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77], bytes.fromhex(
+    "000102031104052131061241510761711322328108144291a1b1c109233352f0156272d10a162434e125f11718191a262728292a35363738393a434445464748494a535455565758595a636465666768696a737475767778797a82838485868788898a92939495969798999aa2a3a4a5a6a7a8a9aab2b3b4b5b6b7b8b9bac2c3c4c5c6c7c8c9cad2d3d4d5d6d7d8d9dae2e3e4e5e6e7e8e9eaf2f3f4f5f6f7f8f9fa"))
+
+
+def scale(table, quality):
+    s = 5000 // quality if quality < 50 else 200 - quality * 2
+    return [min(max((q * s + 50) // 100, 1), 255) for q in table]
+
+
+def codes(spec):
+    counts, values = spec
+    table, code, k = {}, 0, 0
+    for length in range(1, 17):
+        for _ in range(counts[length - 1]):
+            table[values[k]] = (code, length)
+            code += 1
+            k += 1
+        code <<= 1
+    return table
+
+
+class Writer:
+    def __init__(self):
+        self.out = bytearray()
+        self.acc = 0
+        self.n = 0
+
+    def bits(self, value, n):
+        for i in range(n - 1, -1, -1):
+            self.acc = (self.acc << 1) | ((value >> i) & 1)
+            self.n += 1
+            if self.n == 8:
+                self.out.append(self.acc)
+                if self.acc == 0xff:
+                    self.out.append(0)
+                self.acc, self.n = 0, 0
+
+    def pad(self):
+        while self.n:
+            self.bits(1, 1)
+
+
+def fdct(block):
+    out = [0.0] * 64
+    for v in range(8):
+        for u in range(8):
+            s = 0.0
+            for y in range(8):
+                for x in range(8):
+                    s += block[y * 8 + x] * math.cos((2 * x + 1) * u * math.pi / 16) * math.cos((2 * y + 1) * v * math.pi / 16)
+            cu = 1 / math.sqrt(2) if u == 0 else 1
+            cv = 1 / math.sqrt(2) if v == 0 else 1
+            out[v * 8 + u] = 0.25 * cu * cv * s
+    return out
+
+
+def magnitude(value):
+    n = abs(value).bit_length()
+    return n, (value if value >= 0 else value + (1 << n) - 1)
+
+
+def segment(marker, payload):
+    return bytes([0xff, marker]) + (len(payload) + 2).to_bytes(2, "big") + payload
+
+
+def prepare(width, height, pixel, sampling, quality):
+    gray = len(sampling) == 1
+    planes = []
+    for c in range(len(sampling)):
+        plane = []
+        for y in range(height):
+            for x in range(width):
+                p = pixel(x, y)
+                if gray:
+                    plane.append(p)
+                else:
+                    r, g, b = p
+                    plane.append([0.299 * r + 0.587 * g + 0.114 * b,
+                                  -0.168736 * r - 0.331264 * g + 0.5 * b + 128,
+                                  0.5 * r - 0.418688 * g - 0.081312 * b + 128][c])
+        planes.append(plane)
+    hmax = max(h for h, v in sampling)
+    vmax = max(v for h, v in sampling)
+    mcux = -(-width // (8 * hmax))
+    mcuy = -(-height // (8 * vmax))
+    tables = [scale(LUMA_Q, quality), scale(CHROMA_Q, quality)]
+
+    # Downsample by averaging, padding by edge replication
+    comps = []
+    for c, (h, v) in enumerate(sampling):
+        fx, fy = hmax // h, vmax // v
+        cw, ch = -(-width * h // hmax), -(-height * v // vmax)
+        bw, bh = mcux * h * 8, mcuy * v * 8
+        data = []
+        for y in range(bh):
+            for x in range(bw):
+                sx, sy = min(x, cw - 1), min(y, ch - 1)
+                total = 0
+                for dy in range(fy):
+                    for dx in range(fx):
+                        total += planes[c][min(sy * fy + dy, height - 1) * width + min(sx * fx + dx, width - 1)]
+                data.append(total / (fx * fy))
+        comps.append((data, bw, cw, ch))
+
+    def block(c, bx, by):
+        data, bw, _, _ = comps[c]
+        samples = [data[(by * 8 + y) * bw + bx * 8 + x] - 128 for y in range(8) for x in range(8)]
+        coefficients = fdct(samples)
+        q = tables[min(c, 1)]
+        return [int(round(coefficients[ZIGZAG[k]] / q[k])) for k in range(64)]
+
+    return gray, tables, mcux, mcuy, comps, block
+
+
+def encode(name, width, height, pixel, sampling, quality=90, restart=0, separate=False, extra=b"", fill=False):
+    gray, tables, mcux, mcuy, comps, block = prepare(width, height, pixel, sampling, quality)
+    dc_codes = [codes(DC_L), codes(DC_C)]
+    ac_codes = [codes(AC_L), codes(AC_C)]
+
+    def write_block(w, c, coefficients, predictors):
+        t = min(c, 1)
+        diff = coefficients[0] - predictors[c]
+        predictors[c] = coefficients[0]
+        n, bits = magnitude(diff)
+        w.bits(*dc_codes[t][n])
+        w.bits(bits, n)
+        run = 0
+        for k in range(1, 64):
+            if coefficients[k] == 0:
+                run += 1
+                continue
+            while run > 15:
+                w.bits(*ac_codes[t][0xf0])
+                run -= 16
+            n, bits = magnitude(coefficients[k])
+            w.bits(*ac_codes[t][(run << 4) | n])
+            w.bits(bits, n)
+            run = 0
+        if run:
+            w.bits(*ac_codes[t][0])
+
+    out = bytearray(b"\xff\xd8")
+    out += extra
+    for t in range(1 if gray else 2):
+        out += segment(0xdb, bytes([t]) + bytes(tables[t]))
+    sof = bytes([8]) + height.to_bytes(2, "big") + width.to_bytes(2, "big") + bytes([len(sampling)])
+    for c, (h, v) in enumerate(sampling):
+        sof += bytes([c + 1, h << 4 | v, min(c, 1)])
+    out += segment(0xc0, sof)
+    for t, spec in enumerate([DC_L, DC_C][: 1 if gray else 2]):
+        out += segment(0xc4, bytes([t]) + bytes(spec[0]) + bytes(spec[1]))
+    for t, spec in enumerate([AC_L, AC_C][: 1 if gray else 2]):
+        out += segment(0xc4, bytes([0x10 | t]) + bytes(spec[0]) + bytes(spec[1]))
+    if restart:
+        out += segment(0xdd, restart.to_bytes(2, "big"))
+
+    def scan(components, units):
+        nonlocal out
+        header = bytes([len(components)])
+        for c in components:
+            header += bytes([c + 1, min(c, 1) << 4 | min(c, 1)])
+        if fill:
+            out += b"\xff\xff"
+        out += segment(0xda, header + bytes([0, 63, 0]))
+        w = Writer()
+        predictors = [0] * len(sampling)
+        for i, unit in enumerate(units):
+            if restart and i and i % restart == 0:
+                w.pad()
+                w.out += bytes([0xff, 0xd0 + (i // restart - 1) % 8])
+                predictors = [0] * len(sampling)
+            for c, bx, by in unit:
+                write_block(w, c, block(c, bx, by), predictors)
+        w.pad()
+        out += w.out
+
+    if separate or gray:
+        for c, (h, v) in enumerate(sampling):
+            _, _, cw, ch = comps[c]
+            units = [[(c, bx, by)] for by in range(-(-ch // 8)) for bx in range(-(-cw // 8))]
+            scan([c], units)
+    else:
+        units = []
+        for my in range(mcuy):
+            for mx in range(mcux):
+                unit = []
+                for c, (h, v) in enumerate(sampling):
+                    for y in range(v):
+                        for x in range(h):
+                            unit.append((c, mx * h + x, my * v + y))
+                units.append(unit)
+        scan(list(range(len(sampling))), units)
+    out += b"\xff\xd9"
+    with open(os.path.join(OUT, name + ".jpg"), "wb") as f:
+        f.write(out)
+
+
+# Code lengths of JPEG Annex K.2, limited to 16 bits
+def optimal_spec(frequencies):
+    freq = [frequencies.get(i, 0) for i in range(256)] + [1]
+    size = [0] * 257
+    others = [-1] * 257
+    while True:
+        candidates = sorted((f, -i) for i, f in enumerate(freq) if f > 0)
+        if len(candidates) < 2:
+            break
+        v1, v2 = -candidates[0][1], -candidates[1][1]
+        if v1 > v2:
+            v1, v2 = v2, v1
+        freq[v1] += freq[v2]
+        freq[v2] = 0
+        size[v1] += 1
+        while others[v1] >= 0:
+            v1 = others[v1]
+            size[v1] += 1
+        others[v1] = v2
+        size[v2] += 1
+        while others[v2] >= 0:
+            v2 = others[v2]
+            size[v2] += 1
+    bits = [0] * 33
+    for i in range(257):
+        if size[i]:
+            bits[size[i]] += 1
+    for i in range(32, 16, -1):
+        while bits[i] > 0:
+            j = i - 2
+            while bits[j] == 0:
+                j -= 1
+            bits[i] -= 2
+            bits[i - 1] += 1
+            bits[j + 1] += 2
+            bits[j] -= 1
+    i = 16
+    while bits[i] == 0:
+        i -= 1
+    bits[i] -= 1
+    values = [v for length in range(1, 33) for v in range(256) if size[v] == length]
+    return (bits[1:17], values)
+
+
+class Recorder:
+    """Counts symbols on the first pass, writes them with the tables on the second"""
+
+    def __init__(self, tables=None):
+        self.writer = Writer()
+        self.tables = tables
+        self.frequencies = {}
+
+    def symbol(self, table, value):
+        if self.tables is None:
+            counts = self.frequencies.setdefault(table, {})
+            counts[value] = counts.get(value, 0) + 1
+        else:
+            self.writer.bits(*self.tables[table][value])
+
+    def bits(self, value, n):
+        if self.tables is not None and n:
+            self.writer.bits(value & ((1 << n) - 1), n)
+
+
+def encode_progressive(name, width, height, pixel, sampling, script, quality=90, restart=0):
+    gray, tables, mcux, mcuy, comps, block = prepare(width, height, pixel, sampling, quality)
+    cache = {}
+
+    def coefficients(c, bx, by):
+        if (c, bx, by) not in cache:
+            cache[(c, bx, by)] = block(c, bx, by)
+        return cache[(c, bx, by)]
+
+    out = bytearray(b"\xff\xd8")
+    for t in range(1 if gray else 2):
+        out += segment(0xdb, bytes([t]) + bytes(tables[t]))
+    sof = bytes([8]) + height.to_bytes(2, "big") + width.to_bytes(2, "big") + bytes([len(sampling)])
+    for c, (h, v) in enumerate(sampling):
+        sof += bytes([c + 1, h << 4 | v, min(c, 1)])
+    out += segment(0xc2, sof)
+    if restart:
+        out += segment(0xdd, restart.to_bytes(2, "big"))
+
+    for components, ss, se, ah, al in script:
+        if len(components) == 1:
+            c = components[0]
+            _, _, cw, ch = comps[c]
+            units = [[(c, bx, by)] for by in range(-(-ch // 8)) for bx in range(-(-cw // 8))]
+        else:
+            units = []
+            for my in range(mcuy):
+                for mx in range(mcux):
+                    unit = []
+                    for c in components:
+                        h, v = sampling[c]
+                        for y in range(v):
+                            for x in range(h):
+                                unit.append((c, mx * h + x, my * v + y))
+                    units.append(unit)
+
+        # DC scans use table 0 for luma and 1 for chroma, AC scans always table 0
+        def table_of(c):
+            return ("dc", min(c, 1)) if ss == 0 else ("ac", 0)
+
+        def run(out):
+            state = {"eobrun": 0, "pending": []}
+
+            def emit_eobrun():
+                if state["eobrun"]:
+                    n = state["eobrun"].bit_length() - 1
+                    out.symbol(("ac", 0), n << 4)
+                    out.bits(state["eobrun"], n)
+                    state["eobrun"] = 0
+                    for bit in state["pending"]:
+                        out.bits(bit, 1)
+                    state["pending"] = []
+
+            predictors = [0] * len(sampling)
+            for i, unit in enumerate(units):
+                if restart and i and i % restart == 0:
+                    emit_eobrun()
+                    if out.tables is not None:
+                        out.writer.pad()
+                        out.writer.out += bytes([0xff, 0xd0 + (i // restart - 1) % 8])
+                    predictors = [0] * len(sampling)
+                for c, bx, by in unit:
+                    coef = coefficients(c, bx, by)
+                    if ss == 0 and ah == 0:
+                        value = coef[0] >> al
+                        diff = value - predictors[c]
+                        predictors[c] = value
+                        n, bits = magnitude(diff)
+                        out.symbol(table_of(c), n)
+                        out.bits(bits, n)
+                    elif ss == 0:
+                        out.bits((coef[0] >> al) & 1, 1)
+                    elif ah == 0:
+                        r = 0
+                        for k in range(ss, se + 1):
+                            value = coef[k]
+                            magnitude_value = (abs(value) >> al)
+                            if magnitude_value == 0:
+                                r += 1
+                                continue
+                            emit_eobrun()
+                            while r > 15:
+                                out.symbol(("ac", 0), 0xf0)
+                                r -= 16
+                            n = magnitude_value.bit_length()
+                            out.symbol(("ac", 0), (r << 4) | n)
+                            out.bits(magnitude_value if value > 0 else ~magnitude_value, n)
+                            r = 0
+                        if r:
+                            state["eobrun"] += 1
+                            if state["eobrun"] == 0x7fff:
+                                emit_eobrun()
+                    else:
+                        absolute = [abs(coef[k]) >> al for k in range(64)]
+                        eob = max([k for k in range(ss, se + 1) if absolute[k] == 1], default=0)
+                        r = 0
+                        corrections = []
+                        for k in range(ss, se + 1):
+                            value = absolute[k]
+                            if value == 0:
+                                r += 1
+                                continue
+                            while r > 15 and k <= eob:
+                                emit_eobrun()
+                                out.symbol(("ac", 0), 0xf0)
+                                r -= 16
+                                for bit in corrections:
+                                    out.bits(bit, 1)
+                                corrections = []
+                            if value > 1:
+                                corrections.append(value & 1)
+                                continue
+                            emit_eobrun()
+                            out.symbol(("ac", 0), (r << 4) | 1)
+                            out.bits(0 if coef[k] < 0 else 1, 1)
+                            for bit in corrections:
+                                out.bits(bit, 1)
+                            corrections = []
+                            r = 0
+                        if r or corrections:
+                            state["eobrun"] += 1
+                            state["pending"] += corrections
+                            if state["eobrun"] == 0x7fff or len(state["pending"]) > 937:
+                                emit_eobrun()
+            emit_eobrun()
+
+        counter = Recorder()
+        run(counter)
+        specs = {table: optimal_spec(f) for table, f in sorted(counter.frequencies.items())}
+        for (kind, t), spec in specs.items():
+            out += segment(0xc4, bytes([(0x10 if kind == "ac" else 0) | t]) + bytes(spec[0]) + bytes(spec[1]))
+        writer = Recorder({table: codes(spec) for table, spec in specs.items()})
+        run(writer)
+        writer.writer.pad()
+        header = bytes([len(components)])
+        for c in components:
+            _, t = table_of(c)
+            header += bytes([c + 1, (t << 4 | t) if ss == 0 else t])
+        out += segment(0xda, header + bytes([ss, se, ah << 4 | al]))
+        out += writer.writer.out
+    out += b"\xff\xd9"
+    with open(os.path.join(OUT, name + ".jpg"), "wb") as f:
+        f.write(out)
+
+
+# Spectral selection only
+SELECTION = [([0, 1, 2], 0, 0, 0, 0), ([0], 1, 5, 0, 0), ([2], 1, 63, 0, 0), ([1], 1, 63, 0, 0), ([0], 6, 63, 0, 0)]
+# The default script of libjpeg, with successive approximation
+APPROXIMATION = [([0, 1, 2], 0, 0, 0, 1), ([0], 1, 5, 0, 2), ([2], 1, 63, 0, 1), ([1], 1, 63, 0, 1),
+                 ([0], 6, 63, 0, 2), ([0], 1, 63, 2, 1), ([0, 1, 2], 0, 0, 1, 0), ([2], 1, 63, 1, 0),
+                 ([1], 1, 63, 1, 0), ([0], 1, 63, 1, 0)]
+GRAY_APPROXIMATION = [([0], 0, 0, 0, 1), ([0], 1, 5, 0, 2), ([0], 6, 63, 0, 2), ([0], 1, 63, 2, 1),
+                      ([0], 0, 0, 1, 0), ([0], 1, 63, 1, 0)]
+
+
+def rgb(x, y):
+    return (x * 255 // 36, y * 255 // 28, (x + y) * 255 // 64)
+
+
+def gray(x, y):
+    return x * 3 + y * 2
+
+
+jfif = segment(0xe0, b"JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00")
+comment = segment(0xfe, b"szimg test fixture")
+encode("ycbcr_444", 37, 29, rgb, [(1, 1), (1, 1), (1, 1)], extra=jfif)
+encode("ycbcr_422", 37, 29, rgb, [(2, 1), (1, 1), (1, 1)], extra=jfif)
+encode("ycbcr_420", 37, 29, rgb, [(2, 2), (1, 1), (1, 1)], extra=jfif + comment)
+encode("ycbcr_440", 37, 29, rgb, [(1, 2), (1, 1), (1, 1)])
+encode("ycbcr_420_restart", 37, 29, rgb, [(2, 2), (1, 1), (1, 1)], restart=2, fill=True)
+encode("ycbcr_420_separate", 37, 29, rgb, [(2, 2), (1, 1), (1, 1)], separate=True)
+encode("gray", 37, 29, gray, [(1, 1)], extra=comment)
+encode("gray_restart", 37, 29, gray, [(1, 1)], restart=5)
+encode("gray_q100", 16, 16, gray, [(1, 1)], quality=100)
+encode_progressive("progressive_420", 37, 29, rgb, [(2, 2), (1, 1), (1, 1)], APPROXIMATION)
+encode_progressive("progressive_444", 37, 29, rgb, [(1, 1), (1, 1), (1, 1)], SELECTION)
+encode_progressive("progressive_420_restart", 37, 29, rgb, [(2, 2), (1, 1), (1, 1)], APPROXIMATION, restart=3)
+encode_progressive("progressive_gray", 37, 29, gray, [(1, 1)], GRAY_APPROXIMATION)
+encode_progressive("progressive_gray_q100", 16, 16, gray, [(1, 1)], GRAY_APPROXIMATION, quality=100)
+
+
+# Sharp edges give long runs of zeros and many refinement bits
+def pattern(x, y):
+    return 200 if (x // 5 + y // 7) % 2 else 40 + x // 4
+
+
+encode("gray_large", 160, 120, pattern, [(1, 1)], quality=75)
+encode_progressive("progressive_gray_large", 160, 120, pattern, [(1, 1)], GRAY_APPROXIMATION, quality=75)
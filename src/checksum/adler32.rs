@@ -0,0 +1,49 @@
+use super::Checksum;
+
+// Largest prime below 2^16
+const BASE: u32 = 65521;
+// Most bytes summed before `b` could overflow 32 bits, so the modulo is taken once per run
+const NMAX: usize = 5552;
+
+/// Adler-32 of zlib streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Checksum for Adler32 {
+    fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        let (mut a, mut b) = (self.a, self.b);
+        for run in bytes.chunks(NMAX) {
+            for &byte in run.iter() {
+                a += byte as u32;
+                b += a;
+            }
+            a %= BASE;
+            b %= BASE;
+        }
+        self.a = a;
+        self.b = b;
+    }
+
+    fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    // The bytes of the second piece each add the first `a` once more to `b`
+    fn combine(first: u32, second: u32, second_length: u64) -> u32 {
+        let base = BASE as u64;
+        let length = second_length % base;
+        let (a1, b1) = ((first & 0xffff) as u64, (first >> 16) as u64);
+        let (a2, b2) = ((second & 0xffff) as u64, (second >> 16) as u64);
+        // The initial 1 of the second `a` is counted twice
+        let a = (a1 + a2 + base - 1) % base;
+        let b = (b1 + b2 + length * a1 + base - length) % base;
+        (b << 16 | a) as u32
+    }
+}
@@ -0,0 +1,113 @@
+use super::Checksum;
+
+// Reversed polynomial of CRC-32 (ISO-HDLC), as used by PNG and gzip
+const POLYNOMIAL: u32 = 0xedb88320;
+
+// TABLES[0] is the usual byte table, TABLES[k] advances a byte followed by k zero bytes
+const TABLES: [[u32; 256]; 8] = make_tables();
+
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][n] = crc;
+        n += 1;
+    }
+
+    let mut n = 0;
+    while n < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let previous = tables[k - 1][n];
+            tables[k][n] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            k += 1;
+        }
+        n += 1;
+    }
+    tables
+}
+
+/// CRC-32 reading 8 bytes per step with slice-by-8 tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Checksum for Crc32 {
+    fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.crc;
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let low = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) ^ crc;
+            let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            crc = TABLES[7][(low & 0xff) as usize]
+                ^ TABLES[6][(low >> 8 & 0xff) as usize]
+                ^ TABLES[5][(low >> 16 & 0xff) as usize]
+                ^ TABLES[4][(low >> 24) as usize]
+                ^ TABLES[3][(high & 0xff) as usize]
+                ^ TABLES[2][(high >> 8 & 0xff) as usize]
+                ^ TABLES[1][(high >> 16 & 0xff) as usize]
+                ^ TABLES[0][(high >> 24) as usize];
+        }
+        for &byte in chunks.remainder() {
+            crc = (crc >> 8) ^ TABLES[0][((crc ^ byte as u32) & 0xff) as usize];
+        }
+        self.crc = crc;
+    }
+
+    fn value(&self) -> u32 {
+        !self.crc
+    }
+
+    // Appending n bytes multiplies the first CRC by x^(8n) modulo the polynomial
+    fn combine(first: u32, second: u32, second_length: u64) -> u32 {
+        multiply(x_power(second_length), first) ^ second
+    }
+}
+
+// Product of two polynomials modulo the CRC polynomial, bit 31 holds x^0
+fn multiply(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut mask = 1 << 31;
+    while mask != 0 {
+        if a & mask != 0 {
+            product ^= b;
+        }
+        b = if b & 1 == 1 {
+            (b >> 1) ^ POLYNOMIAL
+        } else {
+            b >> 1
+        };
+        mask >>= 1;
+    }
+    product
+}
+
+// x^(8 * length) modulo the CRC polynomial, by repeated squaring
+fn x_power(mut length: u64) -> u32 {
+    let mut result = 1 << 31;
+    // x^8
+    let mut square = 1 << 23;
+    while length != 0 {
+        if length & 1 == 1 {
+            result = multiply(result, square);
+        }
+        square = multiply(square, square);
+        length >>= 1;
+    }
+    result
+}
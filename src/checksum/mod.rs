@@ -0,0 +1,62 @@
+//! CRC-32 and Adler-32, the checksums of PNG chunks and zlib streams.
+
+mod adler32;
+mod crc32;
+
+pub use adler32::Adler32;
+pub use crc32::Crc32;
+
+use std::io::{self, Write};
+
+/// Checksum computed over data given in any number of pieces.
+pub trait Checksum {
+    fn new() -> Self;
+
+    fn update(&mut self, bytes: &[u8]);
+
+    fn value(&self) -> u32;
+
+    /// Checksum of two pieces joined together, from the checksum of each
+    /// and the length of the second one.
+    fn combine(first: u32, second: u32, second_length: u64) -> u32;
+
+    fn checksum(bytes: &[u8]) -> u32
+    where
+        Self: Sized,
+    {
+        let mut checksum = Self::new();
+        checksum.update(bytes);
+        checksum.value()
+    }
+}
+
+/// Calculate the checksum of every byte on its way to the inner writer.
+pub struct ChecksumWriter<'a, W: Write, C: Checksum> {
+    writer: &'a mut W,
+    checksum: C,
+}
+
+impl<'a, W: Write, C: Checksum> ChecksumWriter<'a, W, C> {
+    pub fn new(writer: &'a mut W) -> Self {
+        Self {
+            writer,
+            checksum: C::new(),
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        self.checksum.value()
+    }
+}
+
+impl<'a, W: Write, C: Checksum> Write for ChecksumWriter<'a, W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.checksum.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,33 @@
+use super::{impl_channels, Primitive, Rgb};
+
+/// CMYK pixel, each channel is an amount of ink from none to the maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Cmyk<T: Primitive>(pub [T; 4]);
+
+impl_channels!(Cmyk, 4);
+
+impl<T: Primitive> Cmyk<T> {
+    /// Separate a color into inks. `under_color_removal` from 0 to 1 is the
+    /// share of the gray the three colored inks have in common that black
+    /// prints instead, 1 uses as little colored ink as possible.
+    pub fn from_rgb(rgb: Rgb<T>, under_color_removal: f64) -> Self {
+        let max = T::MAX.to_f64();
+        let [c, m, y] = rgb.0.map(|value| 1. - value.to_f64() / max);
+        let k = c.min(m).min(y) * under_color_removal.clamp(0., 1.);
+        if k >= 1. {
+            return Cmyk([T::default(), T::default(), T::default(), T::MAX]);
+        }
+
+        // Colored inks only cover what black lets through
+        let ink = |value: f64| T::from_f64((value - k) / (1. - k) * max);
+        Cmyk([ink(c), ink(m), ink(y), T::from_f64(k * max)])
+    }
+
+    /// Color of the inks printed on white, the inverse of `from_rgb`.
+    pub fn to_rgb(&self) -> Rgb<T> {
+        let max = T::MAX.to_f64();
+        let [c, m, y, k] = self.0.map(|value| value.to_f64() / max);
+        let light = |value: f64| T::from_f64((1. - value) * (1. - k) * max);
+        Rgb([light(c), light(m), light(y)])
+    }
+}
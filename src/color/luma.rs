@@ -0,0 +1,86 @@
+use super::{impl_channels, ColorType, Pixel, Primitive, Rgb, Rgba};
+
+/// Grayscale pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Luma<T: Primitive>(pub [T; 1]);
+
+/// Grayscale pixel with alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct LumaA<T: Primitive>(pub [T; 2]);
+
+impl_channels!(Luma, 1);
+impl_channels!(LumaA, 2);
+
+impl<T: Primitive> Pixel for Luma<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: usize = 1;
+    const COLOR_TYPE: ColorType = ColorType::Luma;
+
+    fn channels(&self) -> &[T] {
+        &self.0
+    }
+
+    fn channels_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+
+    fn from_slice(slice: &[T]) -> Self {
+        Self([slice[0]])
+    }
+
+    fn to_luma(&self) -> Luma<T> {
+        *self
+    }
+
+    fn to_luma_alpha(&self) -> LumaA<T> {
+        LumaA([self.0[0], T::MAX])
+    }
+
+    fn to_rgb(&self) -> Rgb<T> {
+        let [l] = self.0;
+        Rgb([l, l, l])
+    }
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [l] = self.0;
+        Rgba([l, l, l, T::MAX])
+    }
+}
+
+impl<T: Primitive> Pixel for LumaA<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: usize = 2;
+    const COLOR_TYPE: ColorType = ColorType::LumaA;
+
+    fn channels(&self) -> &[T] {
+        &self.0
+    }
+
+    fn channels_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+
+    fn from_slice(slice: &[T]) -> Self {
+        Self([slice[0], slice[1]])
+    }
+
+    fn to_luma(&self) -> Luma<T> {
+        Luma([self.0[0]])
+    }
+
+    fn to_luma_alpha(&self) -> LumaA<T> {
+        *self
+    }
+
+    fn to_rgb(&self) -> Rgb<T> {
+        let [l, _] = self.0;
+        Rgb([l, l, l])
+    }
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [l, a] = self.0;
+        Rgba([l, l, l, a])
+    }
+}
@@ -1,3 +1,128 @@
 mod rgb;
+mod luma;
 mod ycbcr;
-mod cmyk;
\ No newline at end of file
+mod cmyk;
+
+pub use rgb::{Rgb, Rgba};
+pub use luma::{Luma, LumaA};
+pub use cmyk::Cmyk;
+
+use std::fmt::Debug;
+
+/// Channel layout of a pixel type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Luma,
+    LumaA,
+    Rgb,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn channel_count(&self) -> usize {
+        match self {
+            ColorType::Luma => 1,
+            ColorType::LumaA => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::LumaA | ColorType::Rgba)
+    }
+}
+
+/// Value type of a single channel.
+pub trait Primitive: Copy + Default + PartialEq + Debug + 'static {
+    /// Bit depth of the channel
+    const BITS: u8;
+    /// Fully saturated or opaque value
+    const MAX: Self;
+
+    fn to_f64(self) -> f64;
+
+    // Round to nearest and clamp into the valid range
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Primitive for u8 {
+    const BITS: u8 = 8;
+    const MAX: Self = u8::MAX;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round().clamp(0., Self::MAX as f64) as Self
+    }
+}
+
+impl Primitive for u16 {
+    const BITS: u8 = 16;
+    const MAX: Self = u16::MAX;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round().clamp(0., Self::MAX as f64) as Self
+    }
+}
+
+/// A pixel made of `CHANNEL_COUNT` channels of `Subpixel`.
+pub trait Pixel: Copy + Default + PartialEq + Debug {
+    type Subpixel: Primitive;
+
+    const CHANNEL_COUNT: usize;
+    const COLOR_TYPE: ColorType;
+
+    fn channels(&self) -> &[Self::Subpixel];
+
+    fn channels_mut(&mut self) -> &mut [Self::Subpixel];
+
+    /// Panic if the length of `slice` is not `CHANNEL_COUNT`.
+    fn from_slice(slice: &[Self::Subpixel]) -> Self;
+
+    fn to_luma(&self) -> Luma<Self::Subpixel>;
+
+    fn to_luma_alpha(&self) -> LumaA<Self::Subpixel>;
+
+    fn to_rgb(&self) -> Rgb<Self::Subpixel>;
+
+    fn to_rgba(&self) -> Rgba<Self::Subpixel>;
+}
+
+// ITU-R BT.601 luma, the same weights JFIF uses
+fn rgb_to_luma<T: Primitive>(r: T, g: T, b: T) -> T {
+    T::from_f64(0.2990 * r.to_f64() + 0.5870 * g.to_f64() + 0.1140 * b.to_f64())
+}
+
+// Conversions and indexing shared by all the tuple-like pixel types
+macro_rules! impl_channels {
+    ($name:ident, $count:expr) => {
+        impl<T: Primitive> From<[T; $count]> for $name<T> {
+            fn from(channels: [T; $count]) -> Self {
+                Self(channels)
+            }
+        }
+
+        impl<T: Primitive> std::ops::Index<usize> for $name<T> {
+            type Output = T;
+
+            fn index(&self, index: usize) -> &T {
+                &self.0[index]
+            }
+        }
+
+        impl<T: Primitive> std::ops::IndexMut<usize> for $name<T> {
+            fn index_mut(&mut self, index: usize) -> &mut T {
+                &mut self.0[index]
+            }
+        }
+    };
+}
+
+pub(crate) use impl_channels;
@@ -0,0 +1,88 @@
+use super::{impl_channels, rgb_to_luma, ColorType, Luma, LumaA, Pixel, Primitive};
+
+/// RGB pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Rgb<T: Primitive>(pub [T; 3]);
+
+/// RGB pixel with alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Rgba<T: Primitive>(pub [T; 4]);
+
+impl_channels!(Rgb, 3);
+impl_channels!(Rgba, 4);
+
+impl<T: Primitive> Pixel for Rgb<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: usize = 3;
+    const COLOR_TYPE: ColorType = ColorType::Rgb;
+
+    fn channels(&self) -> &[T] {
+        &self.0
+    }
+
+    fn channels_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+
+    fn from_slice(slice: &[T]) -> Self {
+        Self([slice[0], slice[1], slice[2]])
+    }
+
+    fn to_luma(&self) -> Luma<T> {
+        let [r, g, b] = self.0;
+        Luma([rgb_to_luma(r, g, b)])
+    }
+
+    fn to_luma_alpha(&self) -> LumaA<T> {
+        let [r, g, b] = self.0;
+        LumaA([rgb_to_luma(r, g, b), T::MAX])
+    }
+
+    fn to_rgb(&self) -> Rgb<T> {
+        *self
+    }
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [r, g, b] = self.0;
+        Rgba([r, g, b, T::MAX])
+    }
+}
+
+impl<T: Primitive> Pixel for Rgba<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: usize = 4;
+    const COLOR_TYPE: ColorType = ColorType::Rgba;
+
+    fn channels(&self) -> &[T] {
+        &self.0
+    }
+
+    fn channels_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+
+    fn from_slice(slice: &[T]) -> Self {
+        Self([slice[0], slice[1], slice[2], slice[3]])
+    }
+
+    fn to_luma(&self) -> Luma<T> {
+        let [r, g, b, _] = self.0;
+        Luma([rgb_to_luma(r, g, b)])
+    }
+
+    fn to_luma_alpha(&self) -> LumaA<T> {
+        let [r, g, b, a] = self.0;
+        LumaA([rgb_to_luma(r, g, b), a])
+    }
+
+    fn to_rgb(&self) -> Rgb<T> {
+        let [r, g, b, _] = self.0;
+        Rgb([r, g, b])
+    }
+
+    fn to_rgba(&self) -> Rgba<T> {
+        *self
+    }
+}
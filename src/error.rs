@@ -0,0 +1,46 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Error returned by every encoder and decoder in this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// Width or height is zero, or does not match the size of the data
+    InvalidDimensions { width: u32, height: u32 },
+    /// The format can not store the given mode or pixel layout
+    Unsupported(String),
+    /// A value exceeds what a field of the format can hold, e.g. a 16-bit size
+    FormatLimit(String),
+    /// The input is not a well-formed file of the expected format
+    Decoding(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidDimensions { width, height } => {
+                write!(f, "invalid image dimensions {}x{}", width, height)
+            }
+            Error::Unsupported(message) => write!(f, "unsupported: {}", message),
+            Error::FormatLimit(message) => write!(f, "format limit exceeded: {}", message),
+            Error::Decoding(message) => write!(f, "decoding error: {}", message),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -1,8 +1,17 @@
-use crate::img::{Serializable, Image};
+// Headers are named as in the BMP specification, e.g. `DIB`
+#![allow(clippy::upper_case_acronyms)]
+
+use crate::img::{Serializable, Image, ImageBuffer};
+use crate::color::{ColorType, Pixel};
+use crate::img::{check_data_size, check_dimensions};
+use crate::Error;
+
+use std::io::Write;
 
 pub struct BMP {
     header: Header,
     dib: DIB,
+    palette: Palette,
     data: Data,
 }
 
@@ -26,136 +35,193 @@ struct DIB {
     importance_color: u32,
 }
 
+// Only grayscale image has palette
+struct Palette {
+    color_num: u32,
+}
+
 struct Data {
     width: u32,
     height: u32,
+    depth: u16,
     data: Vec<u8>,
 }
 
 impl BMP {
-    pub fn new(width: u32, height: u32, data: &Vec<u8>) -> Self {
-        Self {
-            header: Header::new(width, height),
-            dib: DIB::new(width, height),
-            data: Data::new(width, height, data),
+    // Only Luma, RGB and RGBA are supported
+    pub fn new(width: u32, height: u32, color_type: ColorType, data: &[u8]) -> Result<Self, Error> {
+        check_dimensions(width, height)?;
+        let depth = match color_type {
+            ColorType::Luma => 8,
+            ColorType::Rgb => 24,
+            ColorType::LumaA | ColorType::Rgba => 32,
+        };
+        check_data_size(width, height, depth as usize / 8, data)?;
+        // Width and height are signed 32 bits, file size is unsigned 32 bits
+        let file_size = 54 + 1024 + height as u64 * get_row_size(width, depth);
+        if width > i32::MAX as u32 || height > i32::MAX as u32 || file_size > u32::MAX as u64 {
+            return Err(Error::FormatLimit(format!(
+                "BMP of {}x{} pixels is larger than 4 GiB",
+                width, height
+            )));
         }
+        let palette = Palette {
+            color_num: if depth == 8 { 256 } else { 0 },
+        };
+
+        Ok(Self {
+            header: Header::new(width, height, depth, &palette),
+            dib: DIB::new(width, height, depth, &palette),
+            palette,
+            data: Data::new(width, height, depth, data),
+        })
     }
 }
 
-impl Serializable for BMP {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        bytes.extend(self.header.get_bytes());
-        bytes.extend(self.dib.get_bytes());
-        bytes.extend(self.data.get_bytes());
+impl BMP {
+    // Depth is decided by the pixel type:
+    // 8 bits with gray palette, 24 bits for RGB and 32 bits for pixel with alpha
+    pub fn from_buffer<P: Pixel<Subpixel = u8>>(image: &ImageBuffer<P>) -> Result<Self, Error> {
+        let (width, height) = image.dimensions();
+        let data = match P::COLOR_TYPE {
+            // BMP does not have gray with alpha
+            ColorType::LumaA => image.map(Pixel::to_rgba).to_channels(),
+            _ => image.to_channels(),
+        };
+        Self::new(width, height, P::COLOR_TYPE, &data)
+    }
+}
 
-        bytes
+impl Serializable for BMP {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.header.serialize(writer)?;
+        self.dib.serialize(writer)?;
+        self.palette.serialize(writer)?;
+        self.data.serialize(writer)
     }
 }
 
 impl Header {
-    fn new(width: u32, height: u32) -> Self {
+    fn new(width: u32, height: u32, depth: u16, palette: &Palette) -> Self {
+        let data_offset = 54 + palette.get_size();
         Self {
             magic_number: [66, 77],
-            file_size: 54 + get_data_size(width, height),
-            data_offset: 54,
+            file_size: data_offset + get_data_size(width, height, depth),
+            data_offset,
         }
     }
 }
 
 impl Serializable for Header {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        bytes.extend(self.magic_number);
-        bytes.extend(self.file_size.to_le_bytes());
-        bytes.extend([0; 4]);
-        bytes.extend(self.data_offset.to_le_bytes());
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.magic_number)?;
+        writer.write_all(&self.file_size.to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        writer.write_all(&self.data_offset.to_le_bytes())?;
 
-        bytes
+        Ok(())
     }
 }
 
 impl DIB {
-    fn new(width: u32, height: u32) -> Self {
+    fn new(width: u32, height: u32, depth: u16, palette: &Palette) -> Self {
         Self {
             size: 40,
             width, height,
             plane_num: 1,
-            depth: 24,
+            depth,
             compression: 0,
-            data_size: get_data_size(width, height),
+            data_size: get_data_size(width, height, depth),
             horizental_resolution: 1000,
             vertical_resolution: 1000,
-            palette_color_num: 0,
+            palette_color_num: palette.color_num,
             importance_color: 0,
         }
     }
 }
 
 impl Serializable for DIB {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        bytes.extend(self.size.to_le_bytes());
-        bytes.extend(self.width.to_le_bytes());
-        bytes.extend(self.height.to_le_bytes());
-        bytes.extend(self.plane_num.to_le_bytes());
-        bytes.extend(self.depth.to_le_bytes());
-        bytes.extend(self.compression.to_le_bytes());
-        bytes.extend(self.data_size.to_le_bytes());
-        bytes.extend(self.horizental_resolution.to_le_bytes());
-        bytes.extend(self.vertical_resolution.to_le_bytes());
-        bytes.extend(self.palette_color_num.to_le_bytes());
-        bytes.extend(self.importance_color.to_le_bytes());
-
-        bytes
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        writer.write_all(&self.plane_num.to_le_bytes())?;
+        writer.write_all(&self.depth.to_le_bytes())?;
+        writer.write_all(&self.compression.to_le_bytes())?;
+        writer.write_all(&self.data_size.to_le_bytes())?;
+        writer.write_all(&self.horizental_resolution.to_le_bytes())?;
+        writer.write_all(&self.vertical_resolution.to_le_bytes())?;
+        writer.write_all(&self.palette_color_num.to_le_bytes())?;
+        writer.write_all(&self.importance_color.to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl Palette {
+    fn get_size(&self) -> u32 {
+        // Each color have 4 bytes
+        self.color_num * 4
+    }
+}
+
+impl Serializable for Palette {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // Gray ramp in BGR0 order
+        for index in 0..self.color_num {
+            let gray = index as u8;
+            writer.write_all(&[gray, gray, gray, 0])?;
+        }
+
+        Ok(())
     }
 }
 
 impl Data {
-    fn new(width: u32, height: u32, data: &Vec<u8>) -> Self {
+    fn new(width: u32, height: u32, depth: u16, data: &[u8]) -> Self {
         Self {
             width,
             height,
+            depth,
             data: data.to_vec(),
         }
     }
 }
 
 impl Serializable for Data {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let channel = (self.depth / 8) as usize;
+        // Create a row vector with specified length due to the padding
+        let row_size = get_row_size(self.width, self.depth) as usize;
+        let mut row = vec![0u8; row_size];
 
         for y_index in 0..self.height {
-            // Create a row vector with specified length due to the padding
-            let row_size= (self.width as f64 * 24. / 32.).ceil() as usize * 4;
-            let mut row = Vec::with_capacity(row_size);
-            row.resize(row_size, 0u8);
-
-            for x_index in 0..self.width {
-                let offset = (self.height - 1 - y_index) * self.width + x_index;
-                // BGR order
-                row[x_index as usize * 3 + 0] = self.data[(offset * 3 + 2) as usize];
-                row[x_index as usize * 3 + 1] = self.data[(offset * 3 + 1) as usize];
-                row[x_index as usize * 3 + 2] = self.data[(offset * 3 + 0) as usize];
+            for x_index in 0..self.width as usize {
+                let offset = ((self.height - 1 - y_index) * self.width) as usize + x_index;
+                let pixel = &self.data[offset * channel..(offset + 1) * channel];
+                let row_pixel = &mut row[x_index * channel..(x_index + 1) * channel];
+                row_pixel.copy_from_slice(pixel);
+                // BGR(A) order
+                if channel >= 3 {
+                    row_pixel.swap(0, 2);
+                }
             }
 
-            bytes.extend(row);
+            writer.write_all(&row)?;
         }
 
-        bytes
+        Ok(())
     }
 }
 
-fn get_data_size(width: u32, height: u32) -> u32 {
-    // Each pixel have 24 bits
-    // And a row will padding to multiple of 4
-    let row_size = (width as f64 * 24. / 32.).ceil() as u32 * 4;
-    let total_size = height * row_size;
+fn get_row_size(width: u32, depth: u16) -> u64 {
+    // A row will padding to multiple of 4
+    (width as u64 * depth as u64).div_ceil(32) * 4
+}
 
-    total_size
+// Checked by `BMP::new` that the size fits in 32 bits
+fn get_data_size(width: u32, height: u32, depth: u16) -> u32 {
+    (height as u64 * get_row_size(width, depth)) as u32
 }
 
 impl Image for BMP {}
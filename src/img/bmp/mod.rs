@@ -1,14 +1,23 @@
+#[allow(clippy::module_inception)]
 mod bmp;
 
-use bmp::BMP;
+use crate::color::{Pixel, Rgb};
+use crate::{Error, Image, ImageBuffer};
 
-use crate::Image;
-use std::error::Error;
+pub use bmp::BMP;
 
 pub fn save_bmp<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[[u8; 3]; WIDTH]; HEIGHT]
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    BMP::new(WIDTH as u32, HEIGHT as u32, &data).dump(path)
+) -> Result<(), Error> {
+    save_bmp_buffer(path, &ImageBuffer::from(data).map(|&rgb| Rgb(rgb)))
+}
+
+// Depth is decided by the pixel type:
+// 8 bits with gray palette, 24 bits for RGB and 32 bits for pixel with alpha
+pub fn save_bmp_buffer<P: Pixel<Subpixel = u8>>(
+    path: &str,
+    image: &ImageBuffer<P>,
+) -> Result<(), Error> {
+    BMP::from_buffer(image)?.dump(path)
 }
@@ -0,0 +1,194 @@
+use crate::color::{ColorType, Pixel};
+
+use std::ops::{Index, IndexMut};
+
+/// Image stored on the heap, whose size is only known at runtime.
+///
+/// Pixels are kept row by row from the top-left corner. `P` is the pixel
+/// type, e.g. `Luma<u8>` for grayscale or `Rgb<u8>` for RGB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageBuffer<P> {
+    width: u32,
+    height: u32,
+    data: Vec<P>,
+}
+
+impl<P: Copy> ImageBuffer<P> {
+    /// Create an image filled with the default pixel (usually black).
+    pub fn new(width: u32, height: u32) -> Self
+    where
+        P: Default,
+    {
+        Self::from_pixel(width, height, P::default())
+    }
+
+    /// Create an image filled with the given pixel.
+    pub fn from_pixel(width: u32, height: u32, pixel: P) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![pixel; width as usize * height as usize],
+        }
+    }
+
+    /// Create an image by calling `f(x, y)` for every pixel.
+    pub fn from_fn<F>(width: u32, height: u32, mut f: F) -> Self
+    where
+        F: FnMut(u32, u32) -> P,
+    {
+        let mut data = Vec::with_capacity(width as usize * height as usize);
+        // Cache friendly loop
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Wrap a row-major pixel vector.
+    /// Return `None` if its length does not match `width * height`.
+    pub fn from_raw(width: u32, height: u32, data: Vec<P>) -> Option<Self> {
+        if data.len() != width as usize * height as usize {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Return `(width, height)`.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Panic if `(x, y)` is out of bounds.
+    pub fn get_pixel(&self, x: u32, y: u32) -> P {
+        self[(x, y)]
+    }
+
+    /// Panic if `(x, y)` is out of bounds.
+    pub fn get_pixel_mut(&mut self, x: u32, y: u32) -> &mut P {
+        &mut self[(x, y)]
+    }
+
+    /// Panic if `(x, y)` is out of bounds.
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        self[(x, y)] = pixel;
+    }
+
+    /// Pixels in row-major order.
+    pub fn pixels(&self) -> std::slice::Iter<'_, P> {
+        self.data.iter()
+    }
+
+    /// Rows from top to bottom, each of them `width` pixels long.
+    pub fn rows(&self) -> std::slice::Chunks<'_, P> {
+        // Chunk size can not be 0, an empty image has no row anyway
+        self.data.chunks(std::cmp::max(self.width as usize, 1))
+    }
+
+    pub fn as_raw(&self) -> &[P] {
+        &self.data
+    }
+
+    pub fn into_raw(self) -> Vec<P> {
+        self.data
+    }
+
+    /// Create a new image by converting every pixel with `f`.
+    pub fn map<Q, F>(&self, f: F) -> ImageBuffer<Q>
+    where
+        Q: Copy,
+        F: FnMut(&P) -> Q,
+    {
+        ImageBuffer {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(f).collect(),
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is out of bounds ({}, {})",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+impl<P: Pixel> ImageBuffer<P> {
+    /// Wrap interleaved channel values, e.g. `[r, g, b, r, g, b, ...]`.
+    /// Return `None` if its length does not match the size.
+    pub fn from_channels(width: u32, height: u32, channels: Vec<P::Subpixel>) -> Option<Self> {
+        if channels.len() != width as usize * height as usize * P::CHANNEL_COUNT {
+            return None;
+        }
+        let data = channels.chunks(P::CHANNEL_COUNT).map(P::from_slice).collect();
+
+        Self::from_raw(width, height, data)
+    }
+
+    /// Interleaved channel values, e.g. `[r, g, b, r, g, b, ...]`.
+    pub fn to_channels(&self) -> Vec<P::Subpixel> {
+        let mut channels = Vec::with_capacity(self.data.len() * P::CHANNEL_COUNT);
+        for pixel in self.data.iter() {
+            channels.extend_from_slice(pixel.channels());
+        }
+
+        channels
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        P::COLOR_TYPE
+    }
+}
+
+impl<P: Copy> Index<(u32, u32)> for ImageBuffer<P> {
+    type Output = P;
+
+    fn index(&self, (x, y): (u32, u32)) -> &P {
+        &self.data[self.offset(x, y)]
+    }
+}
+
+impl<P: Copy> IndexMut<(u32, u32)> for ImageBuffer<P> {
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut P {
+        let offset = self.offset(x, y);
+        &mut self.data[offset]
+    }
+}
+
+// Keep the stack array API working
+impl<P: Copy, const WIDTH: usize, const HEIGHT: usize> From<[[P; WIDTH]; HEIGHT]>
+    for ImageBuffer<P>
+{
+    fn from(data: [[P; WIDTH]; HEIGHT]) -> Self {
+        Self {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            data: data.iter().flatten().copied().collect(),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::ImageBuffer;
+use crate::color::{ColorType, Luma, LumaA, Pixel, Rgb, Rgba};
+
+/// Image returned by decoders, whose pixel type is only known at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicImage {
+    Luma8(ImageBuffer<Luma<u8>>),
+    LumaA8(ImageBuffer<LumaA<u8>>),
+    Rgb8(ImageBuffer<Rgb<u8>>),
+    Rgba8(ImageBuffer<Rgba<u8>>),
+    Luma16(ImageBuffer<Luma<u16>>),
+    LumaA16(ImageBuffer<LumaA<u16>>),
+    Rgb16(ImageBuffer<Rgb<u16>>),
+    Rgba16(ImageBuffer<Rgba<u16>>),
+}
+
+// Apply the same expression to whichever buffer is inside
+macro_rules! dynamic_map {
+    ($dynamic:expr, $image:ident => $body:expr) => {
+        match $dynamic {
+            DynamicImage::Luma8($image) => $body,
+            DynamicImage::LumaA8($image) => $body,
+            DynamicImage::Rgb8($image) => $body,
+            DynamicImage::Rgba8($image) => $body,
+            DynamicImage::Luma16($image) => $body,
+            DynamicImage::LumaA16($image) => $body,
+            DynamicImage::Rgb16($image) => $body,
+            DynamicImage::Rgba16($image) => $body,
+        }
+    };
+}
+
+// Convert to an 8-bit buffer, keeping the high byte of 16-bit channels
+macro_rules! to_8bit {
+    ($dynamic:expr, $convert:ident) => {
+        match $dynamic {
+            DynamicImage::Luma8(image) => image.map(|pixel| pixel.$convert()),
+            DynamicImage::LumaA8(image) => image.map(|pixel| pixel.$convert()),
+            DynamicImage::Rgb8(image) => image.map(|pixel| pixel.$convert()),
+            DynamicImage::Rgba8(image) => image.map(|pixel| pixel.$convert()),
+            DynamicImage::Luma16(image) => image.map(|pixel| narrow(&pixel.$convert())),
+            DynamicImage::LumaA16(image) => image.map(|pixel| narrow(&pixel.$convert())),
+            DynamicImage::Rgb16(image) => image.map(|pixel| narrow(&pixel.$convert())),
+            DynamicImage::Rgba16(image) => image.map(|pixel| narrow(&pixel.$convert())),
+        }
+    };
+}
+
+impl DynamicImage {
+    pub fn width(&self) -> u32 {
+        dynamic_map!(self, image => image.width())
+    }
+
+    pub fn height(&self) -> u32 {
+        dynamic_map!(self, image => image.height())
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        dynamic_map!(self, image => image.dimensions())
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        dynamic_map!(self, image => image.color_type())
+    }
+
+    /// Bits per channel, either 8 or 16.
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            DynamicImage::Luma8(_)
+            | DynamicImage::LumaA8(_)
+            | DynamicImage::Rgb8(_)
+            | DynamicImage::Rgba8(_) => 8,
+            _ => 16,
+        }
+    }
+
+    pub fn to_luma8(&self) -> ImageBuffer<Luma<u8>> {
+        to_8bit!(self, to_luma)
+    }
+
+    pub fn to_luma_alpha8(&self) -> ImageBuffer<LumaA<u8>> {
+        to_8bit!(self, to_luma_alpha)
+    }
+
+    pub fn to_rgb8(&self) -> ImageBuffer<Rgb<u8>> {
+        to_8bit!(self, to_rgb)
+    }
+
+    pub fn to_rgba8(&self) -> ImageBuffer<Rgba<u8>> {
+        to_8bit!(self, to_rgba)
+    }
+}
+
+fn narrow<P, Q>(pixel: &P) -> Q
+where
+    P: Pixel<Subpixel = u16>,
+    Q: Pixel<Subpixel = u8>,
+{
+    let mut channels = [0_u8; 4];
+    for (narrowed, channel) in channels.iter_mut().zip(pixel.channels()) {
+        *narrowed = (*channel >> 8) as u8;
+    }
+    Q::from_slice(&channels[..Q::CHANNEL_COUNT])
+}
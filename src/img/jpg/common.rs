@@ -6,7 +6,9 @@ pub struct Bits {
     // Since some bits' length may less than 8
     // So the length field is introduced
     length: u8,
-    bits: u32,
+    // Left-aligned, wide enough for a 16-bit code and its value after
+    // 7 pending bits
+    bits: u64,
 }
 
 impl Bits {
@@ -20,10 +22,15 @@ impl Bits {
     pub fn dump(&mut self) -> Vec<u8> {
         let mut bytes = Vec::new();
         while self.length >= 8 {
-            bytes.push((self.bits >> 24) as u8);
+            let byte = (self.bits >> 56) as u8;
+            bytes.push(byte);
+            // Stuff a zero so the decoder does not take coded data for a marker
+            if byte == 0xff {
+                bytes.push(0x00);
+            }
 
             self.length -= 8;
-            self.bits = ((self.bits as u64) << 8) as u32;
+            self.bits <<= 8;
         }
 
         bytes
@@ -31,8 +38,8 @@ impl Bits {
 
     // Must call at the end
     pub fn complete(&self) -> (u8, bool) {
-        let is_complete = if self.length == 0 { true } else { false };
-        let mut last_byte = (self.bits >> 24) as u8;
+        let is_complete = self.length == 0;
+        let mut last_byte = (self.bits >> 56) as u8;
         last_byte |= bit::get_lowest_n_bits(8 - self.length, 0b1111111);
         (last_byte, is_complete)
     }
@@ -52,6 +59,8 @@ impl Add for Bits {
 
 impl AddAssign for Bits {
     fn add_assign(&mut self, rhs: Bits) {
+        // Callers must dump in between, or the later bits fall off the end
+        debug_assert!(self.length + rhs.length <= 64, "{} pending bits", self.length);
         *self = Self {
             length: self.length + rhs.length,
             bits: self.bits | (rhs.bits >> self.length),
@@ -73,16 +82,16 @@ impl fmt::Display for Bits {
 
 // Utility bit operations
 pub mod bit {
-    pub fn to_highest_pos(length: u8, bits: u32) -> u32 {
-        ((bits as u64) << (32 - length) as u64) as u32
+    pub fn to_highest_pos(length: u8, bits: u32) -> u64 {
+        ((bits as u128) << (64 - length)) as u64
     }
 
     pub fn get_lowest_n_bits(length: u8, bits: u8) -> u8 {
         bits & (2_u16.pow(length as u32) - 1) as u8
     }
 
-    pub fn get_heighest_n_bits(length: u8, bits: u32) -> u8 {
-        (bits >> (32 - length) as u32) as u8
+    pub fn get_heighest_n_bits(length: u8, bits: u64) -> u64 {
+        ((bits as u128) >> (64 - length)) as u64
     }
 
     pub fn get_bit_conut(num: i32) -> u32 {
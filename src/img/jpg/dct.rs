@@ -1,37 +1,297 @@
-/// Keep simple for readablity
+use super::jpeg::ZIG_ZAG_ORDER;
+use super::options::DctMethod;
+use super::quant::QuantTable;
 
-pub fn get_dct(g: [i32; 64]) -> [f64; 64] {
-    let mut result = [0.; 64];
-    // Phase 1
-    let mut temp = g;
-    for v in 0..8 {
-        for u in 0..8 {
-            temp[v * 8 + u] -= 128;
+use lazy_static::lazy_static;
+
+// Scale factors of the AAN outputs: cos(k * PI / 16) * sqrt(2), 1 for k = 0
+const AAN_SCALE: [f64; 8] = [
+    1.0,
+    1.387039845,
+    1.306562965,
+    1.175875602,
+    1.0,
+    0.785694958,
+    0.541196100,
+    0.275899379,
+];
+
+// Fixed-point precision of the integer multipliers, and extra bits kept
+// between the row and column pass
+const CONST_BITS: u32 = 14;
+const PASS1_BITS: u32 = 8;
+// Precision of the integer reciprocals of the divisors
+const RECIPROCAL_BITS: u32 = 24;
+
+// Fast forward DCT with a quantization table folded into its output scaling,
+// so a block costs 80 multiplications and 464 additions, then quantizing is
+// one multiplication by a reciprocal per coefficient
+pub enum ForwardDct {
+    Float([f32; 64]),
+    Integer([i32; 64]),
+}
+
+impl ForwardDct {
+    // `quant_table` is in zig-zag order, the reciprocals are in natural order
+    pub fn new(method: DctMethod, quant_table: &QuantTable) -> Self {
+        // AAN leaves every output multiplied by 8 and the scale of its row and column
+        let divisor = |index: usize| {
+            let natural = ZIG_ZAG_ORDER[index];
+            quant_table[index / 8][index % 8] as f64
+                * AAN_SCALE[natural / 8]
+                * AAN_SCALE[natural % 8]
+                * 8.
+        };
+        match method {
+            DctMethod::Float => {
+                let mut reciprocals = [0.; 64];
+                for index in 0..64 {
+                    reciprocals[ZIG_ZAG_ORDER[index]] = (1. / divisor(index)) as f32;
+                }
+                ForwardDct::Float(reciprocals)
+            }
+            DctMethod::Integer => {
+                let mut reciprocals = [0; 64];
+                for index in 0..64 {
+                    let scaled = divisor(index) * (1 << PASS1_BITS) as f64;
+                    reciprocals[ZIG_ZAG_ORDER[index]] =
+                        ((1 << RECIPROCAL_BITS) as f64 / scaled).round() as i32;
+                }
+                ForwardDct::Integer(reciprocals)
+            }
         }
     }
 
-    // Phase 2
-    for v in 0..8 {
-        for u in 0..8 {
-            let a_u = if u == 0 { 1. / 2_f64.sqrt() } else { 1. };
-            let a_v = if v == 0 { 1. / 2_f64.sqrt() } else { 1. };
-            result[v * 8 + u] = 0.25 * a_u * a_v * multiply_add(&temp, u, v);
+    // Level shift, transform and quantize, coefficients stay in natural order
+    pub fn quantize(&self, g: [i32; 64]) -> [i32; 64] {
+        let mut result = [0; 64];
+        match self {
+            ForwardDct::Float(reciprocals) => {
+                let mut data = [0.; 64];
+                for index in 0..64 {
+                    data[index] = (g[index] - 128) as f32;
+                }
+                for row in 0..8 {
+                    aan_float(&mut data, row * 8, 1);
+                }
+                for column in 0..8 {
+                    aan_float(&mut data, column, 8);
+                }
+                for index in 0..64 {
+                    result[index] = (data[index] * reciprocals[index]).round() as i32;
+                }
+            }
+            ForwardDct::Integer(reciprocals) => {
+                let mut data = [0; 64];
+                for index in 0..64 {
+                    data[index] = (g[index] - 128) << PASS1_BITS;
+                }
+                for row in 0..8 {
+                    aan_integer(&mut data, row * 8, 1);
+                }
+                for column in 0..8 {
+                    aan_integer(&mut data, column, 8);
+                }
+                for index in 0..64 {
+                    // Round half away from zero like the float variant
+                    let magnitude = (data[index].abs() as i64 * reciprocals[index] as i64
+                        + (1 << (RECIPROCAL_BITS - 1)))
+                        >> RECIPROCAL_BITS;
+                    result[index] = magnitude as i32 * data[index].signum();
+                }
+            }
         }
+
+        result
     }
+}
 
-    result
+// One dimension of the Arai, Agui and Nakajima DCT, as in libjpeg's jfdctflt.c
+fn aan_float(data: &mut [f32; 64], offset: usize, stride: usize) {
+    const F_0_382683433: f32 = 0.382_683_43;
+    const F_0_541196100: f32 = 0.541_196_1;
+    const F_0_707106781: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    const F_1_306562965: f32 = 1.306_563;
+
+    let d = |k: usize| data[offset + k * stride];
+    let (tmp0, tmp7) = (d(0) + d(7), d(0) - d(7));
+    let (tmp1, tmp6) = (d(1) + d(6), d(1) - d(6));
+    let (tmp2, tmp5) = (d(2) + d(5), d(2) - d(5));
+    let (tmp3, tmp4) = (d(3) + d(4), d(3) - d(4));
+
+    // Even part
+    let (tmp10, tmp13) = (tmp0 + tmp3, tmp0 - tmp3);
+    let (tmp11, tmp12) = (tmp1 + tmp2, tmp1 - tmp2);
+    let z1 = (tmp12 + tmp13) * F_0_707106781;
+    data[offset] = tmp10 + tmp11;
+    data[offset + 4 * stride] = tmp10 - tmp11;
+    data[offset + 2 * stride] = tmp13 + z1;
+    data[offset + 6 * stride] = tmp13 - z1;
+
+    // Odd part
+    let (tmp10, tmp11, tmp12) = (tmp4 + tmp5, tmp5 + tmp6, tmp6 + tmp7);
+    let z5 = (tmp10 - tmp12) * F_0_382683433;
+    let z2 = tmp10 * F_0_541196100 + z5;
+    let z4 = tmp12 * F_1_306562965 + z5;
+    let z3 = tmp11 * F_0_707106781;
+    let (z11, z13) = (tmp7 + z3, tmp7 - z3);
+    data[offset + 5 * stride] = z13 + z2;
+    data[offset + 3 * stride] = z13 - z2;
+    data[offset + stride] = z11 + z4;
+    data[offset + 7 * stride] = z11 - z4;
+}
+
+// The same flow graph in integers, multipliers are scaled by 2^CONST_BITS
+fn aan_integer(data: &mut [i32; 64], offset: usize, stride: usize) {
+    const FIX_0_382683433: i64 = 6270;
+    const FIX_0_541196100: i64 = 8867;
+    const FIX_0_707106781: i64 = 11585;
+    const FIX_1_306562965: i64 = 21407;
+    // Column sums reach 2^22, so the products need more than 32 bits
+    let multiply = |value: i32, constant: i64| {
+        ((value as i64 * constant + (1 << (CONST_BITS - 1))) >> CONST_BITS) as i32
+    };
+
+    let d = |k: usize| data[offset + k * stride];
+    let (tmp0, tmp7) = (d(0) + d(7), d(0) - d(7));
+    let (tmp1, tmp6) = (d(1) + d(6), d(1) - d(6));
+    let (tmp2, tmp5) = (d(2) + d(5), d(2) - d(5));
+    let (tmp3, tmp4) = (d(3) + d(4), d(3) - d(4));
+
+    // Even part
+    let (tmp10, tmp13) = (tmp0 + tmp3, tmp0 - tmp3);
+    let (tmp11, tmp12) = (tmp1 + tmp2, tmp1 - tmp2);
+    let z1 = multiply(tmp12 + tmp13, FIX_0_707106781);
+    data[offset] = tmp10 + tmp11;
+    data[offset + 4 * stride] = tmp10 - tmp11;
+    data[offset + 2 * stride] = tmp13 + z1;
+    data[offset + 6 * stride] = tmp13 - z1;
+
+    // Odd part
+    let (tmp10, tmp11, tmp12) = (tmp4 + tmp5, tmp5 + tmp6, tmp6 + tmp7);
+    let z5 = multiply(tmp10 - tmp12, FIX_0_382683433);
+    let z2 = multiply(tmp10, FIX_0_541196100) + z5;
+    let z4 = multiply(tmp12, FIX_1_306562965) + z5;
+    let z3 = multiply(tmp11, FIX_0_707106781);
+    let (z11, z13) = (tmp7 + z3, tmp7 - z3);
+    data[offset + 5 * stride] = z13 + z2;
+    data[offset + 3 * stride] = z13 - z2;
+    data[offset + stride] = z11 + z4;
+    data[offset + 7 * stride] = z11 - z4;
+}
+
+lazy_static! {
+    // Basis functions of the inverse transform by sample and frequency, scaled by a(u)
+    static ref IDCT_COS: [[f64; 8]; 8] = {
+        let mut cos = [[0.; 8]; 8];
+        for (x, row) in cos.iter_mut().enumerate() {
+            for (u, entry) in row.iter_mut().enumerate() {
+                let a_u = if u == 0 { 1. / 2_f64.sqrt() } else { 1. };
+                *entry =
+                    a_u * f64::cos((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.);
+            }
+        }
+        cos
+    };
 }
 
-fn multiply_add(g: &[i32; 64], u: usize, v: usize) -> f64 {
-    let mut result = 0.;
+// Inverse transform, one dimension at a time, back to clamped samples
+pub fn get_idct(f: [f64; 64]) -> [u8; 64] {
+    let cos = &*IDCT_COS;
 
+    // Rows first, then columns
+    let mut temp = [0.; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            temp[v * 8 + x] = (0..8).map(|u| cos[x][u] * f[v * 8 + u]).sum::<f64>() / 2.;
+        }
+    }
+    let mut result = [0; 64];
     for y in 0..8 {
         for x in 0..8 {
-            let cos2 = f64::cos((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.);
-            let cos1 = f64::cos((2 * y + 1) as f64 * v as f64 * std::f64::consts::PI / 16.);
-            result += g[y * 8 + x] as f64 * cos1 * cos2;
+            let sample = (0..8).map(|v| cos[y][v] * temp[v * 8 + x]).sum::<f64>() / 2.;
+            result[y * 8 + x] = (sample + 128.).round().clamp(0., 255.) as u8;
         }
     }
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::jpg::quant::{scale_quant_table, LUMINANCE_QUANT_TABLE};
+
+    // The textbook definition, O(N^4) with a cosine per term
+    fn reference_dct(g: [i32; 64]) -> [f64; 64] {
+        let mut result = [0.; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let a_u = if u == 0 { 1. / 2_f64.sqrt() } else { 1. };
+                let a_v = if v == 0 { 1. / 2_f64.sqrt() } else { 1. };
+                let mut sum = 0.;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let cos_x =
+                            f64::cos((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.);
+                        let cos_y =
+                            f64::cos((2 * y + 1) as f64 * v as f64 * std::f64::consts::PI / 16.);
+                        sum += (g[y * 8 + x] - 128) as f64 * cos_x * cos_y;
+                    }
+                }
+                result[v * 8 + u] = 0.25 * a_u * a_v * sum;
+            }
+        }
+
+        result
+    }
+
+    // Quantized coefficients may only differ where the reference is close to
+    // halfway between two steps
+    fn check_method(method: DctMethod) {
+        let mut seed = 1_u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as i32
+        };
+        for quality in [1, 50, 75, 90, 100] {
+            let quant_table = scale_quant_table(&LUMINANCE_QUANT_TABLE, quality);
+            let forward_dct = ForwardDct::new(method, &quant_table);
+            for _ in 0..200 {
+                let mut block = [0; 64];
+                for (index, sample) in block.iter_mut().enumerate() {
+                    // Mix smooth gradients with noise and hard edges
+                    *sample = match next() % 3 {
+                        0 => next() & 255,
+                        1 => (index as i32 * 4) & 255,
+                        _ => 255 * (index % 8 > 3) as i32,
+                    };
+                }
+                let reference = reference_dct(block);
+                let result = forward_dct.quantize(block);
+                for (index, &zig_zag) in ZIG_ZAG_ORDER.iter().enumerate() {
+                    let step = quant_table[index / 8][index % 8] as f64;
+                    let exact = reference[zig_zag] / step;
+                    let difference = (result[zig_zag] as f64 - exact).abs();
+                    assert!(
+                        difference < 0.52,
+                        "{:?} {} {}",
+                        method,
+                        exact,
+                        result[zig_zag]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_forward_dct_float() {
+        check_method(DctMethod::Float);
+    }
+
+    #[test]
+    fn test_forward_dct_integer() {
+        check_method(DctMethod::Integer);
+    }
+}
@@ -0,0 +1,1017 @@
+use super::dct::get_idct;
+use super::huffman::HuffmanSpec;
+use super::jpeg::ZIG_ZAG_ORDER;
+use super::lossless::predict;
+use super::{DynamicImage, ImageBuffer};
+use crate::color::Cmyk;
+use crate::Error;
+
+// Codes up to this length are found with a single table lookup
+const LOOKUP_BITS: u32 = 9;
+// Coefficients of 2^22 blocks take 1 GiB, enough for 8192 by 8192 pixels in three components
+const MAX_BLOCKS: usize = 1 << 22;
+// 2^28 samples of 16 bits take 512 MiB, enough for 8192 by 8192 pixels of RGB
+const MAX_SAMPLES: usize = 1 << 28;
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant_table: usize,
+    // Blocks allocated per row and column, whole MCUs even at the edges
+    blocks_x: usize,
+    blocks_y: usize,
+    // Quantized coefficients of each block, in natural order
+    coefficients: Vec<[i32; 64]>,
+}
+
+struct Frame {
+    progressive: bool,
+    width: usize,
+    height: usize,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    // `remaining` is the number of bytes after the segment, which bounds the blocks
+    fn parse(data: &[u8], progressive: bool, remaining: usize) -> Result<Self, Error> {
+        if data.len() < 6 || data.len() != 6 + data[5] as usize * 3 {
+            return Err(Error::Decoding("invalid SOF segment length".to_string()));
+        }
+        let precision = data[0];
+        let height = u16::from_be_bytes([data[1], data[2]]) as usize;
+        let width = u16::from_be_bytes([data[3], data[4]]) as usize;
+        if precision != 8 {
+            return Err(Error::Unsupported(format!("{}-bit JPEG", precision)));
+        }
+        // The height would come later in a DNL segment
+        if height == 0 {
+            return Err(Error::Unsupported("JPEG with a DNL segment".to_string()));
+        }
+        if width == 0 {
+            return Err(Error::Decoding("JPEG width must not be zero".to_string()));
+        }
+        if ![1, 3, 4].contains(&data[5]) {
+            return Err(Error::Unsupported(format!(
+                "JPEG with {} components",
+                data[5]
+            )));
+        }
+
+        let mut components = Vec::new();
+        for spec in data[6..].chunks_exact(3) {
+            let (h, v) = ((spec[1] >> 4) as usize, (spec[1] & 0xf) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || spec[2] > 3 {
+                return Err(Error::Decoding(format!(
+                    "invalid parameters of component {}",
+                    spec[0]
+                )));
+            }
+            if components.iter().any(|c: &Component| c.id == spec[0]) {
+                return Err(Error::Decoding(format!("duplicate component {}", spec[0])));
+            }
+            components.push(Component {
+                id: spec[0],
+                h,
+                v,
+                quant_table: spec[2] as usize,
+                blocks_x: 0,
+                blocks_y: 0,
+                coefficients: Vec::new(),
+            });
+        }
+
+        let h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+        let mcus_x = width.div_ceil(8 * h_max);
+        let mcus_y = height.div_ceil(8 * v_max);
+        let blocks = components.iter().map(|c| mcus_x * c.h * mcus_y * c.v).sum();
+        check_units(blocks, MAX_BLOCKS, remaining)?;
+        for component in components.iter_mut() {
+            component.blocks_x = mcus_x * component.h;
+            component.blocks_y = mcus_y * component.v;
+            component.coefficients = vec![[0; 64]; component.blocks_x * component.blocks_y];
+        }
+
+        Ok(Self {
+            progressive,
+            width,
+            height,
+            h_max,
+            v_max,
+            mcus_x,
+            mcus_y,
+            components,
+        })
+    }
+
+    // Samples of a component inside the image, before upsampling
+    fn component_size(&self, component: &Component) -> (usize, usize) {
+        (
+            (self.width * component.h).div_ceil(self.h_max),
+            (self.height * component.v).div_ceil(self.v_max),
+        )
+    }
+}
+
+// Frame of a lossless file, whose samples are decoded directly
+struct LosslessFrame {
+    precision: u8,
+    width: usize,
+    height: usize,
+    // Identifier of each component and its samples, row by row
+    components: Vec<(u8, Vec<u16>)>,
+}
+
+impl LosslessFrame {
+    fn parse(data: &[u8], remaining: usize) -> Result<Self, Error> {
+        if data.len() < 6 || data.len() != 6 + data[5] as usize * 3 {
+            return Err(Error::Decoding("invalid SOF segment length".to_string()));
+        }
+        let precision = data[0];
+        let height = u16::from_be_bytes([data[1], data[2]]) as usize;
+        let width = u16::from_be_bytes([data[3], data[4]]) as usize;
+        if !(2..=16).contains(&precision) {
+            return Err(Error::Decoding(format!("{}-bit lossless JPEG", precision)));
+        }
+        if height == 0 {
+            return Err(Error::Unsupported("JPEG with a DNL segment".to_string()));
+        }
+        if width == 0 {
+            return Err(Error::Decoding("JPEG width must not be zero".to_string()));
+        }
+        if data[5] != 1 && data[5] != 3 {
+            return Err(Error::Unsupported(format!(
+                "lossless JPEG with {} components",
+                data[5]
+            )));
+        }
+
+        check_units(width * height * data[5] as usize, MAX_SAMPLES, remaining)?;
+
+        let mut components = Vec::new();
+        for spec in data[6..].chunks_exact(3) {
+            if spec[1] != 0x11 {
+                return Err(Error::Unsupported(
+                    "lossless JPEG with subsampling".to_string(),
+                ));
+            }
+            if components.iter().any(|(id, _)| *id == spec[0]) {
+                return Err(Error::Decoding(format!("duplicate component {}", spec[0])));
+            }
+            components.push((spec[0], vec![0; width * height]));
+        }
+
+        Ok(Self {
+            precision,
+            width,
+            height,
+            components,
+        })
+    }
+
+    fn output(&self) -> Option<DynamicImage> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let samples = (0..self.width * self.height)
+            .flat_map(|i| self.components.iter().map(move |(_, plane)| plane[i]));
+        if self.precision <= 8 {
+            let data = samples.map(|sample| sample as u8).collect();
+            match self.components.len() {
+                1 => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Luma8),
+                _ => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Rgb8),
+            }
+        } else {
+            let data = samples.collect();
+            match self.components.len() {
+                1 => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Luma16),
+                _ => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Rgb16),
+            }
+        }
+    }
+}
+
+// Blocks or lossless samples of a frame, each takes at least one bit of scan data
+fn check_units(units: usize, limit: usize, remaining: usize) -> Result<(), Error> {
+    if units > limit {
+        return Err(Error::Unsupported(format!(
+            "JPEG with more than {} blocks or samples",
+            limit
+        )));
+    }
+    if units > remaining.saturating_mul(8) {
+        return Err(Error::Decoding(
+            "JPEG data is too short for the frame size".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+struct HuffmanTable {
+    // Value and code length by the next `LOOKUP_BITS` bits, length 0 for longer codes
+    lookup: Vec<(u8, u8)>,
+    // Largest code of each length, -1 when there is none
+    max_code: [i32; 17],
+    // Subtracted from a code to get the index of its value
+    offset: [i32; 17],
+    value: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(spec: &HuffmanSpec) -> Result<Self, Error> {
+        let mut lookup = vec![(0, 0); 1 << LOOKUP_BITS];
+        let mut max_code = [-1; 17];
+        let mut offset = [0; 17];
+        let mut code = 0_i32;
+        let mut index = 0_i32;
+        for length in 1..=16 {
+            let count = spec.count[length - 1] as i32;
+            offset[length] = code - index;
+            for _ in 0..count {
+                if code >= 1 << length {
+                    return Err(Error::Decoding("invalid Huffman table".to_string()));
+                }
+                if length as u32 <= LOOKUP_BITS {
+                    let shift = LOOKUP_BITS - length as u32;
+                    let start = (code as usize) << shift;
+                    for entry in &mut lookup[start..start + (1 << shift)] {
+                        *entry = (spec.value[index as usize], length as u8);
+                    }
+                }
+                code += 1;
+                index += 1;
+            }
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Self {
+            lookup,
+            max_code,
+            offset,
+            value: spec.value.clone(),
+        })
+    }
+}
+
+// Entropy-coded data read MSB first, with stuffed zero bytes removed
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    bits: u32,
+    // Set once a marker ends the data, zeros are read after it
+    marker: Option<u8>,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self {
+            data,
+            position,
+            buffer: 0,
+            bits: 0,
+            marker: None,
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), Error> {
+        while self.bits <= 56 {
+            let mut byte = 0;
+            if self.marker.is_none() {
+                byte = *self.data.get(self.position).ok_or_else(truncated)?;
+                if byte == 0xff {
+                    let next = *self.data.get(self.position + 1).ok_or_else(truncated)?;
+                    if next == 0 {
+                        self.position += 2;
+                    } else {
+                        // Leave the marker for the caller
+                        self.marker = Some(next);
+                        byte = 0;
+                    }
+                } else {
+                    self.position += 1;
+                }
+            }
+            self.buffer |= (byte as u64) << (56 - self.bits);
+            self.bits += 8;
+        }
+        Ok(())
+    }
+
+    fn peek(&mut self, count: u32) -> Result<u32, Error> {
+        if self.bits < count {
+            self.fill()?;
+        }
+        Ok((self.buffer >> (64 - count)) as u32)
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.buffer <<= count;
+        self.bits -= count;
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32, Error> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let value = self.peek(count)?;
+        self.consume(count);
+        Ok(value)
+    }
+
+    // A `count` bit magnitude category, where the lower half is negative
+    fn receive_extend(&mut self, count: u32) -> Result<i32, Error> {
+        let value = self.read_bits(count)? as i32;
+        if count > 0 && value < 1 << (count - 1) {
+            Ok(value - (1 << count) + 1)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, Error> {
+        let peek = self.peek(16)?;
+        let (value, length) = table.lookup[(peek >> (16 - LOOKUP_BITS)) as usize];
+        if length > 0 {
+            self.consume(length as u32);
+            return Ok(value);
+        }
+        for length in LOOKUP_BITS as usize + 1..=16 {
+            let code = (peek >> (16 - length)) as i32;
+            if code <= table.max_code[length] {
+                self.consume(length as u32);
+                return Ok(table.value[(code - table.offset[length]) as usize]);
+            }
+        }
+        Err(Error::Decoding("invalid Huffman code".to_string()))
+    }
+
+    // Drop the padding bits and read the RSTn marker that must follow
+    fn restart(&mut self, expected: u8) -> Result<(), Error> {
+        self.buffer = 0;
+        self.bits = 0;
+        // Reading never steps past a marker, so it is next either way
+        self.marker = None;
+        let marker = next_marker(self.data, &mut self.position)?;
+        if marker != expected {
+            return Err(Error::Decoding(format!(
+                "expected RST{} marker, found {:02X}",
+                expected - 0xd0,
+                marker
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn truncated() -> Error {
+    Error::Decoding("unexpected end of JPEG data".to_string())
+}
+
+// Marker at `position`, skipping any 0xFF fill bytes before it
+fn next_marker(data: &[u8], position: &mut usize) -> Result<u8, Error> {
+    if data.get(*position) != Some(&0xff) {
+        return Err(Error::Decoding(format!(
+            "expected a marker at offset {}",
+            position
+        )));
+    }
+    while data.get(*position + 1) == Some(&0xff) {
+        *position += 1;
+    }
+    let marker = *data.get(*position + 1).ok_or_else(truncated)?;
+    *position += 2;
+    Ok(marker)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    // Stored in natural order
+    quant_tables: [Option<[u16; 64]>; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: usize,
+    // Color transform of an Adobe segment
+    adobe_transform: Option<u8>,
+    frame: Option<Frame>,
+    lossless: Option<LosslessFrame>,
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, Error> {
+    if bytes.len() < 2 || bytes[..2] != [0xff, 0xd8] {
+        return Err(Error::Decoding("not a JPEG file".to_string()));
+    }
+
+    let mut decoder = Decoder {
+        data: bytes,
+        position: 2,
+        quant_tables: [None; 4],
+        dc_tables: [None, None, None, None],
+        ac_tables: [None, None, None, None],
+        restart_interval: 0,
+        adobe_transform: None,
+        frame: None,
+        lossless: None,
+    };
+    loop {
+        let marker = next_marker(bytes, &mut decoder.position)?;
+        match marker {
+            0xd9 => break,
+            // Markers without a segment
+            0x01 | 0xd0..=0xd7 => continue,
+            _ => {}
+        }
+
+        let data = decoder.segment()?;
+        match marker {
+            0xc0..=0xc3 if decoder.frame.is_some() || decoder.lossless.is_some() => {
+                return Err(Error::Decoding("more than one SOF segment".to_string()));
+            }
+            0xc0..=0xc2 => {
+                let remaining = bytes.len() - decoder.position;
+                decoder.frame = Some(Frame::parse(data, marker == 0xc2, remaining)?);
+            }
+            0xc3 => {
+                let remaining = bytes.len() - decoder.position;
+                decoder.lossless = Some(LosslessFrame::parse(data, remaining)?);
+            }
+            0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err(Error::Unsupported(format!(
+                    "JPEG process of marker {:02X}",
+                    marker
+                )))
+            }
+            0xc4 => decoder.parse_huffman_tables(data)?,
+            0xdb => decoder.parse_quant_tables(data)?,
+            0xdd => {
+                if data.len() != 2 {
+                    return Err(Error::Decoding("invalid DRI segment length".to_string()));
+                }
+                decoder.restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize;
+            }
+            0xdc => return Err(Error::Unsupported("JPEG with a DNL segment".to_string())),
+            0xda if decoder.lossless.is_some() => decoder.decode_lossless_scan(data)?,
+            0xda => decoder.decode_scan(data)?,
+            0xee if data.len() >= 12 && data.starts_with(b"Adobe") => {
+                decoder.adobe_transform = Some(data[11]);
+            }
+            // APPn, COM and anything else is skipped
+            _ => {}
+        }
+    }
+
+    decoder.output()
+}
+
+impl<'a> Decoder<'a> {
+    // Payload of the segment at the current position, after its length
+    fn segment(&mut self) -> Result<&'a [u8], Error> {
+        let data = self.data;
+        let start = self.position + 2;
+        if start > data.len() {
+            return Err(truncated());
+        }
+        let length = u16::from_be_bytes([data[self.position], data[self.position + 1]]) as usize;
+        if length < 2 || self.position + length > data.len() {
+            return Err(Error::Decoding("invalid segment length".to_string()));
+        }
+        self.position += length;
+        Ok(&data[start..self.position])
+    }
+
+    fn parse_quant_tables(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let (precision, id) = (data[0] >> 4, (data[0] & 0xf) as usize);
+            let size = if precision == 0 { 64 } else { 128 };
+            if precision > 1 || id > 3 || data.len() < 1 + size {
+                return Err(Error::Decoding("invalid DQT segment".to_string()));
+            }
+            let mut table = [0; 64];
+            for (k, &index) in ZIG_ZAG_ORDER.iter().enumerate() {
+                table[index] = if precision == 0 {
+                    data[1 + k] as u16
+                } else {
+                    u16::from_be_bytes([data[1 + 2 * k], data[2 + 2 * k]])
+                };
+            }
+            self.quant_tables[id] = Some(table);
+            data = &data[1 + size..];
+        }
+        Ok(())
+    }
+
+    fn parse_huffman_tables(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            if data.len() < 17 || data[0] >> 4 > 1 || data[0] & 0xf > 3 {
+                return Err(Error::Decoding("invalid DHT segment".to_string()));
+            }
+            let mut count = [0; 16];
+            count.copy_from_slice(&data[1..17]);
+            let total = count.iter().map(|&n| n as usize).sum::<usize>();
+            if total > 256 || data.len() < 17 + total {
+                return Err(Error::Decoding("invalid DHT segment".to_string()));
+            }
+            let spec = HuffmanSpec {
+                count,
+                value: data[17..17 + total].to_vec(),
+            };
+            let table = Some(HuffmanTable::new(&spec)?);
+            let id = (data[0] & 0xf) as usize;
+            if data[0] >> 4 == 0 {
+                self.dc_tables[id] = table;
+            } else {
+                self.ac_tables[id] = table;
+            }
+            data = &data[17 + total..];
+        }
+        Ok(())
+    }
+
+    fn decode_scan(&mut self, data: &[u8]) -> Result<(), Error> {
+        let frame = self
+            .frame
+            .as_mut()
+            .ok_or_else(|| Error::Decoding("SOS segment before SOF".to_string()))?;
+        let count = *data.first().unwrap_or(&0) as usize;
+        if count == 0 || count > 4 || data.len() != 4 + 2 * count {
+            return Err(Error::Decoding("invalid SOS segment".to_string()));
+        }
+        let parameters = &data[1 + 2 * count..];
+        let (start, end) = (parameters[0] as usize, parameters[1] as usize);
+        let (high, low) = ((parameters[2] >> 4) as u32, (parameters[2] & 0xf) as u32);
+        let valid = if frame.progressive {
+            // DC and AC coefficients never share a scan, AC scans have one component
+            start <= end
+                && end <= 63
+                && (start == 0) == (end == 0)
+                && (start == 0 || count == 1)
+                && high <= 13
+                && low <= 13
+        } else {
+            // Spectral selection and successive approximation cover everything at once
+            start == 0 && end == 63 && high == 0 && low == 0
+        };
+        if !valid {
+            return Err(Error::Decoding(format!(
+                "invalid scan parameters {}-{} {}/{}",
+                start, end, high, low
+            )));
+        }
+
+        // Component index with its DC and AC tables, when the scan uses them
+        let mut components = Vec::new();
+        for spec in data[1..1 + 2 * count].chunks_exact(2) {
+            let index = frame
+                .components
+                .iter()
+                .position(|c| c.id == spec[0])
+                .ok_or_else(|| Error::Decoding(format!("unknown component {}", spec[0])))?;
+            let (dc, ac) = ((spec[1] >> 4) as usize, (spec[1] & 0xf) as usize);
+            let dc = match self.dc_tables.get(dc) {
+                Some(Some(table)) => Some(table),
+                _ if start == 0 && high == 0 => {
+                    return Err(Error::Decoding("missing Huffman table".to_string()))
+                }
+                _ => None,
+            };
+            let ac = match self.ac_tables.get(ac) {
+                Some(Some(table)) => Some(table),
+                _ if end > 0 => return Err(Error::Decoding("missing Huffman table".to_string())),
+                _ => None,
+            };
+            components.push((index, dc, ac));
+        }
+        let mut scan = Scan {
+            components,
+            start,
+            end,
+            high,
+            low,
+            predictors: [0; 4],
+            eob_run: 0,
+        };
+
+        // Blocks of every unit between restart markers
+        let units: Vec<Vec<(usize, usize)>> = if let [(index, _, _)] = scan.components[..] {
+            // A single component is not interleaved and skips the padding blocks
+            let component = &frame.components[index];
+            let (width, height) = frame.component_size(component);
+            let (blocks_x, blocks_y) = (width.div_ceil(8), height.div_ceil(8));
+            (0..blocks_x * blocks_y)
+                .map(|i| vec![(index, (i / blocks_x) * component.blocks_x + i % blocks_x)])
+                .collect()
+        } else {
+            (0..frame.mcus_x * frame.mcus_y)
+                .map(|mcu| {
+                    let (mcu_x, mcu_y) = (mcu % frame.mcus_x, mcu / frame.mcus_x);
+                    let mut blocks = Vec::new();
+                    for &(index, _, _) in scan.components.iter() {
+                        let c = &frame.components[index];
+                        for y in 0..c.v {
+                            for x in 0..c.h {
+                                let row = mcu_y * c.v + y;
+                                blocks.push((index, row * c.blocks_x + mcu_x * c.h + x));
+                            }
+                        }
+                    }
+                    blocks
+                })
+                .collect()
+        };
+
+        let mut reader = BitReader::new(self.data, self.position);
+        for (i, unit) in units.iter().enumerate() {
+            if self.restart_interval > 0 && i > 0 && i % self.restart_interval == 0 {
+                let expected = 0xd0 + ((i / self.restart_interval - 1) % 8) as u8;
+                reader.restart(expected)?;
+                scan.predictors = [0; 4];
+                scan.eob_run = 0;
+            }
+            for &(index, block) in unit.iter() {
+                let coefficients = &mut frame.components[index].coefficients[block];
+                scan.decode_block(&mut reader, index, coefficients)?;
+            }
+        }
+
+        // Continue at the marker that ended the data
+        self.position = reader.position;
+        Ok(())
+    }
+
+    fn decode_lossless_scan(&mut self, data: &[u8]) -> Result<(), Error> {
+        let frame = self
+            .lossless
+            .as_mut()
+            .ok_or_else(|| Error::Decoding("SOS segment before SOF".to_string()))?;
+        let count = *data.first().unwrap_or(&0) as usize;
+        if count == 0 || count > frame.components.len() || data.len() != 4 + 2 * count {
+            return Err(Error::Decoding("invalid SOS segment".to_string()));
+        }
+        // Predictor, then the spectral end and successive approximation that must be 0
+        let parameters = &data[1 + 2 * count..];
+        let (predictor, point_transform) = (parameters[0], parameters[2] & 0xf);
+        if !(1..=7).contains(&predictor) || parameters[1] != 0 || parameters[2] >> 4 != 0 {
+            return Err(Error::Decoding(format!(
+                "invalid lossless scan parameters {} {} {}",
+                parameters[0], parameters[1], parameters[2]
+            )));
+        }
+        if point_transform != 0 {
+            return Err(Error::Unsupported(
+                "lossless JPEG with a point transform".to_string(),
+            ));
+        }
+
+        // Component index with its table
+        let mut components = Vec::new();
+        for spec in data[1..1 + 2 * count].chunks_exact(2) {
+            let index = frame
+                .components
+                .iter()
+                .position(|(id, _)| *id == spec[0])
+                .ok_or_else(|| Error::Decoding(format!("unknown component {}", spec[0])))?;
+            let table = match self.dc_tables.get((spec[1] >> 4) as usize) {
+                Some(Some(table)) => table,
+                _ => return Err(Error::Decoding("missing Huffman table".to_string())),
+            };
+            components.push((index, table));
+        }
+
+        let (width, precision) = (frame.width, frame.precision);
+        let mask = (1 << precision) - 1;
+        let mut reader = BitReader::new(self.data, self.position);
+        // First row since the last restart
+        let mut top = 0;
+        for y in 0..frame.height {
+            for x in 0..width {
+                let i = y * width + x;
+                if self.restart_interval > 0 && i > 0 && i % self.restart_interval == 0 {
+                    // Prediction starts over from the first row
+                    if x != 0 {
+                        return Err(Error::Unsupported(
+                            "lossless JPEG restart interval within a row".to_string(),
+                        ));
+                    }
+                    let expected = 0xd0 + ((i / self.restart_interval - 1) % 8) as u8;
+                    reader.restart(expected)?;
+                    top = y;
+                }
+                for &(index, table) in components.iter() {
+                    let difference = match reader.decode(table)? {
+                        size @ 0..=15 => reader.receive_extend(size as u32)?,
+                        16 => 32768,
+                        size => {
+                            return Err(Error::Decoding(format!(
+                                "invalid difference category {}",
+                                size
+                            )))
+                        }
+                    };
+                    let plane = &mut frame.components[index].1;
+                    let prediction = predict(plane, width, (x, y), top, predictor, precision);
+                    plane[i] = ((prediction + difference) & mask) as u16;
+                }
+            }
+        }
+
+        // Continue at the marker that ended the data
+        self.position = reader.position;
+        Ok(())
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn output(&self) -> Result<DynamicImage, Error> {
+        if let Some(lossless) = &self.lossless {
+            return lossless.output().ok_or_else(|| {
+                Error::Decoding("image data does not match the header".to_string())
+            });
+        }
+        let frame = self
+            .frame
+            .as_ref()
+            .ok_or_else(|| Error::Decoding("missing SOF segment".to_string()))?;
+        let (width, height) = (frame.width, frame.height);
+
+        let mut planes = Vec::new();
+        for component in frame.components.iter() {
+            let table = self.quant_tables[component.quant_table]
+                .ok_or_else(|| Error::Decoding("missing quantization table".to_string()))?;
+            let plane = samples(component, &table);
+            let (plane_width, plane_height) = frame.component_size(component);
+            planes.push(upsample(
+                &plane,
+                component.blocks_x * 8,
+                (plane_width, plane_height),
+                (width, height),
+            ));
+        }
+
+        let image = if let [luma] = &planes[..] {
+            ImageBuffer::from_channels(width as u32, height as u32, luma.clone())
+                .map(DynamicImage::Luma8)
+        } else if let [c, m, y, k] = &planes[..] {
+            let mut data = Vec::with_capacity(width * height * 3);
+            for i in 0..width * height {
+                let (c, m, y) = if self.adobe_transform == Some(2) {
+                    // The complement of R, G and B like libjpeg, still inverted
+                    let [r, g, b] = ycbcr_to_rgb(c[i], m[i], y[i]);
+                    (255 - r, 255 - g, 255 - b)
+                } else {
+                    (c[i], m[i], y[i])
+                };
+                let mut inks = [c, m, y, k[i]];
+                // Adobe's applications write inverted ink
+                if self.adobe_transform.is_some() {
+                    inks = inks.map(|ink| 255 - ink);
+                }
+                data.extend(Cmyk(inks).to_rgb().0);
+            }
+            ImageBuffer::from_channels(width as u32, height as u32, data).map(DynamicImage::Rgb8)
+        } else {
+            let mut data = Vec::with_capacity(width * height * 3);
+            for i in 0..width * height {
+                let (a, b, c) = (planes[0][i], planes[1][i], planes[2][i]);
+                // Adobe's transform 0 marks samples stored as RGB
+                if self.adobe_transform == Some(0) {
+                    data.extend([a, b, c]);
+                } else {
+                    data.extend(ycbcr_to_rgb(a, b, c));
+                }
+            }
+            ImageBuffer::from_channels(width as u32, height as u32, data).map(DynamicImage::Rgb8)
+        };
+
+        image.ok_or_else(|| Error::Decoding("image data does not match the header".to_string()))
+    }
+}
+
+// Parameters of a scan, and what is carried from one block to the next
+struct Scan<'t> {
+    components: Vec<(usize, Option<&'t HuffmanTable>, Option<&'t HuffmanTable>)>,
+    // Spectral selection, in zig-zag order
+    start: usize,
+    end: usize,
+    // Successive approximation, the bit position before and in this scan
+    high: u32,
+    low: u32,
+    predictors: [i32; 4],
+    // Blocks left whose coefficients in this band are all zero
+    eob_run: u32,
+}
+
+impl<'t> Scan<'t> {
+    // A baseline block is the first DC pass followed by the first AC pass
+    fn decode_block(
+        &mut self,
+        reader: &mut BitReader,
+        index: usize,
+        coefficients: &mut [i32; 64],
+    ) -> Result<(), Error> {
+        let (_, dc, ac) = *self.components.iter().find(|c| c.0 == index).unwrap();
+        if self.start == 0 {
+            if let Some(dc) = dc.filter(|_| self.high == 0) {
+                let size = reader.decode(dc)? as u32;
+                if size > 11 {
+                    return Err(Error::Decoding("invalid DC coefficient".to_string()));
+                }
+                let predictor = self.predictors[index] + reader.receive_extend(size)?;
+                // DC coefficients of 8-bit samples take 11 bits and a sign, a running sum
+                // beyond that is corrupt and would overflow once shifted
+                if predictor.abs() > 1 << 11 {
+                    return Err(Error::Decoding("DC coefficient out of range".to_string()));
+                }
+                self.predictors[index] = predictor;
+                coefficients[0] = predictor * (1 << self.low);
+            } else if reader.read_bits(1)? == 1 {
+                coefficients[0] |= 1 << self.low;
+            }
+        }
+        if let Some(ac) = ac.filter(|_| self.end > 0) {
+            if self.high == 0 {
+                self.decode_ac_first(reader, ac, coefficients)?;
+            } else {
+                self.decode_ac_refine(reader, ac, coefficients)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_ac_first(
+        &mut self,
+        reader: &mut BitReader,
+        ac: &HuffmanTable,
+        coefficients: &mut [i32; 64],
+    ) -> Result<(), Error> {
+        if self.eob_run > 0 {
+            self.eob_run -= 1;
+            return Ok(());
+        }
+
+        let mut k = self.start.max(1);
+        while k <= self.end {
+            let symbol = reader.decode(ac)?;
+            let (run, size) = ((symbol >> 4) as u32, (symbol & 0xf) as u32);
+            if size == 0 {
+                // ZRL skips 16 zeros, EOBn ends this and the next blocks
+                if run != 15 {
+                    self.eob_run = (1 << run) - 1 + reader.read_bits(run)?;
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run as usize;
+            if k > self.end {
+                return Err(Error::Decoding("AC coefficients past the band".to_string()));
+            }
+            coefficients[ZIG_ZAG_ORDER[k]] = reader.receive_extend(size)? * (1 << self.low);
+            k += 1;
+        }
+        Ok(())
+    }
+
+    // Coefficients already nonzero get a correction bit each, zero ones may
+    // become one step away from zero
+    fn decode_ac_refine(
+        &mut self,
+        reader: &mut BitReader,
+        ac: &HuffmanTable,
+        coefficients: &mut [i32; 64],
+    ) -> Result<(), Error> {
+        let step = 1 << self.low;
+        let mut k = self.start;
+        if self.eob_run == 0 {
+            while k <= self.end {
+                let symbol = reader.decode(ac)?;
+                let (mut run, size) = ((symbol >> 4) as u32, (symbol & 0xf) as u32);
+                let mut value = 0;
+                if size != 0 {
+                    if size != 1 {
+                        return Err(Error::Decoding("invalid AC refinement".to_string()));
+                    }
+                    value = if reader.read_bits(1)? == 1 {
+                        step
+                    } else {
+                        -step
+                    };
+                } else if run != 15 {
+                    self.eob_run = (1 << run) + reader.read_bits(run)?;
+                    break;
+                }
+
+                // Skip `run` zero coefficients, refining the nonzero ones on the way
+                while k <= self.end {
+                    let coefficient = &mut coefficients[ZIG_ZAG_ORDER[k]];
+                    if *coefficient != 0 {
+                        refine(reader, coefficient, step)?;
+                    } else if run == 0 {
+                        *coefficient = value;
+                        break;
+                    } else {
+                        run -= 1;
+                    }
+                    k += 1;
+                }
+                k += 1;
+            }
+        }
+
+        if self.eob_run > 0 {
+            while k <= self.end {
+                let coefficient = &mut coefficients[ZIG_ZAG_ORDER[k]];
+                if *coefficient != 0 {
+                    refine(reader, coefficient, step)?;
+                }
+                k += 1;
+            }
+            self.eob_run -= 1;
+        }
+        Ok(())
+    }
+}
+
+// Add the next lower bit to the magnitude of a nonzero coefficient
+fn refine(reader: &mut BitReader, coefficient: &mut i32, step: i32) -> Result<(), Error> {
+    if reader.read_bits(1)? == 1 && *coefficient & step == 0 {
+        *coefficient += if *coefficient > 0 { step } else { -step };
+    }
+    Ok(())
+}
+
+// Dequantize and transform every block into one plane of samples
+fn samples(component: &Component, table: &[u16; 64]) -> Vec<u8> {
+    let stride = component.blocks_x * 8;
+    let mut plane = vec![0; stride * component.blocks_y * 8];
+    for (i, coefficients) in component.coefficients.iter().enumerate() {
+        let (block_x, block_y) = (i % component.blocks_x, i / component.blocks_x);
+        let mut dequantized = [0.; 64];
+        for k in 0..64 {
+            // Corrupt data can hold coefficients far beyond the 16 bits of a valid one
+            dequantized[k] = coefficients[k].saturating_mul(table[k] as i32) as f64;
+        }
+        let block = get_idct(dequantized);
+        for y in 0..8 {
+            let start = (block_y * 8 + y) * stride + block_x * 8;
+            plane[start..start + 8].copy_from_slice(&block[y * 8..y * 8 + 8]);
+        }
+    }
+    plane
+}
+
+// Stretch a plane to the image size, interpolating between sample centers
+fn upsample(
+    plane: &[u8],
+    stride: usize,
+    (width, height): (usize, usize),
+    (out_width, out_height): (usize, usize),
+) -> Vec<u8> {
+    if (width, height) == (out_width, out_height) {
+        return (0..height)
+            .flat_map(|y| plane[y * stride..y * stride + width].iter().copied())
+            .collect();
+    }
+
+    // Neighbouring source samples and the weight of the second one
+    let positions = |size: usize, out_size: usize| -> Vec<(usize, usize, f32)> {
+        let ratio = size as f32 / out_size as f32;
+        (0..out_size)
+            .map(|i| {
+                let position = ((i as f32 + 0.5) * ratio - 0.5).clamp(0., (size - 1) as f32);
+                let low = position as usize;
+                (low, (low + 1).min(size - 1), position - low as f32)
+            })
+            .collect()
+    };
+    let columns = positions(width, out_width);
+    let rows = positions(height, out_height);
+
+    let mut data = Vec::with_capacity(out_width * out_height);
+    for &(top, bottom, dy) in rows.iter() {
+        for &(left, right, dx) in columns.iter() {
+            let sample = |x: usize, y: usize| plane[y * stride + x] as f32;
+            let upper = sample(left, top) * (1. - dx) + sample(right, top) * dx;
+            let lower = sample(left, bottom) * (1. - dx) + sample(right, bottom) * dx;
+            data.push((upper * (1. - dy) + lower * dy).round() as u8);
+        }
+    }
+    data
+}
+
+// JFIF full range conversion
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let (y, cb, cr) = (y as f32, cb as f32 - 128., cr as f32 - 128.);
+    let clamp = |value: f32| value.round().clamp(0., 255.) as u8;
+    [
+        clamp(y + 1.402 * cr),
+        clamp(y - 0.344136 * cb - 0.714136 * cr),
+        clamp(y + 1.772 * cb),
+    ]
+}
@@ -4,12 +4,16 @@ use lazy_static::lazy_static;
 
 use std::collections::HashMap;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HuffmanSpec {
     pub count: [u8; 16],
     pub value: Vec<u8>,
 }
 
-fn generate_huffman_table(huffman_spec: &HuffmanSpec) -> HashMap<u8, Bits> {
+// Codeword of every symbol
+pub type HuffmanTable = HashMap<u8, Bits>;
+
+pub fn generate_huffman_table(huffman_spec: &HuffmanSpec) -> HuffmanTable {
     let mut huffman_table = HashMap::new();
 
     let mut value_index = 0;
@@ -30,6 +34,91 @@ fn generate_huffman_table(huffman_spec: &HuffmanSpec) -> HashMap<u8, Bits> {
     huffman_table
 }
 
+// Code lengths from symbol frequencies as in JPEG Annex K.2, no longer than 16 bits
+pub fn optimal_huffman_spec(frequencies: &[u32; 256]) -> HuffmanSpec {
+    // A reserved symbol with the lowest frequency keeps any code from being all ones
+    let mut frequency = [0_u64; 257];
+    for (symbol, &count) in frequencies.iter().enumerate() {
+        frequency[symbol] = count as u64;
+    }
+    frequency[256] = 1;
+    let mut code_size = [0_usize; 257];
+    // Next symbol in the same subtree
+    let mut others = [None; 257];
+
+    loop {
+        // The two least frequent subtrees, ties go to the larger symbol
+        let (mut v1, mut v2): (Option<usize>, Option<usize>) = (None, None);
+        for symbol in 0..257 {
+            if frequency[symbol] == 0 {
+                continue;
+            }
+            if v1.is_none_or(|v| frequency[symbol] <= frequency[v]) {
+                v2 = v1;
+                v1 = Some(symbol);
+            } else if v2.is_none_or(|v| frequency[symbol] <= frequency[v]) {
+                v2 = Some(symbol);
+            }
+        }
+        let (mut v1, mut v2) = match (v1, v2) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => break,
+        };
+
+        // Merge them, every symbol of both gets one bit longer
+        frequency[v1] += frequency[v2];
+        frequency[v2] = 0;
+        code_size[v1] += 1;
+        while let Some(next) = others[v1] {
+            v1 = next;
+            code_size[v1] += 1;
+        }
+        others[v1] = Some(v2);
+        code_size[v2] += 1;
+        while let Some(next) = others[v2] {
+            v2 = next;
+            code_size[v2] += 1;
+        }
+    }
+
+    let mut bits = [0_u32; 258];
+    for &size in code_size.iter().filter(|&&size| size > 0) {
+        bits[size] += 1;
+    }
+    // Move pairs of the longest codes up, taking a prefix from a shorter code
+    for length in (17..bits.len()).rev() {
+        while bits[length] > 0 {
+            let mut shorter = length - 2;
+            while bits[shorter] == 0 {
+                shorter -= 1;
+            }
+            bits[length] -= 2;
+            bits[length - 1] += 1;
+            bits[shorter + 1] += 2;
+            bits[shorter] -= 1;
+        }
+    }
+    // Drop the reserved symbol, which is one of the longest codes
+    if let Some(longest) = (1..=16).rev().find(|&length| bits[length] > 0) {
+        bits[longest] -= 1;
+    }
+
+    let mut count = [0; 16];
+    for length in 1..=16 {
+        count[length - 1] = bits[length] as u8;
+    }
+    let mut value = Vec::new();
+    for size in 1..code_size.len() {
+        value.extend(
+            (0..256)
+                .filter(|&symbol| code_size[symbol] == size)
+                .map(|symbol| symbol as u8),
+        );
+    }
+
+    HuffmanSpec { count, value }
+}
+
 // JPEG general purpose hash table
 lazy_static! {
     pub static ref LUMINANCE_DC_SPEC: HuffmanSpec = HuffmanSpec {
@@ -92,12 +181,57 @@ lazy_static! {
             0xf9, 0xfa,
         ],
     };
-    pub static ref LUMINANCE_DC_TABLE: HashMap<u8, Bits> =
-        generate_huffman_table(&LUMINANCE_DC_SPEC);
-    pub static ref CHROMINANCE_DC_TABLE: HashMap<u8, Bits> =
-        generate_huffman_table(&CHROMINANCE_DC_SPEC);
-    pub static ref LUMINANCE_AC_TABLE: HashMap<u8, Bits> =
-        generate_huffman_table(&LUMINANCE_AC_SPEC);
-    pub static ref CHROMINANCE_AC_TABLE: HashMap<u8, Bits> =
-        generate_huffman_table(&CHROMINANCE_AC_SPEC);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Kraft sum of the code lengths, in units of 2^-16
+    fn kraft_sum(spec: &HuffmanSpec) -> u32 {
+        (0..16).map(|i| spec.count[i] as u32 * (1 << (15 - i))).sum()
+    }
+
+    #[test]
+    fn test_optimal_huffman_spec() {
+        let mut frequencies = [0; 256];
+        frequencies[0] = 100;
+        frequencies[1] = 50;
+        frequencies[2] = 25;
+        frequencies[3] = 25;
+        let spec = optimal_huffman_spec(&frequencies);
+        assert_eq!(spec.value, vec![0, 1, 2, 3]);
+        assert_eq!(&spec.count[..4], &[1, 1, 1, 1]);
+        // The all-ones code of 4 bits is left unused
+        assert!(kraft_sum(&spec) < 1 << 16);
+    }
+
+    #[test]
+    fn test_optimal_huffman_spec_length_limit() {
+        // Fibonacci frequencies make a code as deep as the number of symbols
+        let mut frequencies = [0; 256];
+        let (mut a, mut b) = (1_u32, 1_u32);
+        for frequency in frequencies.iter_mut().take(30) {
+            *frequency = a;
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let spec = optimal_huffman_spec(&frequencies);
+        assert_eq!(spec.value.len(), 30);
+        assert_eq!(spec.count.iter().map(|&n| n as usize).sum::<usize>(), 30);
+        assert!(kraft_sum(&spec) < 1 << 16);
+        // The most frequent symbols get the shortest codes
+        assert_eq!(spec.count[1], 2);
+        assert!(spec.value[..2].contains(&29) && spec.value[..2].contains(&28));
+    }
+
+    #[test]
+    fn test_optimal_huffman_spec_single_symbol() {
+        let mut frequencies = [0; 256];
+        frequencies[7] = 10;
+        let spec = optimal_huffman_spec(&frequencies);
+        assert_eq!(spec.count[0], 1);
+        assert_eq!(spec.value, vec![7]);
+    }
 }
@@ -1,15 +1,30 @@
-use super::{Image, Serializable};
+// Segment structs are named after their markers, e.g. `DQT`
+#![allow(clippy::upper_case_acronyms)]
+
+use super::{Image, ImageBuffer, Serializable};
+use crate::color::{Cmyk, ColorType, Pixel, Primitive, Rgb, Rgba};
+use crate::img::{check_data_size, check_dimensions};
+use crate::Error;
 
 use super::common::Bits;
-use super::dct::get_dct;
+use super::dct::ForwardDct;
 use super::huffman::{
+    generate_huffman_table, optimal_huffman_spec, HuffmanSpec, HuffmanTable,
     CHROMINANCE_AC_SPEC, CHROMINANCE_DC_SPEC, LUMINANCE_AC_SPEC, LUMINANCE_DC_SPEC,
 };
-use super::quant::{quant, LUMINANCE_QUANT_TABLE, CHROMINANCE_QUANT_TABLE};
-use super::rle::encode;
+use super::lossless::encode_lossless;
+use super::metadata::Density;
+use super::options::{ColorSpace, JpegOptions, LosslessOptions, Subsampling};
+use super::progressive::{
+    encode_scans, validate_script, ComponentBlocks, EncodedScan, ProgressiveScan,
+};
+use super::quant::QuantTable;
+use super::rle::{count_symbols, encode};
+
+use std::io::Write;
 
 // Pre-defxined zig-zag order index for array
-const ZIG_ZAG_ORDER: [usize; 64] = [
+pub const ZIG_ZAG_ORDER: [usize; 64] = [
     0, 1, 8, 16, 9, 2, 3, 10,
     17, 24, 32, 25, 18, 11, 4, 5,
     12, 19, 26, 33, 40, 48, 41, 34,
@@ -22,13 +37,34 @@ const ZIG_ZAG_ORDER: [usize; 64] = [
 
 pub struct JPEG {
     // start_of_image: Segment<SOI>
-    quant_tables: Segment<DQT>,
-    start_of_frame0: Segment<SOF0>,
-    huffman_tables: Segment<DHT>,
-    image_data: Segment<SOS>,
+    // JFIF only allows gray and YCbCr, Adobe's segment marks the other color spaces
+    jfif: Option<Segment<APP0>>,
+    adobe: Option<Segment<APP14>>,
+    metadata: Vec<Segment<Metadata>>,
+    // Lossless files have no quantization
+    quant_tables: Option<Segment<DQT>>,
+    start_of_frame: Segment<SOF>,
+    restart_interval: Option<Segment<DRI>>,
+    scans: Scans,
     // end_of_image: Segment<EOI>
 }
 
+// Baseline files code all coefficients in a single scan, progressive ones
+// spread them over scans that each come with their own tables
+enum Scans {
+    Sequential {
+        huffman_tables: Segment<DHT>,
+        // Holds the pixels and the scaled quantization tables
+        image_data: Box<Segment<SOS>>,
+    },
+    Progressive(Vec<(Option<Segment<DHT>>, Segment<EncodedScan>)>),
+    // Predicted samples instead of coefficients, the tables are built for them
+    Lossless {
+        huffman_tables: Segment<DHT>,
+        scan: Segment<EncodedScan>,
+    },
+}
+
 struct Segment<T: Payload> {
     marker: [u8; 2],
     // The following field can be deduced by payload
@@ -36,24 +72,87 @@ struct Segment<T: Payload> {
     payload: Option<T>,
 }
 
+// JFIF header
+struct APP0 {
+    density: Density,
+}
+
+// Adobe header, naming the color transform of the components
+struct APP14 {
+    // 0 for none, 1 for YCbCr and 2 for YCCK
+    transform: u8,
+}
+
+// Data of an APPn or COM segment, already encoded
+struct Metadata {
+    data: Vec<u8>,
+}
+
 // Quantization tables
 struct DQT {
-    // The following field is imported from `quant` module
-    // quant_table: [[[u8; 8]; 8]; 2]
+    // Luminance first, chrominance only if a component uses it
+    quant_tables: Vec<QuantTable>,
 }
 
-struct SOF0 {
+// Start Of Frame, baseline (SOF0), progressive (SOF2) or lossless (SOF3)
+struct SOF {
     depth: u8,
     width: u16,
     height: u16,
+    components: Vec<FrameComponent>,
+}
+
+// Sampling factors of a component and the index of its quantization and
+// Huffman tables, 0 for luminance and 1 for chrominance
+#[derive(Debug, Clone, Copy)]
+struct FrameComponent {
+    h: usize,
+    v: usize,
+    table: usize,
+}
+
+impl FrameComponent {
+    fn new(h: usize, v: usize, table: usize) -> Self {
+        FrameComponent { h, v, table }
+    }
+}
+
+// Components in the order they are numbered, as libjpeg lays them out: luma
+// (and black for YCCK) carries the sampling factors, chroma is sampled once
+// per MCU and CMYK is never subsampled
+fn frame_components(
     component: u8,
+    color_space: ColorSpace,
+    subsampling: Subsampling,
+) -> Vec<FrameComponent> {
+    let (h, v) = subsampling.factors();
+    match (component, color_space) {
+        (1, _) => vec![FrameComponent::new(1, 1, 0)],
+        (_, ColorSpace::YCbCr) => vec![
+            FrameComponent::new(h, v, 0),
+            FrameComponent::new(1, 1, 1),
+            FrameComponent::new(1, 1, 1),
+        ],
+        (_, ColorSpace::Cmyk) => vec![FrameComponent::new(1, 1, 0); 4],
+        (_, ColorSpace::Ycck) => vec![
+            FrameComponent::new(h, v, 0),
+            FrameComponent::new(1, 1, 1),
+            FrameComponent::new(1, 1, 1),
+            FrameComponent::new(h, v, 0),
+        ],
+    }
 }
 
 // Huffman tables
 struct DHT {
-    component: u8,
-    // The following field is imported from `huffman` module
-    // huffman_tables: [HuffmanSpec]
+    // Class (0 for DC, 1 for AC) and index of each table, then its spec
+    specs: Vec<(u8, HuffmanSpec)>,
+}
+
+// Define Restart Interval
+struct DRI {
+    // MCUs between two RSTn markers
+    interval: u16,
 }
 
 // Start Of Scanning
@@ -61,63 +160,391 @@ struct DHT {
 struct SOS {
     width: u16,
     height: u16,
-    component: u8,
+    color_space: ColorSpace,
+    components: Vec<FrameComponent>,
     data: Vec<u8>,
+    // Luminance and chrominance quantization folded into the DCT
+    forward_dcts: [ForwardDct; 2],
+    restart_interval: u16,
+    // DC and AC codes, indexed like the specs of DHT
+    huffman_tables: Vec<(HuffmanTable, HuffmanTable)>,
 }
 
 impl JPEG {
-    pub fn new(width: u16, height: u16, component: u8, data: &Vec<u8>) -> Self {
-        Self {
-            quant_tables: Segment {
-                marker: [0xff, 0xdb],
-                payload: Some(DQT {}),
+    // `data` is grayscale for one component, RGBA for three components and
+    // CMYK ink for four, which also takes `ColorSpace::Cmyk` or `Ycck`
+    pub fn new(width: u32, height: u32, component: u8, data: &[u8]) -> Result<Self, Error> {
+        Self::with_options(width, height, component, data, &JpegOptions::default())
+    }
+
+    pub fn with_options(
+        width: u32,
+        height: u32,
+        component: u8,
+        data: &[u8],
+        options: &JpegOptions,
+    ) -> Result<Self, Error> {
+        check_dimensions(width, height)?;
+        options.validate()?;
+        let channel = match (component, options.color_space) {
+            (1, ColorSpace::YCbCr) => 1,
+            (3, ColorSpace::YCbCr) | (4, ColorSpace::Cmyk) | (4, ColorSpace::Ycck) => 4,
+            _ => {
+                return Err(Error::Unsupported(format!(
+                    "JPEG with {} components in {:?}",
+                    component, options.color_space
+                )))
+            }
+        };
+        check_data_size(width, height, channel, data)?;
+        check_size(width, height)?;
+        let (width, height) = (width as u16, height as u16);
+        let quant_tables = options.quant_tables();
+        let components = frame_components(component, options.color_space, options.subsampling);
+        let table_count = components.iter().map(|c| c.table + 1).max().unwrap_or(1);
+
+        let mut metadata = Vec::new();
+        for segment in options.segments.iter() {
+            for data in segment.encode()? {
+                metadata.push(Segment {
+                    marker: segment.marker(),
+                    payload: Some(Metadata { data }),
+                });
+            }
+        }
+
+        let mut image_data = SOS {
+            width,
+            height,
+            color_space: options.color_space,
+            components: components.clone(),
+            // Blend RGBA over the background, the blocks only read the colors
+            data: if component == 3 {
+                options.flatten(width as usize, data)
+            } else {
+                data.to_vec()
             },
-            start_of_frame0: Segment {
-                marker: [0xff, 0xc0],
-                payload: Some(SOF0 {
+            forward_dcts: [
+                ForwardDct::new(options.dct_method, &quant_tables[0]),
+                ForwardDct::new(options.dct_method, &quant_tables[1]),
+            ],
+            restart_interval: options.restart_interval,
+            huffman_tables: Vec::new(),
+        };
+        let scans = if options.progressive {
+            let script = match &options.scan_script {
+                Some(script) => script.clone(),
+                None => ProgressiveScan::standard_script(component),
+            };
+            validate_script(&script, component)?;
+            let blocks = image_data.component_blocks()?;
+            let scans = encode_scans(&script, &blocks, options.restart_interval)?;
+            Scans::Progressive(
+                scans
+                    .into_iter()
+                    .map(|scan| {
+                        // DC refinement scans only carry raw bits
+                        let huffman_tables = if scan.tables.is_empty() {
+                            None
+                        } else {
+                            Some(Segment {
+                                marker: [0xff, 0xc4],
+                                payload: Some(DHT {
+                                    specs: scan.tables.clone(),
+                                }),
+                            })
+                        };
+                        let header = Segment {
+                            marker: [0xff, 0xda],
+                            payload: Some(scan),
+                        };
+                        (huffman_tables, header)
+                    })
+                    .collect(),
+            )
+        } else {
+            let specs = if options.optimize_huffman {
+                image_data.optimal_huffman_specs()?
+            } else {
+                let mut specs = vec![(LUMINANCE_DC_SPEC.clone(), LUMINANCE_AC_SPEC.clone())];
+                if table_count == 2 {
+                    specs.push((CHROMINANCE_DC_SPEC.clone(), CHROMINANCE_AC_SPEC.clone()));
+                }
+                specs
+            };
+            image_data.huffman_tables = specs
+                .iter()
+                .map(|(dc, ac)| (generate_huffman_table(dc), generate_huffman_table(ac)))
+                .collect();
+            // DC and AC tables of luminance, then of chrominance if it is used
+            let specs = specs
+                .into_iter()
+                .enumerate()
+                .flat_map(|(index, (dc, ac))| vec![(index as u8, dc), (0x10 | index as u8, ac)])
+                .collect();
+            Scans::Sequential {
+                huffman_tables: Segment {
+                    marker: [0xff, 0xc4],
+                    payload: Some(DHT { specs }),
+                },
+                image_data: Box::new(Segment {
+                    marker: [0xff, 0xda],
+                    payload: Some(image_data),
+                }),
+            }
+        };
+
+        let (jfif, adobe) = match (component, options.color_space) {
+            (4, color_space) => {
+                let transform = if color_space == ColorSpace::Ycck { 2 } else { 0 };
+                let adobe = Segment {
+                    marker: [0xff, 0xee],
+                    payload: Some(APP14 { transform }),
+                };
+                (None, Some(adobe))
+            }
+            _ => {
+                let jfif = Segment {
+                    marker: [0xff, 0xe0],
+                    payload: Some(APP0 {
+                        density: options.density,
+                    }),
+                };
+                (Some(jfif), None)
+            }
+        };
+
+        Ok(Self {
+            jfif,
+            adobe,
+            metadata,
+            quant_tables: Some(Segment {
+                marker: [0xff, 0xdb],
+                payload: Some(DQT {
+                    quant_tables: quant_tables[..table_count].to_vec(),
+                }),
+            }),
+            start_of_frame: Segment {
+                marker: [0xff, if options.progressive { 0xc2 } else { 0xc0 }],
+                payload: Some(SOF {
                     depth: 8,
                     width,
                     height,
-                    component,
+                    components,
                 }),
             },
-            huffman_tables: Segment {
-                marker: [0xff, 0xc4],
-                payload: Some(DHT { component }),
-            },
-            image_data: Segment {
-                marker: [0xff, 0xda],
-                payload: Some(SOS {
-                    width,
-                    height,
-                    component,
-                    data: data.to_vec(),
+            restart_interval: match options.restart_interval {
+                0 => None,
+                interval => Some(Segment {
+                    marker: [0xff, 0xdd],
+                    payload: Some(DRI { interval }),
                 }),
             },
+            scans,
+        })
+    }
+
+    // Grayscale pixels are saved with one component, others with three, or
+    // four when separated into inks
+    pub fn from_buffer<P: Pixel<Subpixel = u8>>(image: &ImageBuffer<P>) -> Result<Self, Error> {
+        Self::from_buffer_with_options(image, &JpegOptions::default())
+    }
+
+    pub fn from_buffer_with_options<P: Pixel<Subpixel = u8>>(
+        image: &ImageBuffer<P>,
+        options: &JpegOptions,
+    ) -> Result<Self, Error> {
+        let (width, height) = image.dimensions();
+        if options.color_space != ColorSpace::YCbCr {
+            let under_color_removal = options.under_color_removal as f64 / 100.;
+            let data = options
+                .flatten(width as usize, &image.map(Pixel::to_rgba).to_channels())
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let rgb = Rgb([pixel[0], pixel[1], pixel[2]]);
+                    Cmyk::from_rgb(rgb, under_color_removal).0
+                })
+                .collect::<Vec<_>>();
+            return Self::with_options(width, height, 4, &data, options);
+        }
+        match P::COLOR_TYPE {
+            ColorType::Luma => {
+                Self::with_options(width, height, 1, &image.to_channels(), options)
+            }
+            ColorType::LumaA => {
+                // The background may be colored, so blend in RGB before taking luma
+                let data = options
+                    .flatten(width as usize, &image.map(Pixel::to_rgba).to_channels())
+                    .chunks_exact(4)
+                    .map(|pixel| Rgba::from_slice(pixel).to_luma()[0])
+                    .collect::<Vec<_>>();
+                Self::with_options(width, height, 1, &data, options)
+            }
+            ColorType::Rgb | ColorType::Rgba => {
+                let data = image.map(Pixel::to_rgba).to_channels();
+                Self::with_options(width, height, 3, &data, options)
+            }
         }
     }
+
+    // Gray or RGB samples, predicted and coded without loss at the precision
+    // of the options, which can be up to 16 bits
+    pub fn from_buffer_lossless<P: Pixel>(
+        image: &ImageBuffer<P>,
+        options: &LosslessOptions,
+    ) -> Result<Self, Error> {
+        let (width, height) = image.dimensions();
+        check_dimensions(width, height)?;
+        options.validate()?;
+        let component = match P::COLOR_TYPE {
+            ColorType::Luma => 1,
+            ColorType::Rgb => 3,
+            color_type => {
+                return Err(Error::Unsupported(format!(
+                    "lossless JPEG of {:?}",
+                    color_type
+                )))
+            }
+        };
+        check_size(width, height)?;
+        let precision = options.precision.unwrap_or(P::Subpixel::BITS);
+        // The interval counts MCUs, a sample of each component
+        let interval = options.restart_interval as usize * width as usize;
+        if interval > u16::MAX as usize {
+            return Err(Error::FormatLimit(format!(
+                "JPEG restart interval of {} rows {} pixels wide",
+                options.restart_interval, width
+            )));
+        }
+
+        let planes = (0..component)
+            .map(|index| {
+                image
+                    .pixels()
+                    .map(|pixel| pixel.channels()[index].to_f64() as u16)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if let Some(&sample) = planes.iter().flatten().find(|&&s| s as u32 >> precision != 0) {
+            return Err(Error::Unsupported(format!(
+                "sample {} in {}-bit lossless JPEG",
+                sample, precision
+            )));
+        }
+        let scan = encode_lossless(&planes, width as usize, precision, options)?;
+
+        Ok(Self {
+            jfif: None,
+            // Otherwise three components would be taken for YCbCr
+            adobe: match component {
+                3 => Some(Segment {
+                    marker: [0xff, 0xee],
+                    payload: Some(APP14 { transform: 0 }),
+                }),
+                _ => None,
+            },
+            metadata: Vec::new(),
+            quant_tables: None,
+            start_of_frame: Segment {
+                marker: [0xff, 0xc3],
+                payload: Some(SOF {
+                    depth: precision,
+                    width: width as u16,
+                    height: height as u16,
+                    components: vec![FrameComponent::new(1, 1, 0); component],
+                }),
+            },
+            restart_interval: match interval {
+                0 => None,
+                interval => Some(Segment {
+                    marker: [0xff, 0xdd],
+                    payload: Some(DRI {
+                        interval: interval as u16,
+                    }),
+                }),
+            },
+            scans: Scans::Lossless {
+                huffman_tables: Segment {
+                    marker: [0xff, 0xc4],
+                    payload: Some(DHT {
+                        specs: scan.tables.clone(),
+                    }),
+                },
+                scan: Segment {
+                    marker: [0xff, 0xda],
+                    payload: Some(scan),
+                },
+            },
+        })
+    }
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum Mode {
-    Luminance,
-    Chromiance,
+// Size fields of JPEG are 16 bits
+fn check_size(width: u32, height: u32) -> Result<(), Error> {
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(Error::FormatLimit(format!(
+            "JPEG can not be larger than {} pixels",
+            u16::MAX
+        )));
+    }
+
+    Ok(())
 }
 
 impl Serializable for JPEG {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         // SOI marker
-        bytes.extend([0xff, 0xd8]);
-        bytes.extend(self.quant_tables.get_bytes());
-        bytes.extend(self.start_of_frame0.get_bytes());
-        bytes.extend(self.huffman_tables.get_bytes());
-        bytes.extend(self.image_data.get_bytes());
+        writer.write_all(&[0xff, 0xd8])?;
+        if let Some(jfif) = &self.jfif {
+            jfif.serialize(writer)?;
+        }
+        if let Some(adobe) = &self.adobe {
+            adobe.serialize(writer)?;
+        }
+        for segment in self.metadata.iter() {
+            segment.serialize(writer)?;
+        }
+        if let Some(quant_tables) = &self.quant_tables {
+            quant_tables.serialize(writer)?;
+        }
+        self.start_of_frame.serialize(writer)?;
+        match &self.scans {
+            Scans::Sequential {
+                huffman_tables,
+                image_data,
+            } => {
+                huffman_tables.serialize(writer)?;
+                if let Some(restart_interval) = &self.restart_interval {
+                    restart_interval.serialize(writer)?;
+                }
+                image_data.serialize(writer)?;
+            }
+            Scans::Progressive(scans) => {
+                if let Some(restart_interval) = &self.restart_interval {
+                    restart_interval.serialize(writer)?;
+                }
+                for (huffman_tables, scan) in scans.iter() {
+                    if let Some(huffman_tables) = huffman_tables {
+                        huffman_tables.serialize(writer)?;
+                    }
+                    scan.serialize(writer)?;
+                }
+            }
+            Scans::Lossless {
+                huffman_tables,
+                scan,
+            } => {
+                huffman_tables.serialize(writer)?;
+                if let Some(restart_interval) = &self.restart_interval {
+                    restart_interval.serialize(writer)?;
+                }
+                scan.serialize(writer)?;
+            }
+        }
         // EOI marker
-        bytes.extend([0xff, 0xd9]);
+        writer.write_all(&[0xff, 0xd9])?;
 
-        bytes
+        Ok(())
     }
 }
 
@@ -131,99 +558,142 @@ impl<T> Serializable for Segment<T>
 where
     T: Payload,
 {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         // Marker header
-        bytes.extend(self.marker);
+        writer.write_all(&self.marker)?;
         if let Some(payload) = &self.payload {
             // Marker length
             let length = payload.get_length() + 2; // include length's own space(2 byte) as well
-            bytes.extend(length.to_be_bytes()); // High byte first
-            bytes.extend(payload.get_bytes()); // Effective data
+            writer.write_all(&length.to_be_bytes())?; // High byte first
+            payload.serialize(writer)?; // Effective data
         }
 
-        bytes
+        Ok(())
     }
 }
 
-impl Serializable for DQT {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+impl Serializable for APP0 {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // Identifier and version 1.02
+        writer.write_all(b"JFIF\0")?;
+        writer.write_all(&[0x01, 0x02, self.density.unit as u8])?;
+        writer.write_all(&self.density.x.to_be_bytes())?;
+        writer.write_all(&self.density.y.to_be_bytes())?;
+        // No thumbnail
+        writer.write_all(&[0x00, 0x00])?;
+
+        Ok(())
+    }
+}
+
+impl Payload for APP0 {
+    fn get_length(&self) -> u16 {
+        // 5: identifier, 2: version, 1: unit, 4: density, 2: thumbnail size
+        14
+    }
+}
+
+impl Serializable for APP14 {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // Identifier, version 100 and no flags
+        writer.write_all(b"Adobe")?;
+        writer.write_all(&[0x00, 0x64, 0x00, 0x00, 0x00, 0x00, self.transform])?;
+
+        Ok(())
+    }
+}
+
+impl Payload for APP14 {
+    fn get_length(&self) -> u16 {
+        // 5: identifier, 2: version, 4: flags, 1: transform
+        12
+    }
+}
+
+impl Serializable for Metadata {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.data)?;
+
+        Ok(())
+    }
+}
+
+impl Payload for Metadata {
+    fn get_length(&self) -> u16 {
+        // `MetadataSegment::encode` keeps it within a segment
+        self.data.len() as u16
+    }
+}
 
-        // Index 0
-        bytes.push(0_u8);
-        bytes.extend(LUMINANCE_QUANT_TABLE.iter().flatten());
-        // Index 1
-        bytes.push(1_u8);
-        bytes.extend(CHROMINANCE_QUANT_TABLE.iter().flatten());
+impl Serializable for DQT {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (index, quant_table) in self.quant_tables.iter().enumerate() {
+            // 8-bit precision and index of table
+            writer.write_all(&[index as u8])?;
+            for row in quant_table.iter() {
+                writer.write_all(row)?;
+            }
+        }
 
-        bytes
+        Ok(())
     }
 }
 
 impl Payload for DQT {
     fn get_length(&self) -> u16 {
-        // 2: 2 tables(Luminance and chromiance)
         // 1: index of table
         // 64: 64 bytes in each table
-        2 * (1 + 64)
+        self.quant_tables.len() as u16 * (1 + 64)
     }
 }
 
-impl Serializable for SOF0 {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+impl Serializable for SOF {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&[self.depth])?;
+        writer.write_all(&self.height.to_be_bytes())?;
+        writer.write_all(&self.width.to_be_bytes())?;
+        writer.write_all(&[self.components.len() as u8])?;
+
+        // Identifiers count from 1
+        for (index, component) in self.components.iter().enumerate() {
+            let factors = ((component.h << 4) | component.v) as u8;
+            writer.write_all(&[index as u8 + 1, factors, component.table as u8])?;
+        }
 
-        bytes.push(self.depth);
-        bytes.extend(self.height.to_be_bytes());
-        bytes.extend(self.width.to_be_bytes());
-        bytes.push(self.component);
+        Ok(())
+    }
+}
 
-        if self.component == 1 {
-            // No subsampling for grayscale image
-            bytes.extend([0x1, 0x11, 0x00]);
-        } else {
-            // 4:2:0 subsampling for other image
-            bytes.extend([0x01, 0x22, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01]);
-        }
+impl Payload for SOF {
+    fn get_length(&self) -> u16 {
+        // See Serializable traits for SOF for more details
+        6 + 3 * self.components.len() as u16
+    }
+}
 
-        bytes
+impl Serializable for DRI {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.interval.to_be_bytes())?;
+
+        Ok(())
     }
 }
 
-impl Payload for SOF0 {
+impl Payload for DRI {
     fn get_length(&self) -> u16 {
-        // See Serializable traits for SOF0 for more details
-        6 + 3 * self.component as u16
+        2
     }
 }
 
 impl Serializable for DHT {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        bytes.push(0x00);
-        bytes.extend(LUMINANCE_DC_SPEC.count);
-        bytes.extend(&LUMINANCE_DC_SPEC.value);
-
-        bytes.push(0x10);
-        bytes.extend(LUMINANCE_AC_SPEC.count);
-        bytes.extend(&LUMINANCE_AC_SPEC.value);
-
-        // None-gray scale image
-        // Put Chrominace table
-        if self.component != 1 {
-            bytes.push(0x01);
-            bytes.extend(CHROMINANCE_DC_SPEC.count);
-            bytes.extend(&CHROMINANCE_DC_SPEC.value);
-
-            bytes.push(0x11);
-            bytes.extend(CHROMINANCE_AC_SPEC.count);
-            bytes.extend(&CHROMINANCE_AC_SPEC.value);
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (id, spec) in self.specs.iter() {
+            writer.write_all(&[*id])?;
+            writer.write_all(&spec.count)?;
+            writer.write_all(&spec.value)?;
         }
 
-        bytes
+        Ok(())
     }
 }
 
@@ -231,232 +701,250 @@ impl Payload for DHT {
     fn get_length(&self) -> u16 {
         // 1: header
         // 16: count array length
-        let luminance_length = (1 + 16 + LUMINANCE_DC_SPEC.value.len() as u16)
-            + (1 + 16 + LUMINANCE_AC_SPEC.value.len() as u16);
-        if self.component == 1 {
-            return luminance_length;
-        }
-        let chromiance_length = (1 + 16 + CHROMINANCE_DC_SPEC.value.len() as u16)
-            + (1 + 16 + CHROMINANCE_AC_SPEC.value.len() as u16);
-        luminance_length + chromiance_length
+        self.specs
+            .iter()
+            .map(|(_, spec)| 1 + 16 + spec.value.len() as u16)
+            .sum()
     }
 }
 
 impl SOS {
-    // Porcess every block and add byte to the reference
-    // Return remained bits
-    fn process_gray_blocks(&self, bytes: &mut Vec<u8>) -> Bits {
-        let mut prev_dc = 0;
-        let mut bits = Bits::new(0, 0);
-        // Process every 8x8 block
-        for start_y in (0..self.height).step_by(8) {
-            for start_x in (0..self.width).step_by(8) {
-                let block = self.get_gray_block(start_x as usize, start_y as usize);
-                prev_dc = dump_bytes(block, prev_dc, Mode::Luminance, bytes, &mut bits);
-            }
-        }
-
-        bits
+    // Largest sampling factors, which set the size of an MCU
+    fn max_factors(&self) -> (usize, usize) {
+        let h_max = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        (h_max, v_max)
     }
 
-    fn process_rgba_blocks(&self, bytes: &mut Vec<u8>) -> Bits {
-        let (mut prev_y_dc, mut prev_cb_dc, mut prev_cr_dc) = (0, 0, 0);
-        let mut bits = Bits::new(0, 0);
-
-        // Process order:
-        // Y blocks: 16 x 16 (which will be then divided into 4 8x8 blocks)
-        // Cb blocks: 8 x 8 (Subsampled from the 16 x 16 block)
-        // Cr blocks: 8 x 8 (Subsampled from the 16 x 16 block)
-        for start_y in (0..self.height).step_by(16) {
-            for start_x in (0..self.width).step_by(16) {
-                // Get four 4x4 blocks array from origin 16x16 block
-                let (y_blocks, cb_blocks, cr_blocks) =
-                    self.convert_rgb_blocks_to_ycbcr_blocks(start_x.into(), start_y.into());
-                // Divide 16x16 blocks into 4 4x4 blocks
-                for index in 0..4 {
-                    prev_y_dc = dump_bytes(
-                        y_blocks[index],
-                        prev_y_dc,
-                        Mode::Luminance,
-                        // The following 2 params are only to store the state
-                        // If you just want to read the code, just ignore them
-                        bytes,
-                        &mut bits,
-                    );
+    // Visit the quantized blocks in the order they are written, along with
+    // the index of their component, e.g. 0 for Y (or gray), 1 for Cb and 2 for Cr
+    fn for_each_block<F>(&self, mut visit: F) -> Result<(), Error>
+    where
+        F: FnMut(usize, &[i32; 64]) -> Result<(), Error>,
+    {
+        // Process order of every (8h)x(8v) MCU, for each component in turn:
+        // its h x v 8x8 blocks row by row, subsampled ones cover the whole MCU
+        let (h_max, v_max) = self.max_factors();
+        let mut samples = vec![[0; 4]; 64 * h_max * v_max];
+        for start_y in (0..self.height as usize).step_by(8 * v_max) {
+            for start_x in (0..self.width as usize).step_by(8 * h_max) {
+                self.get_samples(start_x, start_y, &mut samples, 8 * h_max);
+                for (index, component) in self.components.iter().enumerate() {
+                    let forward_dct = &self.forward_dcts[component.table];
+                    let scale = (h_max / component.h, v_max / component.v);
+                    for block_y in 0..component.v {
+                        for block_x in 0..component.h {
+                            let position = (block_x * 8 * scale.0, block_y * 8 * scale.1);
+                            let block = get_block(&samples, 8 * h_max, index, position, scale);
+                            visit(index, &quantize_block(block, forward_dct))?;
+                        }
+                    }
                 }
-                let subsampled_cb_block = subsampling(cb_blocks);
-                prev_cb_dc = dump_bytes(
-                    subsampled_cb_block,
-                    prev_cb_dc,
-                    Mode::Chromiance,
-                    bytes,
-                    &mut bits,
-                );
-                let subsampled_cr_block = subsampling(cr_blocks);
-                prev_cr_dc = dump_bytes(
-                    subsampled_cr_block,
-                    prev_cr_dc,
-                    Mode::Chromiance,
-                    bytes,
-                    &mut bits,
-                );
             }
         }
 
-        bits
-    }
-
-     // Get four 4x4 blocks array from origin 16x16 block
-    fn convert_rgb_blocks_to_ycbcr_blocks(
-        &self,
-        start_x: usize,
-        start_y: usize,
-    ) -> ([[i32; 64]; 4], [[i32; 64]; 4], [[i32; 64]; 4]) {
-        let (mut y_blocks, mut cb_blocks, mut cr_blocks) = ([[0; 64]; 4], [[0; 64]; 4], [[0; 64]; 4]);
-
-        for y in (0..16).step_by(8) {
-            for x in (0..16).step_by(8) {
-                // Calculate the position of one of the blocks
-                let block_start_y = std::cmp::min(start_y + y, self.height as usize - 1);
-                let block_start_x = std::cmp::min(start_x + x, self.width as usize - 1);
-
-                // Insert position of the final 4 elements array
-                let index = (2 * y + x) / 8;
-                // Get 8x8 component by given start x and start y
-                let (y_block, cb_block, cr_block) =
-                    self.get_ycbcr_block(block_start_x, block_start_y);
-                y_blocks[index] = y_block;
-                cb_blocks[index] = cb_block;
-                cr_blocks[index] = cr_block;
+        Ok(())
+    }
+
+    // Gather symbol statistics of luminance and chrominance, then build the
+    // shortest codes for exactly those symbols
+    fn optimal_huffman_specs(&self) -> Result<Vec<(HuffmanSpec, HuffmanSpec)>, Error> {
+        let classes = self.components.iter().map(|c| c.table + 1).max().unwrap_or(1);
+        let mut frequencies = vec![([0; 256], [0; 256]); classes];
+        let mut prev_dc = [0; 4];
+        let mut block_index = 0;
+        self.for_each_block(|component, sequence| {
+            if self.restart_before(block_index).is_some() {
+                prev_dc = [0; 4];
             }
-        }
+            block_index += 1;
+            let (dc, ac) = &mut frequencies[self.components[component].table];
+            count_symbols(sequence, prev_dc[component], dc, ac);
+            prev_dc[component] = sequence[0];
+            Ok(())
+        })?;
+
+        Ok(frequencies
+            .iter()
+            .map(|(dc, ac)| (optimal_huffman_spec(dc), optimal_huffman_spec(ac)))
+            .collect())
+    }
 
-        (y_blocks, cb_blocks, cr_blocks)
+    // Blocks of each component on the MCU grid, for scans that revisit them
+    fn component_blocks(&self) -> Result<Vec<ComponentBlocks>, Error> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (h_max, v_max) = self.max_factors();
+        let (mcus_x, mcus_y) = (width.div_ceil(8 * h_max), height.div_ceil(8 * v_max));
+        let mut components = self
+            .components
+            .iter()
+            .map(|&FrameComponent { h, v, table }| ComponentBlocks {
+                blocks: vec![[0; 64]; mcus_x * h * mcus_y * v],
+                stride: mcus_x * h,
+                // A.1.1: a component covers ceil(X * H / Hmax) columns
+                width: (width * h).div_ceil(h_max).div_ceil(8),
+                height: (height * v).div_ceil(v_max).div_ceil(8),
+                h,
+                v,
+                table,
+            })
+            .collect::<Vec<_>>();
+
+        // Blocks come MCU by MCU, each component's blocks row by row
+        let blocks_per_mcu = self.components.iter().map(|c| c.h * c.v).sum::<usize>();
+        let mut block_index = 0;
+        self.for_each_block(|component, sequence| {
+            let (mcu, mut offset) = (block_index / blocks_per_mcu, block_index % blocks_per_mcu);
+            block_index += 1;
+            offset -= self.components[..component].iter().map(|c| c.h * c.v).sum::<usize>();
+            let blocks = &mut components[component];
+            let x = mcu % mcus_x * blocks.h + offset % blocks.h;
+            let y = mcu / mcus_x * blocks.v + offset / blocks.h;
+            blocks.blocks[y * blocks.stride + x] = *sequence;
+            Ok(())
+        })?;
+
+        Ok(components)
     }
 
-    fn get_gray_block(&self, start_x: usize, start_y: usize) -> [i32; 64] {
-        let mut block = [0; 64];
-        // Pad the edge
-        for row_index in 0..8 {
-            for column_index in 0..8 {
-                // Map to self.data by using offset pair
-                let offset_y = std::cmp::min(start_y + row_index, self.height as usize - 1);
-                let offset_x = std::cmp::min(start_x + column_index, self.width as usize - 1);
-                block[row_index * 8 + column_index] = self.data[offset_y * self.width as usize + offset_x] as i32;
-            }
+    // Index of the RSTn marker that goes right before this block, when a
+    // restart interval ends there
+    fn restart_before(&self, block_index: usize) -> Option<u8> {
+        let blocks_per_mcu = self.components.iter().map(|c| c.h * c.v).sum::<usize>();
+        let blocks = blocks_per_mcu * self.restart_interval as usize;
+        if blocks == 0 || block_index == 0 || !block_index.is_multiple_of(blocks) {
+            return None;
         }
-        block
-    }
-
-    fn get_ycbcr_block(&self, start_x: usize, start_y: usize) -> ([i32; 64], [i32; 64], [i32; 64]) {
-        let (mut y_block, mut cb_block, mut cr_block) = ([0; 64], [0; 64], [0; 64]);
-
-        for row_index in 0..8 {
-            for column_index in 0..8 {
-                let offset_y = std::cmp::min(start_y + row_index, self.height as usize - 1);
-                let offset_x = std::cmp::min(start_x + column_index, self.width as usize - 1);
-                let offset = (offset_y * self.width as usize + offset_x) * 4; // RGBA 4 channels
-
-                let (r, g, b) = (
-                    self.data[offset],
-                    self.data[offset + 1],
-                    self.data[offset + 2],
-                );
-                let (y, cb, cr) = rgb_2_ycbcr(r, g, b);
-                y_block[row_index * 8 + column_index] = y as i32;
-                cb_block[row_index * 8 + column_index] = cb as i32;
-                cr_block[row_index * 8 + column_index] = cr as i32;
+
+        Some(((block_index / blocks - 1) % 8) as u8)
+    }
+
+    // Values of each component over the MCU `width` pixels wide starting at
+    // `(start_x, start_y)`, pixels past the edge repeat the last row or column
+    fn get_samples(&self, start_x: usize, start_y: usize, samples: &mut [[u8; 4]], width: usize) {
+        let (image_width, image_height) = (self.width as usize, self.height as usize);
+        let channel = if self.components.len() == 1 { 1 } else { 4 }; // RGBA or CMYK 4 channels
+        let stride = image_width * channel;
+        for (row_index, row) in samples.chunks_exact_mut(width).enumerate() {
+            let offset_y = std::cmp::min(start_y + row_index, image_height - 1);
+            let data = &self.data[offset_y * stride..(offset_y + 1) * stride];
+            for (column_index, sample) in row.iter_mut().enumerate() {
+                let offset = std::cmp::min(start_x + column_index, image_width - 1) * channel;
+                let pixel = &data[offset..offset + channel];
+                *sample = match (channel, self.color_space) {
+                    (1, _) => [pixel[0], 0, 0, 0],
+                    (_, ColorSpace::YCbCr) => {
+                        let (y, cb, cr) = rgb_2_ycbcr(pixel[0], pixel[1], pixel[2]);
+                        [y, cb, cr, 0]
+                    }
+                    // Adobe's applications write how much of each ink is left out
+                    (_, ColorSpace::Cmyk) => {
+                        [255 - pixel[0], 255 - pixel[1], 255 - pixel[2], 255 - pixel[3]]
+                    }
+                    // As libjpeg does, R, G and B are the complements of those values
+                    (_, ColorSpace::Ycck) => {
+                        let (y, cb, cr) = rgb_2_ycbcr(pixel[0], pixel[1], pixel[2]);
+                        [y, cb, cr, 255 - pixel[3]]
+                    }
+                };
             }
         }
-
-        (y_block, cb_block, cr_block)
     }
 }
 
-fn dump_bytes(
-    block: [i32; 64],
-    prev_dc: i32,
-    mode: Mode,
-    // These two params are to store the state
-    bytes: &mut Vec<u8>,
-    bits: &mut Bits,
-) -> i32 {
-    // DCT -> ZigZag -> Quantization -> Huffman
-    let dct = get_dct(block);
-    let zig_zag = to_zig_zag(dct);
-    let (sequence, dc) = quant(zig_zag, mode);
-    let mut encoded = encode(&sequence, bits, prev_dc, mode);
-    bytes.append(&mut encoded);
-
-    // Return as previous DC value
-    dc
-}
+// A block of one component from the samples of an MCU `width` pixels wide,
+// starting at `(x, y)`, each value averaged over `scale` pixels, e.g. Cb and
+// Cr over h x v pixels of luma
+fn get_block(
+    samples: &[[u8; 4]],
+    width: usize,
+    component: usize,
+    (x, y): (usize, usize),
+    (scale_x, scale_y): (usize, usize),
+) -> [i32; 64] {
+    let mut block = [0; 64];
+    for (position, value) in block.iter_mut().enumerate() {
+        let top = y + position / 8 * scale_y;
+        let left = x + position % 8 * scale_x;
+        for row in samples[top * width..].chunks(width).take(scale_y) {
+            for sample in row[left..left + scale_x].iter() {
+                *value += sample[component] as i32;
+            }
+        }
+    }
 
-fn subsampling(data: [[i32; 64]; 4]) -> [i32; 64] {
-    let mut result = [0; 64];
-    const INDEXES_IN_BLOCK: [usize; 64] = [
-        0,  2,  4,  6,  0,  2,  4,  6,
-        16, 18, 20, 22, 16, 18, 20, 22,
-        32, 34, 36, 38, 32, 34, 36, 38,
-        48, 50, 52, 54, 48, 50, 52, 54,
-        0,  2,  4,  6,  0,  2,  4,  6,
-        16, 18, 20, 22, 16, 18, 20, 22,
-        32, 34, 36, 38, 32, 34, 36, 38,
-        48, 50, 52, 54, 48, 50, 52, 54,
-    ];
-
-    for y in 0..8 {
-        for x in 0..8 {
-            let block_index = 2 * (y / 4) + x / 4;
-            let index_block = INDEXES_IN_BLOCK[y * 8 + x];
-            result[y * 8 + x] = (data[block_index][index_block]
-                + data[block_index][index_block + 1]
-                + data[block_index][index_block + 8]
-                + data[block_index][index_block + 9]
-                + 2)
-                / 4;
+    // Round to nearest
+    let count = (scale_x * scale_y) as i32;
+    if count > 1 {
+        for sample in block.iter_mut() {
+            *sample = (*sample + count / 2) / count;
         }
     }
 
-    result
+    block
+}
+
+// DCT and quantization in one pass -> ZigZag, Huffman coding comes later
+fn quantize_block(block: [i32; 64], forward_dct: &ForwardDct) -> [i32; 64] {
+    to_zig_zag(forward_dct.quantize(block))
 }
 
 impl Serializable for SOS {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        let bits: Bits;
-        // Different header for different color space
-        if self.component == 1 {
-            bytes.extend([0x01, 0x01, 0x00, 0x00, 0x3f, 0x00]);
-            bits = self.process_gray_blocks(&mut bytes);
-        } else {
-            bytes.extend([0x03, 0x01, 0x00, 0x02, 0x11, 0x03, 0x11, 0x00, 0x3f, 0x00]);
-            bits = self.process_rgba_blocks(&mut bytes);
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // Every component with the same DC and AC table, then the whole spectrum
+        writer.write_all(&[self.components.len() as u8])?;
+        for (index, component) in self.components.iter().enumerate() {
+            writer.write_all(&[index as u8 + 1, component.table as u8 * 0x11])?;
         }
+        writer.write_all(&[0x00, 0x3f, 0x00])?;
+
+        // The DC difference is predicted from the previous block of the same component
+        let mut prev_dc = [0; 4];
+        let mut bits = Bits::new(0, 0);
+        let mut block_index = 0;
+        self.for_each_block(|component, sequence| {
+            // Each interval starts on a byte boundary with fresh predictors
+            if let Some(index) = self.restart_before(block_index) {
+                flush(&mut bits, writer)?;
+                writer.write_all(&[0xff, 0xd0 + index])?;
+                prev_dc = [0; 4];
+            }
+            block_index += 1;
+            let (dc_table, ac_table) = &self.huffman_tables[self.components[component].table];
+            let encoded = encode(sequence, &mut bits, prev_dc[component], dc_table, ac_table)?;
+            writer.write_all(&encoded)?;
+            prev_dc[component] = sequence[0];
+            Ok(())
+        })?;
 
         // Deal with last byte
-        let (last_byte, is_complete) = bits.complete();
-        if !is_complete {
-            bytes.push(last_byte);
-        }
+        flush(&mut bits, writer)
+    }
+}
 
-        bytes
+// Pad the pending bits with ones to a whole byte, which may need stuffing too
+pub(super) fn flush<W: Write>(bits: &mut Bits, writer: &mut W) -> Result<(), Error> {
+    let (last_byte, is_complete) = bits.complete();
+    if !is_complete {
+        writer.write_all(&[last_byte])?;
+        if last_byte == 0xff {
+            writer.write_all(&[0x00])?;
+        }
     }
+    *bits = Bits::new(0, 0);
+
+    Ok(())
 }
 
 impl Payload for SOS {
     fn get_length(&self) -> u16 {
-        // The meaningless header's length
-        if self.component == 1 {
-            6
-        } else {
-            10
-        }
+        // 1: component count, 2 per component, 3: spectral selection and approximation
+        4 + 2 * self.components.len() as u16
     }
 }
 
-fn to_zig_zag(array: [f64; 64]) -> [f64; 64] {
-    let mut result = [0.; 64];
+fn to_zig_zag(array: [i32; 64]) -> [i32; 64] {
+    let mut result = [0; 64];
 
     for index in 0..64 {
         result[index] = array[ZIG_ZAG_ORDER[index]];
@@ -474,3 +962,23 @@ fn rgb_2_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
         (0.5000 * r as f64 - 0.4187 * g as f64 - 0.0813 * b as f64 + 128.).round() as u8,
     )
 }
+
+impl Serializable for EncodedScan {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&[self.components.len() as u8])?;
+        for (component, tables) in self.components.iter() {
+            writer.write_all(&[component + 1, *tables])?;
+        }
+        writer.write_all(&[self.start, self.end, self.high << 4 | self.low])?;
+        writer.write_all(&self.data)?;
+
+        Ok(())
+    }
+}
+
+impl Payload for EncodedScan {
+    fn get_length(&self) -> u16 {
+        // The header only, the entropy-coded data follows the segment
+        4 + 2 * self.components.len() as u16
+    }
+}
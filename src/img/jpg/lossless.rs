@@ -0,0 +1,134 @@
+use super::common::{bit, Bits};
+use super::huffman::{generate_huffman_table, optimal_huffman_spec};
+use super::jpeg::flush;
+use super::options::LosslessOptions;
+use super::progressive::EncodedScan;
+use crate::Error;
+
+// Prediction of the sample at `(x, y)` from the samples before it, H.1.2.1
+// The first row after a restart, `top`, only looks left and the first column
+// only looks up
+pub fn predict(
+    plane: &[u16],
+    width: usize,
+    (x, y): (usize, usize),
+    top: usize,
+    predictor: u8,
+    precision: u8,
+) -> i32 {
+    let sample = |x: usize, y: usize| plane[y * width + x] as i32;
+    if y == top {
+        return match x {
+            0 => 1 << (precision - 1),
+            _ => sample(x - 1, y),
+        };
+    }
+    if x == 0 {
+        return sample(x, y - 1);
+    }
+
+    let (a, b, c) = (sample(x - 1, y), sample(x, y - 1), sample(x - 1, y - 1));
+    match predictor {
+        1 => a,
+        2 => b,
+        3 => c,
+        4 => a + b - c,
+        5 => a + ((b - c) >> 1),
+        6 => b + ((a - c) >> 1),
+        _ => (a + b) >> 1,
+    }
+}
+
+// Differences are taken modulo 2^16, so 16 bits always cover them: category
+// 16 only codes 32768, with no extra bits
+fn category(difference: i32) -> u8 {
+    match difference {
+        32768 => 16,
+        _ => bit::get_bit_conut(difference.abs()) as u8,
+    }
+}
+
+// The planes of every component are interleaved one sample of each at a time,
+// `visit` gets the index of a restart marker before the first sample after it
+fn for_each_difference<F>(
+    planes: &[Vec<u16>],
+    width: usize,
+    precision: u8,
+    options: &LosslessOptions,
+    mut visit: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Option<u8>, i32) -> Result<(), Error>,
+{
+    let height = planes[0].len() / width;
+    let interval = options.restart_interval as usize;
+    let mut top = 0;
+    for y in 0..height {
+        let mut restart = None;
+        if interval != 0 && y != 0 && y.is_multiple_of(interval) {
+            restart = Some(((y / interval - 1) % 8) as u8);
+            top = y;
+        }
+        for x in 0..width {
+            for plane in planes.iter() {
+                let prediction = predict(plane, width, (x, y), top, options.predictor, precision);
+                let difference = (plane[y * width + x] as i32 - prediction) & 0xffff;
+                let difference = if difference > 32768 {
+                    difference - 65536
+                } else {
+                    difference
+                };
+                visit(restart.take(), difference)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A single Huffman table is built from the differences of all components
+pub fn encode_lossless(
+    planes: &[Vec<u16>],
+    width: usize,
+    precision: u8,
+    options: &LosslessOptions,
+) -> Result<EncodedScan, Error> {
+    let mut frequencies = [0; 256];
+    for_each_difference(planes, width, precision, options, |_, difference| {
+        frequencies[category(difference) as usize] += 1;
+        Ok(())
+    })?;
+    let spec = optimal_huffman_spec(&frequencies);
+    let table = generate_huffman_table(&spec);
+
+    let mut bits = Bits::new(0, 0);
+    let mut data = Vec::new();
+    for_each_difference(planes, width, precision, options, |restart, difference| {
+        // Each interval starts on a byte boundary
+        if let Some(index) = restart {
+            flush(&mut bits, &mut data)?;
+            data.extend_from_slice(&[0xff, 0xd0 + index]);
+        }
+        let size = category(difference);
+        // Counted in the first pass, so every category has a code
+        bits += table[&size];
+        if size < 16 {
+            bits += Bits::new(size, bit::get_ones_complements(difference) as u32);
+        }
+        data.append(&mut bits.dump());
+        Ok(())
+    })?;
+    flush(&mut bits, &mut data)?;
+
+    Ok(EncodedScan {
+        tables: vec![(0x00, spec)],
+        // Every component takes DC table 0, there are no AC tables
+        components: (0..planes.len() as u8).map(|c| (c, 0x00)).collect(),
+        // The predictor goes where DCT scans put the spectral selection
+        start: options.predictor,
+        end: 0,
+        high: 0,
+        low: 0,
+        data,
+    })
+}
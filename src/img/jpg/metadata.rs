@@ -0,0 +1,194 @@
+use crate::Error;
+
+// Payload of a segment is at most 65535 bytes, minus the length field
+const MAX_PAYLOAD_SIZE: usize = u16::MAX as usize - 2;
+const ICC_SIGNATURE: &[u8; 12] = b"ICC_PROFILE\0";
+// Profile bytes per APP2 segment, after the signature and the sequence numbers
+const ICC_CHUNK_SIZE: usize = MAX_PAYLOAD_SIZE - ICC_SIGNATURE.len() - 2;
+
+/// Pixel density written in the JFIF header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Density {
+    pub x: u16,
+    pub y: u16,
+    pub unit: DensityUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DensityUnit {
+    /// Only the aspect ratio is known
+    None = 0,
+    Inch = 1,
+    Centimeter = 2,
+}
+
+impl Density {
+    /// The same dots per inch along x and y.
+    pub fn from_dpi(dpi: u16) -> Self {
+        Density {
+            x: dpi,
+            y: dpi,
+            unit: DensityUnit::Inch,
+        }
+    }
+}
+
+impl Default for Density {
+    // Square pixels of unknown size
+    fn default() -> Self {
+        Density {
+            x: 1,
+            y: 1,
+            unit: DensityUnit::None,
+        }
+    }
+}
+
+/// Optional segment carrying metadata, written after the JFIF header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataSegment {
+    /// `APP1` Exif with a few tags of the first IFD
+    Exif {
+        /// From 1 (upright) to 8, how viewers should rotate and flip the image
+        orientation: Option<u16>,
+        /// ASCII name of the program that made the image
+        software: Option<String>,
+        /// Local time of the last change, as `YYYY:MM:DD HH:MM:SS`
+        date_time: Option<String>,
+    },
+    /// `APP2` ICC profile, split across as many segments as it needs
+    IccProfile(Vec<u8>),
+    /// `COM` text
+    Comment(String),
+}
+
+impl MetadataSegment {
+    pub(crate) fn marker(&self) -> [u8; 2] {
+        match self {
+            MetadataSegment::Exif { .. } => [0xff, 0xe1],
+            MetadataSegment::IccProfile(_) => [0xff, 0xe2],
+            MetadataSegment::Comment(_) => [0xff, 0xfe],
+        }
+    }
+
+    // Payload of each segment, after checking the fields against the specs
+    pub(crate) fn encode(&self) -> Result<Vec<Vec<u8>>, Error> {
+        let payloads = match self {
+            MetadataSegment::Exif {
+                orientation,
+                software,
+                date_time,
+            } => vec![encode_exif(*orientation, software, date_time)?],
+            MetadataSegment::IccProfile(profile) => {
+                // Sequence numbers are a byte and start from 1
+                let count = profile.len().div_ceil(ICC_CHUNK_SIZE);
+                if count == 0 || count > u8::MAX as usize {
+                    return Err(Error::FormatLimit(format!(
+                        "ICC profile of {} bytes in JPEG",
+                        profile.len()
+                    )));
+                }
+                profile
+                    .chunks(ICC_CHUNK_SIZE)
+                    .enumerate()
+                    .map(|(index, chunk)| {
+                        let mut data = ICC_SIGNATURE.to_vec();
+                        data.extend_from_slice(&[index as u8 + 1, count as u8]);
+                        data.extend_from_slice(chunk);
+                        data
+                    })
+                    .collect()
+            }
+            MetadataSegment::Comment(text) => vec![text.as_bytes().to_vec()],
+        };
+
+        if let Some(data) = payloads.iter().find(|data| data.len() > MAX_PAYLOAD_SIZE) {
+            return Err(Error::FormatLimit(format!(
+                "JPEG segment of {} bytes",
+                data.len()
+            )));
+        }
+
+        Ok(payloads)
+    }
+}
+
+// "Exif" header, then a big-endian TIFF structure with a single IFD
+fn encode_exif(
+    orientation: Option<u16>,
+    software: &Option<String>,
+    date_time: &Option<String>,
+) -> Result<Vec<u8>, Error> {
+    // Tag, type and value, sorted by tag as TIFF requires
+    let mut entries: Vec<(u16, u16, Vec<u8>)> = Vec::new();
+    if let Some(orientation) = orientation {
+        if !(1..=8).contains(&orientation) {
+            return Err(Error::Unsupported(format!(
+                "Exif orientation {}",
+                orientation
+            )));
+        }
+        entries.push((0x0112, 3, orientation.to_be_bytes().to_vec()));
+    }
+    if let Some(software) = software {
+        entries.push((0x0131, 2, encode_ascii(software)?));
+    }
+    if let Some(date_time) = date_time {
+        let bytes = date_time.as_bytes();
+        let valid = bytes.len() == 19
+            && bytes.iter().enumerate().all(|(index, &byte)| match index {
+                4 | 7 => byte == b':',
+                10 => byte == b' ',
+                13 | 16 => byte == b':',
+                _ => byte.is_ascii_digit(),
+            });
+        if !valid {
+            return Err(Error::Unsupported(format!("Exif date {:?}", date_time)));
+        }
+        entries.push((0x0132, 2, encode_ascii(date_time)?));
+    }
+
+    let mut data = b"Exif\0\0".to_vec();
+    // Byte order, magic number and offset of the IFD, relative to the TIFF header
+    data.extend_from_slice(&[b'M', b'M', 0x00, 0x2a, 0x00, 0x00, 0x00, 0x08]);
+    data.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    // Values longer than 4 bytes follow the IFD and its next IFD offset
+    let mut offset = 8 + 2 + entries.len() * 12 + 4;
+    let mut values = Vec::new();
+    for (tag, kind, value) in entries.iter() {
+        // Bytes of ASCII, shorts of SHORT
+        let count = if *kind == 2 { value.len() } else { 1 };
+        data.extend_from_slice(&tag.to_be_bytes());
+        data.extend_from_slice(&kind.to_be_bytes());
+        data.extend_from_slice(&(count as u32).to_be_bytes());
+        if value.len() <= 4 {
+            let mut inline = [0; 4];
+            inline[..value.len()].copy_from_slice(value);
+            data.extend_from_slice(&inline);
+        } else {
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            values.extend_from_slice(value);
+            // Values start on a word boundary
+            if value.len() % 2 == 1 {
+                values.push(0);
+            }
+            offset = 8 + 2 + entries.len() * 12 + 4 + values.len();
+        }
+    }
+    // No next IFD
+    data.extend_from_slice(&[0; 4]);
+    data.extend(values);
+
+    Ok(data)
+}
+
+// Null terminated ASCII
+fn encode_ascii(text: &str) -> Result<Vec<u8>, Error> {
+    if !text.bytes().all(|byte| byte.is_ascii() && byte != 0) {
+        return Err(Error::Unsupported(format!("Exif text {:?}", text)));
+    }
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+
+    Ok(bytes)
+}
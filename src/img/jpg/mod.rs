@@ -1,54 +1,90 @@
 mod common;
 mod dct;
+mod decoder;
 mod quant;
 mod huffman;
 mod rle;
 mod jpeg;
+mod lossless;
+mod metadata;
+mod options;
+mod progressive;
 
-use super::{Image, Serializable};
-use jpeg::JPEG;
+use super::{DynamicImage, Image, ImageBuffer, Serializable};
+use crate::color::{Luma, Pixel, Rgb, Rgba};
+use crate::Error;
 
-use std::error::Error;
+pub use jpeg::JPEG;
+pub use metadata::{Density, DensityUnit, MetadataSegment};
+pub use options::{
+    AlphaMode, Background, ColorSpace, DctMethod, JpegOptions, LosslessOptions, Subsampling,
+};
+pub use progressive::ProgressiveScan;
+
+use std::fs::File;
+use std::io::{BufReader, Read};
 
 pub fn save_jpg_gray<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[u8; WIDTH]; HEIGHT],
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().collect::<Vec<_>>();
-    JPEG::new(WIDTH as u16, HEIGHT as u16, 1, &data).dump(path)
+) -> Result<(), Error> {
+    save_jpg_buffer(path, &ImageBuffer::from(data).map(|&l| Luma([l])))
 }
 
 pub fn save_jpg_rgb<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[[u8; 3]; WIDTH]; HEIGHT],
-) -> Result<(), Box<dyn Error>> {
-    // Convert RGB to RGBA
-    let data = to_rgba(data).iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    JPEG::new(WIDTH as u16, HEIGHT as u16, 3, &data).dump(path)
+) -> Result<(), Error> {
+    save_jpg_buffer(path, &ImageBuffer::from(data).map(|&rgb| Rgb(rgb)))
 }
 
 // JPEG does not support alpha channel
-// The value of this channel will be automaticlly ignored
+// Transparent pixels are blended over a white background
 pub fn save_jpg_rgba<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[[u8; 4]; WIDTH]; HEIGHT],
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    JPEG::new(WIDTH as u16, HEIGHT as u16, 3, &data).dump(path)
+) -> Result<(), Error> {
+    save_jpg_buffer(path, &ImageBuffer::from(data).map(|&rgba| Rgba(rgba)))
 }
 
-fn to_rgba<const WIDTH: usize, const HEIGHT: usize>(
-    data: [[[u8; 3]; WIDTH]; HEIGHT],
-) -> [[[u8; 4]; WIDTH]; HEIGHT] {
-    let mut converted = [[[0; 4]; WIDTH]; HEIGHT];
-    for (y, row) in data.iter().enumerate() {
-        for (x, pixel) in row.iter().enumerate() {
-            converted[y][x][0] = pixel[0];
-            converted[y][x][1] = pixel[1];
-            converted[y][x][2] = pixel[2];
-            converted[y][x][3] = 255;
-        }
-    }
-
-    converted
+// Grayscale pixels are saved with one component, others with three
+pub fn save_jpg_buffer<P: Pixel<Subpixel = u8>>(
+    path: &str,
+    image: &ImageBuffer<P>,
+) -> Result<(), Error> {
+    JPEG::from_buffer(image)?.dump(path)
+}
+
+pub fn save_jpg_buffer_with_options<P: Pixel<Subpixel = u8>>(
+    path: &str,
+    image: &ImageBuffer<P>,
+    options: &JpegOptions,
+) -> Result<(), Error> {
+    JPEG::from_buffer_with_options(image, options)?.dump(path)
+}
+
+/// Save gray or RGB samples without loss, in 2 to 16 bits per sample.
+pub fn save_jpg_lossless<P: Pixel>(
+    path: &str,
+    image: &ImageBuffer<P>,
+    options: &LosslessOptions,
+) -> Result<(), Error> {
+    JPEG::from_buffer_lossless(image, options)?.dump(path)
+}
+
+/// Decode a baseline or progressive JPEG file with one (gray), three
+/// (YCbCr) or four (CMYK or YCCK) components, or a lossless one.
+///
+/// Gray files give `Luma8`, color files are upsampled and converted to `Rgb8`,
+/// inks are printed on white as `Cmyk::to_rgb` does. Lossless files give gray
+/// or RGB samples as they are, in 16-bit buffers above 8 bits of precision.
+pub fn load_jpg(path: &str) -> Result<DynamicImage, Error> {
+    decode_jpg(&mut BufReader::new(File::open(path)?))
+}
+
+/// Decode a JPEG file from any reader.
+pub fn decode_jpg<R: Read>(reader: &mut R) -> Result<DynamicImage, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decoder::decode(&bytes)
 }
@@ -0,0 +1,271 @@
+use super::metadata::{Density, MetadataSegment};
+use super::progressive::ProgressiveScan;
+use super::quant::{
+    from_natural_order, scale_quant_table, QuantTable, CHROMINANCE_QUANT_TABLE,
+    LUMINANCE_QUANT_TABLE,
+};
+use crate::color::Rgb;
+use crate::Error;
+
+/// Resolution of the chroma components relative to luma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Full chroma resolution, sharpest colored edges
+    S444,
+    /// Half the columns
+    S422,
+    /// Half the columns and half the rows
+    S420,
+    /// Half the rows
+    S440,
+}
+
+impl Subsampling {
+    // Horizontal and vertical sampling factors of luma, chroma is always 1x1
+    pub(crate) fn factors(self) -> (usize, usize) {
+        match self {
+            Subsampling::S444 => (1, 1),
+            Subsampling::S422 => (2, 1),
+            Subsampling::S420 => (2, 2),
+            Subsampling::S440 => (1, 2),
+        }
+    }
+}
+
+/// Arithmetic of the forward DCT, both are fast separable transforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DctMethod {
+    /// Single precision floating point, the closest to the exact transform
+    Float,
+    /// Fixed point with 8 extra bits between the passes and 64-bit products,
+    /// so the output only depends on integer arithmetic
+    Integer,
+}
+
+/// Components of the encoded image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// One component for gray images and YCbCr for color ones, as JFIF requires
+    YCbCr,
+    /// Four components of ink, marked with Adobe transform 0 and stored
+    /// inverted like Adobe's applications do
+    Cmyk,
+    /// Cyan, magenta and yellow turned into YCbCr, which compresses better,
+    /// and black as is, marked with Adobe transform 2
+    Ycck,
+}
+
+/// What transparent pixels are blended over, as JPEG has no alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    /// A single color
+    Color(Rgb<u8>),
+    /// Squares of `size` pixels alternating between two colors, starting with
+    /// the first at the top left, to preview where the image is transparent
+    Checkerboard { size: u32, colors: [Rgb<u8>; 2] },
+}
+
+impl Background {
+    pub(crate) fn color_at(&self, x: usize, y: usize) -> [u8; 3] {
+        match *self {
+            Background::Color(color) => color.0,
+            Background::Checkerboard { size, colors } => {
+                let size = size as usize;
+                colors[(x / size + y / size) % 2].0
+            }
+        }
+    }
+}
+
+/// How the color channels of a pixel with alpha are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Colors are independent of alpha, as in PNG
+    Straight,
+    /// Colors are already multiplied by alpha
+    Premultiplied,
+}
+
+impl AlphaMode {
+    // Color of `pixel` over `background`, rounded to the nearest
+    pub(crate) fn blend(self, pixel: [u8; 4], background: [u8; 3]) -> [u8; 3] {
+        let alpha = pixel[3] as u32;
+        let mut result = [0; 3];
+        for (index, value) in result.iter_mut().enumerate() {
+            let color = pixel[index] as u32;
+            let behind = background[index] as u32 * (255 - alpha);
+            *value = match self {
+                AlphaMode::Straight => (color * alpha + behind + 127) / 255,
+                // A color larger than its alpha is invalid, clamp it like a straight one
+                AlphaMode::Premultiplied => (color + (behind + 127) / 255).min(255),
+            } as u8;
+        }
+
+        result
+    }
+}
+
+/// Settings of the JPEG encoder, start from `JpegOptions::default()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JpegOptions {
+    /// From 1 (smallest) to 100 (best), scales the tables of Annex K like libjpeg
+    pub quality: u8,
+    /// Luminance and chrominance tables in row-major order, used as they are
+    /// instead of the scaled ones
+    pub quant_tables: Option<[[u8; 64]; 2]>,
+    /// Chroma resolution of color images, gray images have no chroma
+    pub subsampling: Subsampling,
+    /// Build Huffman tables from the statistics of the image in an extra pass,
+    /// usually a few percent smaller than the standard tables
+    pub optimize_huffman: bool,
+    /// How the encoder transforms each 8x8 block
+    pub dct_method: DctMethod,
+    /// MCUs between `RST` markers, so a damaged file recovers at the next
+    /// marker and intervals can be decoded in parallel, 0 for none
+    pub restart_interval: u16,
+    /// Write a progressive file (SOF2), which viewers show coarse to fine as it loads
+    /// Its Huffman tables are always optimized
+    pub progressive: bool,
+    /// Scans of a progressive file, `None` for libjpeg's simple progression
+    pub scan_script: Option<Vec<ProgressiveScan>>,
+    /// Pixel density in the JFIF header, which every file starts with
+    pub density: Density,
+    /// Metadata segments written after the JFIF header, in this order
+    pub segments: Vec<MetadataSegment>,
+    /// `Cmyk` and `Ycck` separate colors into inks and write an Adobe segment
+    /// instead of the JFIF header, so `density` is left out
+    pub color_space: ColorSpace,
+    /// From 0 to 100, the percentage of the gray shared by cyan, magenta and
+    /// yellow that black ink prints instead, see `Cmyk::from_rgb`
+    pub under_color_removal: u8,
+    /// Where images with alpha are not opaque, they are blended over this
+    pub background: Background,
+    /// Whether the colors of images with alpha are premultiplied
+    pub alpha_mode: AlphaMode,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            quant_tables: None,
+            subsampling: Subsampling::S420,
+            optimize_huffman: false,
+            dct_method: DctMethod::Float,
+            restart_interval: 0,
+            progressive: false,
+            scan_script: None,
+            density: Density::default(),
+            segments: Vec::new(),
+            color_space: ColorSpace::YCbCr,
+            under_color_removal: 100,
+            background: Background::Color(Rgb([255, 255, 255])),
+            alpha_mode: AlphaMode::Straight,
+        }
+    }
+}
+
+impl JpegOptions {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(Error::Unsupported(format!("JPEG quality {}", self.quality)));
+        }
+        if let Some(tables) = &self.quant_tables {
+            if tables.iter().flatten().any(|&value| value == 0) {
+                return Err(Error::Unsupported(
+                    "zero in JPEG quantization table".to_string(),
+                ));
+            }
+        }
+        if self.under_color_removal > 100 {
+            return Err(Error::Unsupported(format!(
+                "under color removal of {}%",
+                self.under_color_removal
+            )));
+        }
+        if let Background::Checkerboard { size: 0, .. } = self.background {
+            return Err(Error::Unsupported(
+                "JPEG background checkerboard of size 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Composite RGBA samples of an image `width` pixels wide over the
+    // background, leaving them opaque
+    pub(crate) fn flatten(&self, width: usize, data: &[u8]) -> Vec<u8> {
+        let mut result = data.to_vec();
+        for (index, pixel) in result.chunks_exact_mut(4).enumerate() {
+            if pixel[3] == u8::MAX {
+                continue;
+            }
+            let background = self.background.color_at(index % width, index / width);
+            let color = self
+                .alpha_mode
+                .blend([pixel[0], pixel[1], pixel[2], pixel[3]], background);
+            pixel.copy_from_slice(&[color[0], color[1], color[2], u8::MAX]);
+        }
+
+        result
+    }
+
+    // Luminance and chrominance tables in zig-zag order
+    pub(crate) fn quant_tables(&self) -> [QuantTable; 2] {
+        match &self.quant_tables {
+            Some([luminance, chrominance]) => [
+                from_natural_order(luminance),
+                from_natural_order(chrominance),
+            ],
+            None => [
+                scale_quant_table(&LUMINANCE_QUANT_TABLE, self.quality),
+                scale_quant_table(&CHROMINANCE_QUANT_TABLE, self.quality),
+            ],
+        }
+    }
+}
+
+/// Settings of the lossless (SOF3) encoder, start from `LosslessOptions::default()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LosslessOptions {
+    /// From 1 to 7, how a sample is predicted from its neighbours on the left
+    /// (a), above (b) and above left (c): a, b, c, a + b - c, a + (b - c) / 2,
+    /// b + (a - c) / 2 or (a + b) / 2
+    pub predictor: u8,
+    /// Bits per sample from 2 to 16, `None` for the bit depth of the pixel type
+    /// Every sample must fit, e.g. 12 for the data of a 12-bit camera in `u16`
+    pub precision: Option<u8>,
+    /// Rows between `RST` markers, 0 for none
+    pub restart_interval: u16,
+}
+
+impl Default for LosslessOptions {
+    fn default() -> Self {
+        Self {
+            predictor: 1,
+            precision: None,
+            restart_interval: 0,
+        }
+    }
+}
+
+impl LosslessOptions {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !(1..=7).contains(&self.predictor) {
+            return Err(Error::Unsupported(format!(
+                "lossless JPEG predictor {}",
+                self.predictor
+            )));
+        }
+        if let Some(precision) = self.precision {
+            if !(2..=16).contains(&precision) {
+                return Err(Error::Unsupported(format!(
+                    "{}-bit lossless JPEG",
+                    precision
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
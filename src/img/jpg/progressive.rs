@@ -0,0 +1,466 @@
+use super::common::{bit, Bits};
+use super::huffman::{generate_huffman_table, optimal_huffman_spec, HuffmanSpec, HuffmanTable};
+use super::jpeg::flush;
+use crate::Error;
+
+// Longest EOB run the EOB14 symbol can code
+const MAX_EOB_RUN: u32 = 0x7fff;
+// Correction bits held back for an EOB run before it is cut short, as libjpeg does
+const MAX_CORRECTION_BITS: usize = 1000 - 63;
+
+/// One scan of a progressive JPEG, a file is coded by a list of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressiveScan {
+    /// Components in the scan, 0 for Y or gray, 1 for Cb and 2 for Cr,
+    /// or C, M, Y and K, or Y, Cb, Cr and K for four component files
+    /// AC scans take exactly one component
+    pub components: Vec<u8>,
+    /// First and last coefficient in zig-zag order, both 0 for DC scans
+    pub start: u8,
+    pub end: u8,
+    /// Bit position the previous scan of these coefficients stopped at, 0 for the first scan
+    pub high: u8,
+    /// Bits below this position are left for later scans, each one refining a single bit
+    pub low: u8,
+}
+
+impl ProgressiveScan {
+    fn new(components: &[u8], start: u8, end: u8, high: u8, low: u8) -> Self {
+        ProgressiveScan {
+            components: components.to_vec(),
+            start,
+            end,
+            high,
+            low,
+        }
+    }
+
+    /// Script of libjpeg's `jpeg_simple_progression`, for 1, 3 or 4 components.
+    pub fn standard_script(components: u8) -> Vec<Self> {
+        if components != 3 {
+            // The same AC scans for every component, DC of all of them at once
+            let all = (0..components).collect::<Vec<_>>();
+            let each = |start, end, high, low| {
+                (0..components).map(move |component| Self::new(&[component], start, end, high, low))
+            };
+            let mut script = vec![Self::new(&all, 0, 0, 0, 1)];
+            script.extend(each(1, 5, 0, 2));
+            script.extend(each(6, 63, 0, 2));
+            script.extend(each(1, 63, 2, 1));
+            script.push(Self::new(&all, 0, 0, 1, 0));
+            script.extend(each(1, 63, 1, 0));
+            return script;
+        }
+
+        vec![
+            // Coarse DC of all components first, then low luma frequencies
+            Self::new(&[0, 1, 2], 0, 0, 0, 1),
+            Self::new(&[0], 1, 5, 0, 2),
+            Self::new(&[2], 1, 63, 0, 1),
+            Self::new(&[1], 1, 63, 0, 1),
+            Self::new(&[0], 6, 63, 0, 2),
+            Self::new(&[0], 1, 63, 2, 1),
+            Self::new(&[0, 1, 2], 0, 0, 1, 0),
+            Self::new(&[2], 1, 63, 1, 0),
+            Self::new(&[1], 1, 63, 1, 0),
+            Self::new(&[0], 1, 63, 1, 0),
+        ]
+    }
+}
+
+// Scans must follow G.1.1.1, and together code every bit of every coefficient
+pub fn validate_script(script: &[ProgressiveScan], components: u8) -> Result<(), Error> {
+    let invalid = |scan: &ProgressiveScan| {
+        Err(Error::Unsupported(format!(
+            "progressive JPEG scan {:?}",
+            scan
+        )))
+    };
+    // Bit position each coefficient is coded down to so far
+    let mut positions = vec![[None; 64]; components as usize];
+    for scan in script.iter() {
+        let ordered = scan.components.windows(2).all(|pair| pair[0] < pair[1]);
+        if scan.components.is_empty()
+            || !ordered
+            || scan
+                .components
+                .iter()
+                .any(|&component| component >= components)
+            || scan.start > scan.end
+            || scan.end > 63
+            || (scan.start == 0) != (scan.end == 0)
+            || (scan.start > 0 && scan.components.len() != 1)
+            || scan.low > 13
+            || (scan.high != 0 && scan.high != scan.low + 1)
+        {
+            return invalid(scan);
+        }
+
+        for &component in scan.components.iter() {
+            let positions = &mut positions[component as usize];
+            // AC coefficients of a component wait for its first DC scan
+            if scan.start > 0 && positions[0].is_none() {
+                return invalid(scan);
+            }
+            for position in positions[scan.start as usize..=scan.end as usize].iter_mut() {
+                let expected = position.unwrap_or(0);
+                if position.is_some() == (scan.high == 0) || scan.high != expected {
+                    return invalid(scan);
+                }
+                *position = Some(scan.low);
+            }
+        }
+    }
+
+    if positions
+        .iter()
+        .flatten()
+        .any(|&position| position != Some(0))
+    {
+        return Err(Error::Unsupported(
+            "progressive JPEG script leaves coefficients unfinished".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Quantized blocks of a component in zig-zag order, row by row over whole MCUs
+pub struct ComponentBlocks {
+    pub blocks: Vec<[i32; 64]>,
+    // Blocks per row of the MCU grid
+    pub stride: usize,
+    // Blocks covering the component itself, which scans of it alone go through
+    pub width: usize,
+    pub height: usize,
+    // Sampling factors
+    pub h: usize,
+    pub v: usize,
+    // Huffman tables, 0 for luminance and 1 for chrominance
+    pub table: usize,
+}
+
+// A scan ready to be written
+pub struct EncodedScan {
+    // Class and index of each table, then its spec
+    pub tables: Vec<(u8, HuffmanSpec)>,
+    // Component index and table selectors
+    pub components: Vec<(u8, u8)>,
+    pub start: u8,
+    pub end: u8,
+    pub high: u8,
+    pub low: u8,
+    pub data: Vec<u8>,
+}
+
+// Every scan gets Huffman tables built for it in a first pass, as the
+// standard tables have no codes for EOB runs
+pub fn encode_scans(
+    script: &[ProgressiveScan],
+    components: &[ComponentBlocks],
+    restart_interval: u16,
+) -> Result<Vec<EncodedScan>, Error> {
+    let mut result = Vec::with_capacity(script.len());
+    for scan in script.iter() {
+        let mcus = scan_mcus(scan, components);
+        let mut counter = EntropyWriter::new(None);
+        encode_scan(scan, components, &mcus, restart_interval, &mut counter)?;
+
+        // Luminance and chrominance tables, only for the classes in the scan
+        let is_dc = scan.start == 0;
+        let mut tables = Vec::new();
+        if !is_dc || scan.high == 0 {
+            for class in 0..2 {
+                if scan
+                    .components
+                    .iter()
+                    .any(|&c| components[c as usize].table == class)
+                {
+                    let spec = optimal_huffman_spec(&counter.frequencies[class]);
+                    tables.push(((!is_dc as u8) << 4 | class as u8, spec));
+                }
+            }
+        }
+        let mut codes = [HuffmanTable::new(), HuffmanTable::new()];
+        for (id, spec) in tables.iter() {
+            codes[(id & 0x0f) as usize] = generate_huffman_table(spec);
+        }
+
+        let mut writer = EntropyWriter::new(Some(codes));
+        encode_scan(scan, components, &mcus, restart_interval, &mut writer)?;
+        result.push(EncodedScan {
+            tables,
+            components: scan
+                .components
+                .iter()
+                .map(|&c| {
+                    // DC table in the high nibble, AC table in the low one
+                    let class = components[c as usize].table as u8;
+                    (c, if is_dc { class << 4 } else { class })
+                })
+                .collect(),
+            start: scan.start,
+            end: scan.end,
+            high: scan.high,
+            low: scan.low,
+            data: writer.data,
+        });
+    }
+
+    Ok(result)
+}
+
+// Component and block index of each block, grouped by MCU
+// A scan of a single component is not interleaved, so each of its blocks is an MCU
+fn scan_mcus(scan: &ProgressiveScan, components: &[ComponentBlocks]) -> Vec<Vec<(usize, usize)>> {
+    if let [component] = scan.components[..] {
+        let blocks = &components[component as usize];
+        return (0..blocks.height)
+            .flat_map(|y| {
+                (0..blocks.width).map(move |x| vec![(component as usize, y * blocks.stride + x)])
+            })
+            .collect();
+    }
+
+    let first = &components[0];
+    let (mcus_x, mcus_y) = (
+        first.stride / first.h,
+        first.blocks.len() / first.stride / first.v,
+    );
+    let mut mcus = Vec::with_capacity(mcus_x * mcus_y);
+    for mcu_y in 0..mcus_y {
+        for mcu_x in 0..mcus_x {
+            let mut mcu = Vec::new();
+            for &component in scan.components.iter() {
+                let blocks = &components[component as usize];
+                for y in 0..blocks.v {
+                    for x in 0..blocks.h {
+                        let index = (mcu_y * blocks.v + y) * blocks.stride + mcu_x * blocks.h + x;
+                        mcu.push((component as usize, index));
+                    }
+                }
+            }
+            mcus.push(mcu);
+        }
+    }
+    mcus
+}
+
+fn encode_scan(
+    scan: &ProgressiveScan,
+    components: &[ComponentBlocks],
+    mcus: &[Vec<(usize, usize)>],
+    restart_interval: u16,
+    writer: &mut EntropyWriter,
+) -> Result<(), Error> {
+    // AC scans have a single component, so a single class for EOB runs
+    let class = components[scan.components[0] as usize].table;
+    let interval = restart_interval as usize;
+    let mut predictors = [0; 4];
+    for (index, mcu) in mcus.iter().enumerate() {
+        if interval != 0 && index != 0 && index.is_multiple_of(interval) {
+            writer.restart(((index / interval - 1) % 8) as u8, class)?;
+            predictors = [0; 4];
+        }
+        for &(component, block) in mcu.iter() {
+            let coefficients = &components[component].blocks[block];
+            let class = components[component].table;
+            match (scan.start, scan.high) {
+                (0, 0) => {
+                    let value = coefficients[0] >> scan.low;
+                    let difference = value - predictors[component];
+                    predictors[component] = value;
+                    let size = bit::get_bit_conut(difference.abs()) as u8;
+                    writer.symbol(class, size)?;
+                    writer.bits(size, bit::get_ones_complements(difference) as u32);
+                }
+                (0, _) => writer.bits(1, (coefficients[0] >> scan.low) as u32 & 1),
+                (_, 0) => encode_ac_first(scan, coefficients, class, writer)?,
+                _ => encode_ac_refine(scan, coefficients, class, writer)?,
+            }
+        }
+    }
+
+    writer.finish(class)
+}
+
+fn encode_ac_first(
+    scan: &ProgressiveScan,
+    coefficients: &[i32; 64],
+    class: usize,
+    writer: &mut EntropyWriter,
+) -> Result<(), Error> {
+    let mut run = 0;
+    for &coefficient in coefficients[scan.start as usize..=scan.end as usize].iter() {
+        let magnitude = coefficient.abs() >> scan.low;
+        if magnitude == 0 {
+            run += 1;
+            continue;
+        }
+
+        writer.flush_eob_run(class)?;
+        while run > 15 {
+            writer.symbol(class, 0xf0)?;
+            run -= 16;
+        }
+        let size = bit::get_bit_conut(magnitude) as u8;
+        writer.symbol(class, run << 4 | size)?;
+        let value = if coefficient < 0 {
+            !magnitude
+        } else {
+            magnitude
+        };
+        writer.bits(size, value as u32);
+        run = 0;
+    }
+
+    // Trailing zeros join the EOB run
+    if run > 0 {
+        writer.eob_run += 1;
+        if writer.eob_run == MAX_EOB_RUN {
+            writer.flush_eob_run(class)?;
+        }
+    }
+
+    Ok(())
+}
+
+// G.1.2.3: coefficients turning nonzero are coded like in the first scan,
+// those already nonzero get a correction bit after the next symbol
+fn encode_ac_refine(
+    scan: &ProgressiveScan,
+    coefficients: &[i32; 64],
+    class: usize,
+    writer: &mut EntropyWriter,
+) -> Result<(), Error> {
+    let band = scan.start as usize..=scan.end as usize;
+    let magnitudes = coefficients.map(|coefficient| coefficient.abs() >> scan.low);
+    // Past the last coefficient turning nonzero, zeros go into the EOB run
+    let last_new = band.clone().rev().find(|&k| magnitudes[k] == 1);
+
+    let mut run = 0;
+    let mut corrections = Vec::new();
+    for k in band {
+        let magnitude = magnitudes[k];
+        if magnitude == 0 {
+            run += 1;
+            continue;
+        }
+
+        while run > 15 && last_new.is_some_and(|last| k <= last) {
+            writer.flush_eob_run(class)?;
+            writer.symbol(class, 0xf0)?;
+            run -= 16;
+            for bit in corrections.drain(..) {
+                writer.bits(1, bit);
+            }
+        }
+        if magnitude > 1 {
+            corrections.push(magnitude as u32 & 1);
+            continue;
+        }
+
+        writer.flush_eob_run(class)?;
+        writer.symbol(class, run << 4 | 1)?;
+        writer.bits(1, (coefficients[k] >= 0) as u32);
+        for bit in corrections.drain(..) {
+            writer.bits(1, bit);
+        }
+        run = 0;
+    }
+
+    if run > 0 || !corrections.is_empty() {
+        writer.eob_run += 1;
+        writer.corrections.append(&mut corrections);
+        if writer.eob_run == MAX_EOB_RUN || writer.corrections.len() > MAX_CORRECTION_BITS {
+            writer.flush_eob_run(class)?;
+        }
+    }
+
+    Ok(())
+}
+
+struct EntropyWriter {
+    // Codes of luminance and chrominance, `None` in the pass counting symbols
+    tables: Option<[HuffmanTable; 2]>,
+    frequencies: [[u32; 256]; 2],
+    bits: Bits,
+    data: Vec<u8>,
+    // Blocks with nothing left to code since the last symbol, and the
+    // correction bits they still owe in refinement scans
+    eob_run: u32,
+    corrections: Vec<u32>,
+}
+
+impl EntropyWriter {
+    fn new(tables: Option<[HuffmanTable; 2]>) -> Self {
+        EntropyWriter {
+            tables,
+            frequencies: [[0; 256]; 2],
+            bits: Bits::new(0, 0),
+            data: Vec::new(),
+            eob_run: 0,
+            corrections: Vec::new(),
+        }
+    }
+
+    fn symbol(&mut self, class: usize, symbol: u8) -> Result<(), Error> {
+        match &self.tables {
+            None => self.frequencies[class][symbol as usize] += 1,
+            Some(tables) => {
+                let codeword = tables[class].get(&symbol).ok_or_else(|| {
+                    Error::FormatLimit(format!(
+                        "JPEG symbol {:#04x} can not be Huffman coded",
+                        symbol
+                    ))
+                })?;
+                self.bits += *codeword;
+                self.data.append(&mut self.bits.dump());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bits(&mut self, length: u8, value: u32) {
+        if self.tables.is_some() {
+            self.bits += Bits::new(length, value);
+            self.data.append(&mut self.bits.dump());
+        }
+    }
+
+    // EOBn symbol with the low n bits of the run, then the held back corrections
+    fn flush_eob_run(&mut self, class: usize) -> Result<(), Error> {
+        if self.eob_run == 0 {
+            return Ok(());
+        }
+
+        let size = bit::get_bit_conut(self.eob_run as i32) as u8 - 1;
+        self.symbol(class, size << 4)?;
+        self.bits(size, self.eob_run);
+        self.eob_run = 0;
+        for bit in std::mem::take(&mut self.corrections) {
+            self.bits(1, bit);
+        }
+
+        Ok(())
+    }
+
+    fn restart(&mut self, index: u8, class: usize) -> Result<(), Error> {
+        self.finish(class)?;
+        if self.tables.is_some() {
+            self.data.extend_from_slice(&[0xff, 0xd0 + index]);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, class: usize) -> Result<(), Error> {
+        self.flush_eob_run(class)?;
+        if self.tables.is_some() {
+            flush(&mut self.bits, &mut self.data)?;
+        }
+
+        Ok(())
+    }
+}
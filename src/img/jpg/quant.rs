@@ -1,73 +1,59 @@
-use lazy_static::lazy_static;
-
-use super::jpeg::Mode;
+use super::jpeg::ZIG_ZAG_ORDER;
 
 const QUANT_TABLE_WIDTH: usize = 8;
 const QUANT_TABLE_HEIGHT: usize = 8;
 const QUANT_TABLE_SIZE: usize = QUANT_TABLE_WIDTH * QUANT_TABLE_HEIGHT;
 
-type QuantTable = [[u8; QUANT_TABLE_WIDTH]; QUANT_TABLE_HEIGHT];
-
-lazy_static! {
-    pub static ref LUMINANCE_QUANT_TABLE: QuantTable = {
-        let mut qtable = [
-            [16, 11, 12, 14, 12, 10, 16, 14],
-            [13, 14, 18, 17, 16, 19, 24, 40],
-            [26, 24, 22, 22, 24, 49, 35, 37],
-            [29, 40, 58, 51, 61, 60, 57, 51],
-            [56, 55, 64, 72, 92, 78, 64, 68],
-            [87, 69, 55, 56, 80, 109, 81, 87],
-            [95, 98, 103, 104, 103, 62, 77, 113],
-            [121, 112, 100, 120, 92, 101, 103, 99],
-        ];
-        prescale_quant_table(&mut qtable);
-
-        qtable
+pub type QuantTable = [[u8; QUANT_TABLE_WIDTH]; QUANT_TABLE_HEIGHT];
+
+// Tables of Annex K in zig-zag order, for quality 50
+pub const LUMINANCE_QUANT_TABLE: QuantTable = [
+    [16, 11, 12, 14, 12, 10, 16, 14],
+    [13, 14, 18, 17, 16, 19, 24, 40],
+    [26, 24, 22, 22, 24, 49, 35, 37],
+    [29, 40, 58, 51, 61, 60, 57, 51],
+    [56, 55, 64, 72, 92, 78, 64, 68],
+    [87, 69, 55, 56, 80, 109, 81, 87],
+    [95, 98, 103, 104, 103, 62, 77, 113],
+    [121, 112, 100, 120, 92, 101, 103, 99],
+];
+pub const CHROMINANCE_QUANT_TABLE: QuantTable = [
+    [17, 18, 18, 24, 21, 24, 47, 26],
+    [26, 47, 99, 66, 56, 66, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+];
+
+// Scale like libjpeg: quality 50 keeps the table, 100 makes it all ones
+// Entries stay within 1 to 255 so that baseline decoders accept them
+pub fn scale_quant_table(quant_table: &QuantTable, quality: u8) -> QuantTable {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
     };
-    pub static ref CHROMINANCE_QUANT_TABLE: QuantTable = {
-        let mut qtable = [
-            [17, 18, 18, 24, 21, 24, 47, 26],
-            [26, 47, 99, 66, 56, 66, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-            [99, 99, 99, 99, 99, 99, 99, 99],
-        ];
-        prescale_quant_table(&mut qtable);
 
-        qtable
-    };
-}
-
-// Adjust parameter yourself
-fn prescale_quant_table(quant_table: &mut QuantTable) {
+    let mut result = [[0; QUANT_TABLE_WIDTH]; QUANT_TABLE_HEIGHT];
     for y in 0..QUANT_TABLE_HEIGHT {
         for x in 0..QUANT_TABLE_WIDTH {
-            quant_table[y][x] = (quant_table[y][x] + 1) / 2;
+            let value = (quant_table[y][x] as u32 * scale + 50) / 100;
+            result[y][x] = value.clamp(1, 255) as u8;
         }
     }
-}
 
-pub fn quant(g: [f64; QUANT_TABLE_SIZE], mode: Mode) -> ([i32; QUANT_TABLE_SIZE], i32) {
-    let quanted = match mode {
-        Mode::Luminance => quant_intern(g, &LUMINANCE_QUANT_TABLE),
-        Mode::Chromiance => quant_intern(g, &CHROMINANCE_QUANT_TABLE),
-    };
-
-    // Return DC value
-    (quanted, quanted[0])
+    result
 }
 
-fn quant_intern(g: [f64; QUANT_TABLE_SIZE], quant_table: &QuantTable) -> [i32; QUANT_TABLE_SIZE] {
-    let mut result = [0_i32; QUANT_TABLE_SIZE];
-    for y in 0..QUANT_TABLE_HEIGHT {
-        for x in 0..QUANT_TABLE_WIDTH {
-            result[y * QUANT_TABLE_WIDTH + x] = (g[y * QUANT_TABLE_WIDTH + x] as f64
-                / (quant_table[y][x] as i32) as f64)
-                .round() as i32;
-        }
+// User tables are given in row-major order
+pub fn from_natural_order(table: &[u8; QUANT_TABLE_SIZE]) -> QuantTable {
+    let mut result = [[0; QUANT_TABLE_WIDTH]; QUANT_TABLE_HEIGHT];
+    for (index, &natural) in ZIG_ZAG_ORDER.iter().enumerate() {
+        result[index / QUANT_TABLE_WIDTH][index % QUANT_TABLE_WIDTH] = table[natural];
     }
 
     result
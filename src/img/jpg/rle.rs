@@ -1,17 +1,25 @@
-use super::huffman::{
-    CHROMINANCE_AC_TABLE, CHROMINANCE_DC_TABLE, LUMINANCE_AC_TABLE, LUMINANCE_DC_TABLE,
-};
+use super::huffman::HuffmanTable;
 
 use super::common::{Bits, bit};
-use super::jpeg::Mode;
+use crate::Error;
 
-pub fn encode(squence: &[i32], bits: &mut Bits, prev_dc: i32, mode: Mode) -> Vec<u8> {
+// Largest magnitude categories JPEG defines for 8-bit samples
+const MAX_DC_SIZE: u8 = 11;
+const MAX_AC_SIZE: u8 = 10;
+
+pub fn encode(
+    squence: &[i32],
+    bits: &mut Bits,
+    prev_dc: i32,
+    dc_table: &HuffmanTable,
+    ac_table: &HuffmanTable,
+) -> Result<Vec<u8>, Error> {
     let mut result = Vec::new();
     let mut run_length = 0;
     for (index, num) in squence.iter().enumerate() {
         let mut encode = Bits::new(0, 0);
         if index == 0 {
-            encode = encode_dc(*num - prev_dc, mode);
+            encode = encode_dc(*num - prev_dc, dc_table)?;
         } else {
             // Do not record when encounter 0
             // Only to increase run_length
@@ -22,12 +30,13 @@ pub fn encode(squence: &[i32], bits: &mut Bits, prev_dc: i32, mode: Mode) -> Vec
                 // Note the run_size is up to 15
                 // So if there is more than 15 zeros, emit multiple (15, 0) pairs
                 while run_length > 15 {
-                    let encode = encode_ac(15, 0, mode);
+                    let encode = encode_ac(15, 0, ac_table)?;
                     *bits += encode;
+                    result.append(&mut bits.dump());
                     run_length -= 16;
                 }
                 // After encode zeros, we can now encode this non-zero number
-                encode = encode_ac(run_length, *num, mode);
+                encode = encode_ac(run_length, *num, ac_table)?;
                 run_length = 0;
             }
         }
@@ -37,57 +46,78 @@ pub fn encode(squence: &[i32], bits: &mut Bits, prev_dc: i32, mode: Mode) -> Vec
     }
     // End of Block: rl/size = 0/0
     if run_length != 0 {
-        *bits += encode_ac(0, 0, mode);
+        *bits += encode_ac(0, 0, ac_table)?;
         let mut last_byte = bits.dump();
         result.append(&mut last_byte);
     }
 
-    result
+    Ok(result)
+}
+
+// Count the symbols `encode` would write, to build optimized tables
+pub fn count_symbols(squence: &[i32], prev_dc: i32, dc: &mut [u32; 256], ac: &mut [u32; 256]) {
+    let amplitude = bit::get_bit_conut((squence[0] - prev_dc).abs()) as usize;
+    dc[amplitude.min(255)] += 1;
+
+    let mut run_length = 0;
+    for num in squence[1..].iter() {
+        if *num == 0 {
+            run_length += 1;
+            continue;
+        }
+        while run_length > 15 {
+            ac[0xf0] += 1;
+            run_length -= 16;
+        }
+        let size = (bit::get_bit_conut(num.abs()) as usize).min(15);
+        ac[run_length << 4 | size] += 1;
+        run_length = 0;
+    }
+    if run_length != 0 {
+        ac[0x00] += 1;
+    }
 }
 
-fn encode_dc(dc: i32, mode: Mode) -> Bits {
+fn encode_dc(dc: i32, dc_table: &HuffmanTable) -> Result<Bits, Error> {
     // Huffman-coded sysmbol1
     let amplitude = bit::get_bit_conut(dc.abs()) as u8;
-    let codeword = if mode == Mode::Luminance {
-        LUMINANCE_DC_TABLE.get(&amplitude)
-    } else {
-        CHROMINANCE_DC_TABLE.get(&amplitude)
-    };
+    let codeword = dc_table.get(&amplitude).filter(|_| amplitude <= MAX_DC_SIZE);
     // Row sysmbol2
     let ones_complements = bit::get_ones_complements(dc);
-    if let Some(codeword) = codeword {
-        *codeword + Bits::new(amplitude, ones_complements as u32)
-    } else {
-        panic!("No such DC value!");
+    match codeword {
+        Some(codeword) => Ok(*codeword + Bits::new(amplitude, ones_complements as u32)),
+        None => Err(Error::FormatLimit(format!(
+            "DC difference {} can not be Huffman coded",
+            dc
+        ))),
     }
 }
 
-fn encode_ac(run_length: u8, ac: i32, mode: Mode) -> Bits {
+fn encode_ac(run_length: u8, ac: i32, ac_table: &HuffmanTable) -> Result<Bits, Error> {
     // Huffman-coded sysmbo1
     let size = bit::get_bit_conut(ac.abs()) as u8;
     let symbol1 = run_length << 4 | size;
-    let codeword = if mode == Mode::Luminance {
-        LUMINANCE_AC_TABLE.get(&symbol1)
-    } else {
-        CHROMINANCE_AC_TABLE.get(&symbol1)
-    };
+    let codeword = ac_table.get(&symbol1).filter(|_| size <= MAX_AC_SIZE);
     // Row sysmbo2
     let ones_complements = bit::get_ones_complements(ac);
-    if let Some(codeword) = codeword {
-        *codeword + Bits::new(size, ones_complements as u32)
-    } else {
-        panic!("No such AC value!");
+    match codeword {
+        Some(codeword) => Ok(*codeword + Bits::new(size, ones_complements as u32)),
+        None => Err(Error::FormatLimit(format!(
+            "AC coefficient {} can not be Huffman coded",
+            ac
+        ))),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::img::jpg::huffman::{generate_huffman_table, LUMINANCE_AC_SPEC, LUMINANCE_DC_SPEC};
 
     #[test]
     fn test_encode_dc() {
         assert_eq!(
-            encode_dc(2, Mode::Luminance),
+            encode_dc(2, &generate_huffman_table(&LUMINANCE_DC_SPEC)).unwrap(),
             Bits::new(5, 0b01110)
         );
     }
@@ -95,7 +125,7 @@ mod tests {
     #[test]
     fn test_encode_ac() {
         assert_eq!(
-            encode_ac(0, 16, Mode::Luminance),
+            encode_ac(0, 16, &generate_huffman_table(&LUMINANCE_AC_SPEC)).unwrap(),
             Bits::new(10, 0b1101010000)
         );
     }
@@ -103,6 +133,35 @@ mod tests {
     #[test]
     fn test_encode_sequence() {
         let test_sequence = [2, 16, -21, 10, -15, 0, 0, 0, 3, -2, 0];
-        encode(&test_sequence, &mut Bits::new(0, 0), 0, Mode::Luminance);
+        let dc_table = generate_huffman_table(&LUMINANCE_DC_SPEC);
+        let ac_table = generate_huffman_table(&LUMINANCE_AC_SPEC);
+        encode(&test_sequence, &mut Bits::new(0, 0), 0, &dc_table, &ac_table).unwrap();
+    }
+
+    #[test]
+    fn test_encode_long_codes() {
+        // 7 pending bits, three ZRLs and a 16-bit code with 10 more bits
+        // make 66 bits, more than `Bits` holds without dumping in between
+        let mut test_sequence = [0; 64];
+        test_sequence[63] = 1000;
+        let dc_table = generate_huffman_table(&LUMINANCE_DC_SPEC);
+        let ac_table = generate_huffman_table(&LUMINANCE_AC_SPEC);
+        let mut bits = Bits::new(5, 0b10110);
+        let bytes = encode(&test_sequence, &mut bits, 0, &dc_table, &ac_table).unwrap();
+
+        let mut expected = Bits::new(5, 0b10110);
+        let mut expected_bytes = Vec::new();
+        let zrl = ac_table[&0xf0];
+        for code in [dc_table[&0], zrl, zrl, zrl, ac_table[&0xea] + Bits::new(10, 1000)] {
+            expected += code;
+            expected_bytes.append(&mut expected.dump());
+        }
+        assert_eq!(bytes, expected_bytes);
+        assert_eq!(bits, expected);
+    }
+
+    #[test]
+    fn test_encode_dc_out_of_range() {
+        assert!(encode_dc(4096, &generate_huffman_table(&LUMINANCE_DC_SPEC)).is_err());
     }
 }
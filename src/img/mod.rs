@@ -6,19 +6,62 @@ pub mod bmp;
 pub mod tiff;
 pub mod avif;
 
-use std::error::Error;
+mod buffer;
+mod dynamic;
+
+pub use buffer::ImageBuffer;
+pub use dynamic::DynamicImage;
+
+use crate::Error;
+
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 
 pub trait Serializable {
-    fn get_bytes(&self) -> Vec<u8>;
+    // Write straight into the sink, without building intermediate buffers
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
 }
 
 pub trait Image: Serializable {
-    fn dump(&self, path: &str) -> Result<(), Box<dyn Error>> {
-        let mut file = File::create(path)?;
-        let bytes = self.get_bytes();
-        file.write(&bytes)?;
+    /// Encode the whole file into any writer, e.g. a socket or a `Vec<u8>`.
+    fn encode_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.serialize(writer)
+    }
+
+    /// Encode the whole file into memory.
+    fn encode_to_vec(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        self.encode_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn dump(&self, path: &str) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.encode_to(&mut file)?;
+        file.flush()?;
         Ok(())
     }
 }
+
+// Most formats can not store an empty image
+pub(crate) fn check_dimensions(width: u32, height: u32) -> Result<(), Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::InvalidDimensions { width, height });
+    }
+
+    Ok(())
+}
+
+// Raw data must contain exactly `width * height * channel` values
+pub(crate) fn check_data_size(
+    width: u32,
+    height: u32,
+    channel: usize,
+    data: &[u8],
+) -> Result<(), Error> {
+    if data.len() as u64 != width as u64 * height as u64 * channel as u64 {
+        return Err(Error::InvalidDimensions { width, height });
+    }
+
+    Ok(())
+}
@@ -4,13 +4,13 @@ mod ppm;
 mod pam;
 mod utils;
 
-use super::{Image, Serializable};
-use pbm::PBM;
-use pgm::PGM;
-use ppm::PPM;
-use pam::{PAM, TupleType};
-
-use std::error::Error;
+use super::{Image, ImageBuffer, Serializable};
+use crate::color::{Luma, Pixel, Rgb, Rgba};
+use crate::Error;
+pub use pbm::PBM;
+pub use pgm::PGM;
+pub use ppm::PPM;
+pub use pam::{PAM, TupleType};
 
 pub enum Mode {
     Ascii,
@@ -21,10 +21,16 @@ pub fn save_pbm<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[u8; WIDTH]; HEIGHT],
     mode: Mode,
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().collect::<Vec<_>>();
-    let pbm = PBM::new(WIDTH as u32, HEIGHT as u32, mode, &data);
-    pbm.dump(path)
+) -> Result<(), Error> {
+    save_pbm_buffer(path, &ImageBuffer::from(data).map(|&l| Luma([l])), mode)
+}
+
+pub fn save_pbm_buffer(
+    path: &str,
+    image: &ImageBuffer<Luma<u8>>,
+    mode: Mode,
+) -> Result<(), Error> {
+    PBM::from_buffer(image, mode)?.dump(path)
 }
 
 pub fn save_pgm<const WIDTH: usize, const HEIGHT: usize>(
@@ -32,10 +38,17 @@ pub fn save_pgm<const WIDTH: usize, const HEIGHT: usize>(
     data: [[u8; WIDTH]; HEIGHT],
     max_value: u8,
     mode: Mode,
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().collect::<Vec<_>>();
-    let pbm = PGM::new(mode, WIDTH as u32, HEIGHT as u32, max_value, &data);
-    pbm.dump(path)
+) -> Result<(), Error> {
+    save_pgm_buffer(path, &ImageBuffer::from(data).map(|&l| Luma([l])), max_value, mode)
+}
+
+pub fn save_pgm_buffer(
+    path: &str,
+    image: &ImageBuffer<Luma<u8>>,
+    max_value: u8,
+    mode: Mode,
+) -> Result<(), Error> {
+    PGM::from_buffer(image, max_value, mode)?.dump(path)
 }
 
 pub fn save_ppm<const WIDTH: usize, const HEIGHT: usize>(
@@ -43,21 +56,28 @@ pub fn save_ppm<const WIDTH: usize, const HEIGHT: usize>(
     data: [[[u8; 3]; WIDTH]; HEIGHT],
     max_value: u8,
     mode: Mode,
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    let pbm = PPM::new(mode, WIDTH as u32, HEIGHT as u32, max_value, &data);
-    pbm.dump(path)
+) -> Result<(), Error> {
+    save_ppm_buffer(path, &ImageBuffer::from(data).map(|&rgb| Rgb(rgb)), max_value, mode)
+}
+
+pub fn save_ppm_buffer(
+    path: &str,
+    image: &ImageBuffer<Rgb<u8>>,
+    max_value: u8,
+    mode: Mode,
+) -> Result<(), Error> {
+    PPM::from_buffer(image, max_value, mode)?.dump(path)
 }
 
 pub fn save_pam_2d<const WIDTH: usize, const HEIGHT: usize>(
     path: &str,
     data: [[u8; WIDTH]; HEIGHT],
     mode: TupleType,
-) -> Result<(), Box<dyn Error>> {
-    assert!(mode != TupleType::RGB && mode != TupleType::RGBAlpha);
-    let data = data.iter().cloned().flatten().collect::<Vec<_>>();
-    let pbm = PAM::new(mode, WIDTH as u32, HEIGHT as u32, &data);
-    pbm.dump(path)
+) -> Result<(), Error> {
+    if mode == TupleType::RGB || mode == TupleType::RGBAlpha {
+        return Err(Error::Unsupported(format!("2D PAM with tuple type {}", mode)));
+    }
+    save_pam_buffer_as(path, &ImageBuffer::from(data).map(|&l| Luma([l])), mode)
 }
 
 // Introduce CHANNEL to support alpha channel
@@ -65,9 +85,32 @@ pub fn save_pam_3d<const WIDTH: usize, const HEIGHT: usize, const CHANNEL: usize
     path: &str,
     data: [[[u8; CHANNEL]; WIDTH]; HEIGHT],
     mode: TupleType,
-) -> Result<(), Box<dyn Error>> {
-    assert!(mode == TupleType::RGB || mode == TupleType::RGBAlpha);
-    let data = data.iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    let pbm = PAM::new(mode, WIDTH as u32, HEIGHT as u32, &data);
-    pbm.dump(path)
+) -> Result<(), Error> {
+    if mode != TupleType::RGB && mode != TupleType::RGBAlpha {
+        return Err(Error::Unsupported(format!("3D PAM with tuple type {}", mode)));
+    }
+    let image = ImageBuffer::from(data);
+    match CHANNEL {
+        3 => save_pam_buffer_as(path, &image.map(|pixel| Rgb::from_slice(pixel)), mode),
+        4 => save_pam_buffer_as(path, &image.map(|pixel| Rgba::from_slice(pixel)), mode),
+        _ => Err(Error::Unsupported(format!("PAM with {} channels", CHANNEL))),
+    }
+}
+
+// TUPLETYPE is decided by the pixel type
+pub fn save_pam_buffer<P: Pixel<Subpixel = u8>>(
+    path: &str,
+    image: &ImageBuffer<P>,
+) -> Result<(), Error> {
+    PAM::from_buffer(image)?.dump(path)
+}
+
+// The const-array functions choose TUPLETYPE themselves, e.g. BLACKANDWHITE
+fn save_pam_buffer_as<P: Pixel<Subpixel = u8>>(
+    path: &str,
+    image: &ImageBuffer<P>,
+    mode: TupleType,
+) -> Result<(), Error> {
+    let pam = PAM::new(mode, image.width(), image.height(), &image.to_channels())?;
+    pam.dump(path)
 }
@@ -1,4 +1,10 @@
-use super::{Image, Serializable};
+use super::{Image, ImageBuffer, Serializable};
+use crate::color::{ColorType, Pixel};
+use crate::img::check_data_size;
+use crate::Error;
+
+use std::fmt;
+use std::io::Write;
 
 #[derive(PartialEq)]
 pub enum TupleType {
@@ -32,8 +38,19 @@ impl TupleType {
     }
 }
 
-impl ToString for TupleType {
-    fn to_string(&self) -> String {
+impl From<ColorType> for TupleType {
+    fn from(color_type: ColorType) -> Self {
+        match color_type {
+            ColorType::Luma => TupleType::GrayScale,
+            ColorType::LumaA => TupleType::GrayScaleAlpha,
+            ColorType::Rgb => TupleType::RGB,
+            ColorType::Rgba => TupleType::RGBAlpha,
+        }
+    }
+}
+
+impl fmt::Display for TupleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let str = match self {
             TupleType::BlackAndWhite => "BLACKANDWHITE",
             TupleType::GrayScale => "GRAYSCALE",
@@ -43,7 +60,7 @@ impl ToString for TupleType {
             TupleType::RGBAlpha => "RGB_ALPHA",
         };
 
-        String::from(str)
+        write!(f, "{}", str)
     }
 }
 
@@ -57,42 +74,46 @@ pub struct PAM {
 }
 
 impl PAM {
-    pub fn new(mode: TupleType, width: u32, height: u32, data: &Vec<u8>) -> Self {
-        Self {
+    pub fn new(mode: TupleType, width: u32, height: u32, data: &[u8]) -> Result<Self, Error> {
+        check_data_size(width, height, mode.to_depth() as usize, data)?;
+
+        Ok(Self {
             width,
             height,
             depth: mode.to_depth(),
             max_value: mode.to_max_value(),
             tuple_type: mode,
             data: data.to_vec(),
-        }
+        })
+    }
+
+    // TUPLETYPE is decided by the pixel type
+    pub fn from_buffer<P: Pixel<Subpixel = u8>>(image: &ImageBuffer<P>) -> Result<Self, Error> {
+        let mode = TupleType::from(P::COLOR_TYPE);
+        Self::new(mode, image.width(), image.height(), &image.to_channels())
     }
 }
 
 impl Serializable for PAM {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        let header = format!(
-            "{magic_number}\n\
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write!(
+            writer,
+            "P7\n\
             WIDTH {width}\n\
             HEIGHT {height}\n\
             DEPTH {depth}\n\
             MAXVAL {max_value}\n\
             TUPLETYPE {tuple_type}\n\
             ENDHDR\n",
-            magic_number = "P7",
             width = self.width,
             height = self.height,
             depth = self.depth,
             max_value = self.max_value,
-            tuple_type = self.tuple_type.to_string()
-        );
-        bytes.extend(header.bytes());
-
-        bytes.extend(self.data.to_vec());
+            tuple_type = self.tuple_type
+        )?;
+        writer.write_all(&self.data)?;
 
-        bytes
+        Ok(())
     }
 }
 
@@ -1,6 +1,11 @@
 use super::utils;
 use super::Mode;
-use super::{Image, Serializable};
+use super::{Image, ImageBuffer, Serializable};
+use crate::color::Luma;
+use crate::img::check_data_size;
+use crate::Error;
+
+use std::io::Write;
 
 pub struct PBM {
     mode: Mode,
@@ -10,8 +15,11 @@ pub struct PBM {
 }
 
 impl PBM {
-    pub fn new(width: u32, height: u32, mode: Mode, data: &Vec<u8>) -> Self {
-        match mode {
+    pub fn new(width: u32, height: u32, mode: Mode, data: &[u8]) -> Result<Self, Error> {
+        check_data_size(width, height, 1, data)?;
+        utils::check_max_value(data, 1)?;
+
+        Ok(match mode {
             Mode::Ascii => Self {
                 mode,
                 width,
@@ -26,31 +34,35 @@ impl PBM {
                 // e.g.: [0b1, 0b0, 0b0, 0b0, 0b0] -> 0b10000000
                 data: compress_bits_to_u8_array(width, height, data),
             },
-        }
+        })
+    }
+
+    // Each pixel is either 0 or 1
+    pub fn from_buffer(image: &ImageBuffer<Luma<u8>>, mode: Mode) -> Result<Self, Error> {
+        Self::new(image.width(), image.height(), mode, &image.to_channels())
     }
 }
 
 // Compress byte to bit sequence
-fn compress_bits_to_u8_array(width: u32, height: u32, bits: &Vec<u8>) -> Vec<u8> {
+fn compress_bits_to_u8_array(width: u32, height: u32, bits: &[u8]) -> Vec<u8> {
     let mut converted_data = Vec::new();
     for index in 0..height {
         let index = index as usize;
         let row_start = index * (width as usize);
         let row_end = std::cmp::min(row_start + width as usize, bits.len());
-        let line = &bits[row_start..row_end].to_vec();
+        let line = &bits[row_start..row_end];
         // Extra bits are added at the end of each row to fill a whole byte
         let bytes = utils::u8_to_bits(line);
         converted_data.push(bytes);
     }
 
-    converted_data.iter().cloned().flatten().collect::<Vec<_>>()
+    converted_data.into_iter().flatten().collect::<Vec<_>>()
 }
 
 impl Serializable for PBM {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        let header = format!(
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write!(
+            writer,
             "{magic_number}\n{width} {height}\n",
             magic_number = if let Mode::Ascii = self.mode {
                 "P1"
@@ -59,11 +71,10 @@ impl Serializable for PBM {
             },
             width = self.width,
             height = self.height,
-        );
-        bytes.extend(header.bytes());
-        bytes.extend(&self.data);
+        )?;
+        writer.write_all(&self.data)?;
 
-        bytes
+        Ok(())
     }
 }
 
@@ -1,5 +1,11 @@
+use super::utils;
 use super::Mode;
-use super::{Image, Serializable};
+use super::{Image, ImageBuffer, Serializable};
+use crate::color::Luma;
+use crate::img::check_data_size;
+use crate::Error;
+
+use std::io::Write;
 
 pub struct PGM {
     mode: Mode,
@@ -10,59 +16,69 @@ pub struct PGM {
 }
 
 impl PGM {
-    pub fn new(mode: Mode, width: u32, height: u32, max_value: u8, data: &Vec<u8>) -> Self {
-        Self {
+    pub fn new(
+        mode: Mode,
+        width: u32,
+        height: u32,
+        max_value: u8,
+        data: &[u8],
+    ) -> Result<Self, Error> {
+        check_data_size(width, height, 1, data)?;
+        utils::check_max_value(data, max_value)?;
+
+        Ok(Self {
             mode,
             width,
             height,
             max_value,
             data: data.to_vec(),
-        }
+        })
+    }
+
+    pub fn from_buffer(
+        image: &ImageBuffer<Luma<u8>>,
+        max_value: u8,
+        mode: Mode,
+    ) -> Result<Self, Error> {
+        Self::new(mode, image.width(), image.height(), max_value, &image.to_channels())
     }
 }
 
 impl Serializable for PGM {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         match self.mode {
             Mode::Ascii => {
-                let header = format!(
-                    "{magic_number}\n{width} {height}\n{max_value}\n",
-                    magic_number = "P2",
+                write!(
+                    writer,
+                    "P2\n{width} {height}\n{max_value}\n",
                     width = self.width,
                     height = self.height,
                     max_value = self.max_value
-                );
-                bytes.extend(header.bytes());
+                )?;
 
-                let mut data = String::new();
-                for gray_value in &self.data {
-                    data += &gray_value.to_string();
-                    data += " ";
+                for value in &self.data {
+                    write!(writer, "{} ", value)?;
                 }
-                bytes.extend(data.bytes());
             }
             Mode::Binary => {
-                let header = format!(
-                    "{magic_number}\n{width} {height}\n{max_value}\n",
-                    magic_number = "P5",
+                write!(
+                    writer,
+                    "P5\n{width} {height}\n{max_value}\n",
                     width = self.width,
                     height = self.height,
                     max_value = self.max_value
-                );
-                bytes.extend(header.bytes());
+                )?;
                 // Map the gray value from [0, max_value] to [0, 255]
                 let grays = self
                     .data
                     .iter()
-                    .map(|x| x * (256 / (self.max_value as u16 + 1) as u16) as u8)
+                    .map(|x| x * (256 / (self.max_value as u16 + 1)) as u8)
                     .collect::<Vec<_>>();
-                bytes.extend(grays);
+                writer.write_all(&grays)?;
             }
         }
 
-        bytes
+        Ok(())
     }
 }
 
@@ -1,10 +1,23 @@
-pub fn byte_to_char(u8_array: &Vec<u8>) -> Vec<u8> {
+use crate::Error;
+
+// Larger value can not be represented in the file
+pub fn check_max_value(data: &[u8], max_value: u8) -> Result<(), Error> {
+    match data.iter().find(|&&value| value > max_value) {
+        Some(value) => Err(Error::FormatLimit(format!(
+            "value {} exceeds max value {}",
+            value, max_value
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn byte_to_char(u8_array: &[u8]) -> Vec<u8> {
     u8_array.iter().map(|x| x + 48).collect::<Vec<u8>>()
 }
 
 // Convert u8 array to bit array
-pub fn u8_to_bits(u8_array: &Vec<u8>) -> Vec<u8> {
-    let mut bits_array = Vec::with_capacity((u8_array.len() + 7) / 8);
+pub fn u8_to_bits(u8_array: &[u8]) -> Vec<u8> {
+    let mut bits_array = Vec::with_capacity(u8_array.len().div_ceil(8));
 
     let count = u8_array.len();
     let mut index = 0;
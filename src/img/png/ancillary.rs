@@ -0,0 +1,315 @@
+use super::deflate;
+use crate::Error;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Transparency and animation chunks, derived from the image by the encoders
+const RESERVED: [[u8; 4]; 4] = [*b"tRNS", *b"acTL", *b"fcTL", *b"fdAT"];
+// Chunks with a variant of their own, which checks them and how often they appear
+const TYPED: [[u8; 4]; 9] = [
+    *b"tEXt", *b"zTXt", *b"iTXt", *b"pHYs", *b"gAMA", *b"cHRM", *b"sRGB", *b"iCCP", *b"tIME",
+];
+
+/// Optional chunk carrying metadata, written before the image data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AncillaryChunk {
+    /// `tEXt`, Latin-1 keyword and text
+    Text { keyword: String, text: String },
+    /// `zTXt`, Latin-1 keyword and deflate compressed text
+    CompressedText { keyword: String, text: String },
+    /// `iTXt`, UTF-8 text with its language tag and translated keyword
+    InternationalText {
+        keyword: String,
+        language: String,
+        translated_keyword: String,
+        text: String,
+        compressed: bool,
+    },
+    /// `pHYs`, pixels per unit along x and y
+    PhysicalSize { x: u32, y: u32, unit: PhysicalUnit },
+    /// `gAMA`, image gamma times 100000, e.g. 45455 for 1/2.2
+    Gamma(u32),
+    /// `cHRM`, CIE x and y of the white point and primaries, times 100000
+    Chromaticities {
+        white: (u32, u32),
+        red: (u32, u32),
+        green: (u32, u32),
+        blue: (u32, u32),
+    },
+    /// `sRGB`, the image is in the sRGB color space
+    Srgb(RenderingIntent),
+    /// `iCCP`, embedded ICC profile
+    IccProfile { name: String, profile: Vec<u8> },
+    /// `tIME`, last modification time in UTC
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    /// Any other ancillary chunk, the name must be four ASCII letters starting lowercase.
+    /// `tRNS` and the animation chunks are reserved for the encoders, and chunks
+    /// with a variant above must use it.
+    Custom { name: [u8; 4], data: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    /// Only the aspect ratio is known
+    Unknown = 0,
+    Meter = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+impl AncillaryChunk {
+    /// `pHYs` chunk for the given dots per inch.
+    pub fn from_dpi(dpi: f64) -> Self {
+        let pixels_per_meter = (dpi / 0.0254).round() as u32;
+        AncillaryChunk::PhysicalSize {
+            x: pixels_per_meter,
+            y: pixels_per_meter,
+            unit: PhysicalUnit::Meter,
+        }
+    }
+
+    /// `tIME` chunk for the current system time.
+    pub fn time_now() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let seconds = seconds % 86400;
+        AncillaryChunk::Time {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    pub(crate) fn name(&self) -> [u8; 4] {
+        match self {
+            AncillaryChunk::Text { .. } => *b"tEXt",
+            AncillaryChunk::CompressedText { .. } => *b"zTXt",
+            AncillaryChunk::InternationalText { .. } => *b"iTXt",
+            AncillaryChunk::PhysicalSize { .. } => *b"pHYs",
+            AncillaryChunk::Gamma(_) => *b"gAMA",
+            AncillaryChunk::Chromaticities { .. } => *b"cHRM",
+            AncillaryChunk::Srgb(_) => *b"sRGB",
+            AncillaryChunk::IccProfile { .. } => *b"iCCP",
+            AncillaryChunk::Time { .. } => *b"tIME",
+            AncillaryChunk::Custom { name, .. } => *name,
+        }
+    }
+
+    // Text and user chunks may appear more than once
+    pub(crate) fn is_repeatable(&self) -> bool {
+        matches!(
+            self,
+            AncillaryChunk::Text { .. }
+                | AncillaryChunk::CompressedText { .. }
+                | AncillaryChunk::InternationalText { .. }
+                | AncillaryChunk::Custom { .. }
+        )
+    }
+
+    // Chunk data, after checking the fields against the spec
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        match self {
+            AncillaryChunk::Text { keyword, text } => {
+                data.extend(encode_keyword(keyword)?);
+                data.push(0);
+                data.extend(encode_latin1(text)?);
+            }
+            AncillaryChunk::CompressedText { keyword, text } => {
+                data.extend(encode_keyword(keyword)?);
+                // Null separator and compression method
+                data.extend_from_slice(&[0, 0]);
+                data.extend(deflate::compress(&encode_latin1(text)?, 9));
+            }
+            AncillaryChunk::InternationalText {
+                keyword,
+                language,
+                translated_keyword,
+                text,
+                compressed,
+            } => {
+                data.extend(encode_keyword(keyword)?);
+                data.extend_from_slice(&[0, *compressed as u8, 0]);
+                if !language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    return Err(Error::Unsupported(format!(
+                        "PNG language tag {:?}",
+                        language
+                    )));
+                }
+                data.extend_from_slice(language.as_bytes());
+                data.push(0);
+                if translated_keyword.contains('\0') {
+                    return Err(Error::Unsupported(
+                        "PNG translated keyword with a null character".to_string(),
+                    ));
+                }
+                data.extend_from_slice(translated_keyword.as_bytes());
+                data.push(0);
+                if *compressed {
+                    data.extend(deflate::compress(text.as_bytes(), 9));
+                } else {
+                    data.extend_from_slice(text.as_bytes());
+                }
+            }
+            AncillaryChunk::PhysicalSize { x, y, unit } => {
+                data.extend_from_slice(&x.to_be_bytes());
+                data.extend_from_slice(&y.to_be_bytes());
+                data.push(*unit as u8);
+            }
+            AncillaryChunk::Gamma(gamma) => data.extend_from_slice(&gamma.to_be_bytes()),
+            AncillaryChunk::Chromaticities {
+                white,
+                red,
+                green,
+                blue,
+            } => {
+                for &(x, y) in [white, red, green, blue].iter() {
+                    data.extend_from_slice(&x.to_be_bytes());
+                    data.extend_from_slice(&y.to_be_bytes());
+                }
+            }
+            AncillaryChunk::Srgb(intent) => data.push(*intent as u8),
+            AncillaryChunk::IccProfile { name, profile } => {
+                data.extend(encode_keyword(name)?);
+                data.extend_from_slice(&[0, 0]);
+                data.extend(deflate::compress(profile, 9));
+            }
+            AncillaryChunk::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => {
+                // A leap second is allowed
+                if !(1..=12).contains(month)
+                    || !(1..=31).contains(day)
+                    || *hour > 23
+                    || *minute > 59
+                    || *second > 60
+                {
+                    return Err(Error::Unsupported(format!(
+                        "PNG time {}-{}-{} {}:{}:{}",
+                        year, month, day, hour, minute, second
+                    )));
+                }
+                data.extend_from_slice(&year.to_be_bytes());
+                data.extend_from_slice(&[*month, *day, *hour, *minute, *second]);
+            }
+            AncillaryChunk::Custom {
+                name,
+                data: payload,
+            } => {
+                // Lowercase first letter marks it ancillary, the third one is reserved uppercase
+                if !name.iter().all(|byte| byte.is_ascii_alphabetic())
+                    || !name[0].is_ascii_lowercase()
+                    || !name[2].is_ascii_uppercase()
+                {
+                    return Err(Error::Unsupported(format!(
+                        "PNG chunk name {:?}",
+                        String::from_utf8_lossy(name)
+                    )));
+                }
+                if TYPED.contains(name) {
+                    return Err(Error::Unsupported(format!(
+                        "custom PNG {} chunk, use its own variant instead",
+                        String::from_utf8_lossy(name)
+                    )));
+                }
+                if RESERVED.contains(name) {
+                    return Err(Error::Unsupported(format!(
+                        "custom PNG {} chunk, it is written by the encoder",
+                        String::from_utf8_lossy(name)
+                    )));
+                }
+                data.extend_from_slice(payload);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+// 1 to 79 printable Latin-1 characters, without leading, trailing or consecutive spaces
+fn encode_keyword(keyword: &str) -> Result<Vec<u8>, Error> {
+    let bytes = encode_latin1(keyword)?;
+    let printable = bytes
+        .iter()
+        .all(|&byte| (32..=126).contains(&byte) || byte >= 161);
+    if bytes.is_empty()
+        || bytes.len() > 79
+        || !printable
+        || keyword.starts_with(' ')
+        || keyword.ends_with(' ')
+        || keyword.contains("  ")
+    {
+        return Err(Error::Unsupported(format!("PNG keyword {:?}", keyword)));
+    }
+
+    Ok(bytes)
+}
+
+fn encode_latin1(text: &str) -> Result<Vec<u8>, Error> {
+    text.chars()
+        .map(|c| {
+            if (c as u32) < 256 && c != '\0' {
+                Ok(c as u8)
+            } else {
+                Err(Error::Unsupported(format!(
+                    "character {:?} in Latin-1 PNG text, use international text instead",
+                    c
+                )))
+            }
+        })
+        .collect()
+}
+
+// Days since 1970-01-01 to the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (u16, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as u16, month as u8, day as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2021, 12, 31), civil_from_days(18992));
+    }
+}
@@ -0,0 +1,285 @@
+// Chunk structs are named after their types, e.g. `ACTL`
+#![allow(clippy::upper_case_acronyms)]
+
+use super::png::{
+    ancillary_chunks, check_size, compress_image, Ancillary, Chunk, Scanlines, IDAT, IEND, IHDR,
+    MAX_SIZE, SIGNATURE,
+};
+use super::{Image, ImageBuffer, PngOptions, Serializable};
+use crate::color::Pixel;
+use crate::Error;
+
+use std::io::Write;
+use std::marker::PhantomData;
+
+/// What happens to the frame area before the next frame is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// Leave the canvas as it is
+    None = 0,
+    /// Clear the frame area to transparent black
+    Background = 1,
+    /// Restore the frame area to what it was before this frame
+    Previous = 2,
+}
+
+/// How the frame is drawn onto the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    /// Replace the frame area, alpha included
+    Source = 0,
+    /// Alpha composite the frame over the canvas
+    Over = 1,
+}
+
+/// Position, timing and compositing of one frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// Numerator of the delay before the next frame, in seconds
+    pub delay_num: u16,
+    /// Denominator of the delay, 0 is taken as 100
+    pub delay_den: u16,
+    pub dispose: DisposeOp,
+    pub blend: BlendOp,
+}
+
+impl Default for FrameControl {
+    fn default() -> Self {
+        Self {
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose: DisposeOp::None,
+            blend: BlendOp::Source,
+        }
+    }
+}
+
+/// Animated PNG made of frames of the same pixel type.
+///
+/// The first frame covers the whole canvas and is also the image shown by
+/// viewers without APNG support. Later frames may be smaller sub-rectangles.
+pub struct AnimatedPngEncoder<P: Pixel> {
+    width: u32,
+    height: u32,
+    options: PngOptions,
+    num_plays: u32,
+    ihdr_chunk: Option<Chunk<IHDR>>,
+    ancillary_chunks: Vec<Chunk<Ancillary>>,
+    default_frame: Option<(Chunk<FCTL>, Vec<Chunk<IDAT>>)>,
+    frames: Vec<(Chunk<FCTL>, Vec<Chunk<FDAT>>)>,
+    // Shared by fcTL and fdAT chunks
+    sequence_number: u32,
+    pixel: PhantomData<P>,
+}
+
+// Number of frames and times to play them, 0 for forever
+struct ACTL {
+    num_frames: u32,
+    num_plays: u32,
+}
+
+struct FCTL {
+    sequence_number: u32,
+    width: u32,
+    height: u32,
+    control: FrameControl,
+}
+
+// Part of a zlib stream like IDAT, after a sequence number
+struct FDAT {
+    sequence_number: u32,
+    data: Vec<u8>,
+}
+
+impl<P: Pixel> AnimatedPngEncoder<P> {
+    pub fn new(width: u32, height: u32) -> Result<Self, Error> {
+        Self::with_options(width, height, &PngOptions::default())
+    }
+
+    /// Options apply to every frame, metadata chunks are written once.
+    pub fn with_options(width: u32, height: u32, options: &PngOptions) -> Result<Self, Error> {
+        check_size(width, height)?;
+        options.validate()?;
+        // Frames would need one palette for all of them
+        if options.indexed {
+            return Err(Error::Unsupported("indexed animated PNG".to_string()));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            options: options.clone(),
+            num_plays: 0,
+            ihdr_chunk: None,
+            ancillary_chunks: ancillary_chunks(options)?,
+            default_frame: None,
+            frames: Vec::new(),
+            sequence_number: 0,
+            pixel: PhantomData,
+        })
+    }
+
+    /// Times to play the animation, 0 (the default) loops forever.
+    pub fn set_num_plays(&mut self, num_plays: u32) {
+        self.num_plays = num_plays;
+    }
+
+    /// Add a frame at the offset given by `control`, inside the canvas.
+    /// The first frame must have the size of the canvas and no offset.
+    pub fn add_frame(
+        &mut self,
+        image: &ImageBuffer<P>,
+        control: FrameControl,
+    ) -> Result<(), Error> {
+        let (width, height) = image.dimensions();
+        let is_default = self.default_frame.is_none();
+        let fits = if is_default {
+            control.x_offset == 0
+                && control.y_offset == 0
+                && width == self.width
+                && height == self.height
+        } else {
+            control.x_offset as u64 + width as u64 <= self.width as u64
+                && control.y_offset as u64 + height as u64 <= self.height as u64
+        };
+        if !fits {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        let scanlines = Scanlines::from_buffer(image, &self.options)?;
+        let data = compress_image(&scanlines, width, height, &self.options);
+        let fctl_chunk = Chunk {
+            length: 26,
+            name: *b"fcTL",
+            payload: FCTL {
+                sequence_number: self.next_sequence_number(),
+                width,
+                height,
+                control,
+            },
+        };
+
+        if is_default {
+            self.ihdr_chunk = Some(Chunk {
+                length: 13,
+                name: *b"IHDR",
+                payload: IHDR::new(
+                    width,
+                    height,
+                    scanlines.depth,
+                    scanlines.color_type,
+                    self.options.interlace,
+                ),
+            });
+            let idat_chunks = IDAT::split(&data, self.options.chunk_size);
+            self.default_frame = Some((fctl_chunk, idat_chunks));
+        } else {
+            // Leave room for the sequence number
+            let chunk_size = self.options.chunk_size.min(MAX_SIZE - 4) as usize;
+            let fdat_chunks = data
+                .chunks(chunk_size)
+                .map(|data| Chunk {
+                    length: data.len() as u32 + 4,
+                    name: *b"fdAT",
+                    payload: FDAT {
+                        sequence_number: self.next_sequence_number(),
+                        data: data.to_vec(),
+                    },
+                })
+                .collect();
+            self.frames.push((fctl_chunk, fdat_chunks));
+        }
+
+        Ok(())
+    }
+
+    fn next_sequence_number(&mut self) -> u32 {
+        self.sequence_number += 1;
+        self.sequence_number - 1
+    }
+}
+
+impl<P: Pixel> Serializable for AnimatedPngEncoder<P> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let (ihdr_chunk, (default_fctl_chunk, idat_chunks)) =
+            match (&self.ihdr_chunk, &self.default_frame) {
+                (Some(ihdr_chunk), Some(default_frame)) => (ihdr_chunk, default_frame),
+                _ => {
+                    return Err(Error::Unsupported(
+                        "animated PNG without frames".to_string(),
+                    ))
+                }
+            };
+
+        writer.write_all(&SIGNATURE)?;
+        ihdr_chunk.serialize(writer)?;
+        Chunk {
+            length: 8,
+            name: *b"acTL",
+            payload: ACTL {
+                num_frames: self.frames.len() as u32 + 1,
+                num_plays: self.num_plays,
+            },
+        }
+        .serialize(writer)?;
+        for chunk in self.ancillary_chunks.iter() {
+            chunk.serialize(writer)?;
+        }
+        default_fctl_chunk.serialize(writer)?;
+        for chunk in idat_chunks.iter() {
+            chunk.serialize(writer)?;
+        }
+        for (fctl_chunk, fdat_chunks) in self.frames.iter() {
+            fctl_chunk.serialize(writer)?;
+            for chunk in fdat_chunks.iter() {
+                chunk.serialize(writer)?;
+            }
+        }
+        Chunk {
+            length: 0,
+            name: *b"IEND",
+            payload: IEND {},
+        }
+        .serialize(writer)
+    }
+}
+
+impl<P: Pixel> Image for AnimatedPngEncoder<P> {}
+
+impl Serializable for ACTL {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.num_frames.to_be_bytes())?;
+        writer.write_all(&self.num_plays.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for FCTL {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let control = &self.control;
+        writer.write_all(&self.sequence_number.to_be_bytes())?;
+        writer.write_all(&self.width.to_be_bytes())?;
+        writer.write_all(&self.height.to_be_bytes())?;
+        writer.write_all(&control.x_offset.to_be_bytes())?;
+        writer.write_all(&control.y_offset.to_be_bytes())?;
+        writer.write_all(&control.delay_num.to_be_bytes())?;
+        writer.write_all(&control.delay_den.to_be_bytes())?;
+        writer.write_all(&[control.dispose as u8, control.blend as u8])?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for FDAT {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.sequence_number.to_be_bytes())?;
+        writer.write_all(&self.data)?;
+
+        Ok(())
+    }
+}
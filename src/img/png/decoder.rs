@@ -0,0 +1,349 @@
+use super::filter::unfilter;
+use super::inflate::decompress;
+use super::png::{ADAM7, MAX_SIZE, SIGNATURE};
+use super::{DynamicImage, ImageBuffer};
+use crate::checksum::{Checksum, Crc32};
+use crate::Error;
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: u8,
+    color_type: u8,
+    interlace: bool,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != 13 {
+            return Err(Error::Decoding("IHDR chunk must be 13 bytes".to_string()));
+        }
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let (depth, color_type) = (data[8], data[9]);
+        if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+            return Err(Error::Decoding(format!(
+                "invalid image dimensions {}x{}",
+                width, height
+            )));
+        }
+        // Allowed bit depths of each color type
+        match (color_type, depth) {
+            (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => {}
+            (2, 8) | (2, 16) | (4, 8) | (4, 16) | (6, 8) | (6, 16) => {}
+            (3, 1) | (3, 2) | (3, 4) | (3, 8) => {}
+            _ => {
+                return Err(Error::Decoding(format!(
+                    "invalid bit depth {} for color type {}",
+                    depth, color_type
+                )))
+            }
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(Error::Decoding(
+                "unknown compression or filter method".to_string(),
+            ));
+        }
+        if data[12] > 1 {
+            return Err(Error::Decoding(format!(
+                "unknown interlace method {}",
+                data[12]
+            )));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+            color_type,
+            interlace: data[12] == 1,
+        })
+    }
+
+    // Samples per pixel as stored, a palette index counts as one
+    fn channel(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    // Bytes of a scanline of the given width, without the filter byte
+    fn row_size(&self, width: usize) -> usize {
+        (width * self.channel() * self.depth as usize).div_ceil(8)
+    }
+
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlace {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        }
+    }
+
+    // Bytes of all scanlines with their filter bytes
+    fn data_size(&self) -> u64 {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut size = 0;
+        for &(x0, y0, dx, dy) in self.passes().iter() {
+            let pass_width = (width + dx - 1 - x0) / dx;
+            let pass_height = (height + dy - 1 - y0) / dy;
+            if pass_width > 0 {
+                size += pass_height as u64 * (self.row_size(pass_width) as u64 + 1);
+            }
+        }
+
+        size
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, Error> {
+    if bytes.len() < SIGNATURE.len() || bytes[..SIGNATURE.len()] != SIGNATURE {
+        return Err(Error::Decoding("not a PNG file".to_string()));
+    }
+    let mut position = SIGNATURE.len();
+
+    let (name, data) = read_chunk(bytes, &mut position)?;
+    if &name != b"IHDR" {
+        return Err(Error::Decoding("IHDR must be the first chunk".to_string()));
+    }
+    let header = Header::parse(data)?;
+
+    let mut palette: Option<&[u8]> = None;
+    let mut transparency: Option<&[u8]> = None;
+    let mut compressed = Vec::new();
+    loop {
+        let (name, data) = read_chunk(bytes, &mut position)?;
+        match &name {
+            b"IHDR" => return Err(Error::Decoding("duplicate IHDR chunk".to_string())),
+            b"PLTE" => {
+                if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+                    return Err(Error::Decoding("invalid PLTE chunk".to_string()));
+                }
+                palette = Some(data);
+            }
+            b"tRNS" => transparency = Some(data),
+            // Image data may be split across several chunks
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // Only ancillary chunks, whose first letter is lowercase, can be skipped
+            _ if name[0] & 0x20 == 0 => {
+                return Err(Error::Unsupported(format!(
+                    "critical PNG chunk {}",
+                    String::from_utf8_lossy(&name)
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    if compressed.is_empty() {
+        return Err(Error::Decoding("missing IDAT chunk".to_string()));
+    }
+    // Inflate no more than the header allows, however well the data compresses
+    let limit = header.data_size().min(usize::MAX as u64) as usize;
+    let samples = read_samples(&header, &decompress(&compressed, limit)?)?;
+
+    if header.color_type == 3 {
+        let palette = palette.ok_or_else(|| Error::Decoding("missing PLTE chunk".to_string()))?;
+        expand_palette(&header, &samples, palette, transparency)
+    } else {
+        expand_samples(&header, &samples, transparency)
+    }
+}
+
+// Split the next chunk into its name and data, checking its CRC
+fn read_chunk<'a>(bytes: &'a [u8], position: &mut usize) -> Result<([u8; 4], &'a [u8]), Error> {
+    let rest = &bytes[*position..];
+    if rest.len() < 12 {
+        return Err(Error::Decoding("unexpected end of file".to_string()));
+    }
+    let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+    if length > MAX_SIZE || rest.len() < 12 + length as usize {
+        return Err(Error::Decoding("unexpected end of file".to_string()));
+    }
+    let length = length as usize;
+    let name = [rest[4], rest[5], rest[6], rest[7]];
+
+    // CRC covers both name and data
+    let crc = &rest[8 + length..12 + length];
+    if Crc32::checksum(&rest[4..8 + length]) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+        return Err(Error::Decoding(format!(
+            "CRC mismatch in chunk {}",
+            String::from_utf8_lossy(&name)
+        )));
+    }
+
+    *position += 12 + length;
+    Ok((name, &rest[8..8 + length]))
+}
+
+// Unfilter every scanline and place its samples in the full image
+fn read_samples(header: &Header, data: &[u8]) -> Result<Vec<u16>, Error> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let channel = header.channel();
+    let bpp = (channel * header.depth as usize / 8).max(1);
+    let passes = header.passes();
+
+    // Check the length before allocating anything as large as the header claims
+    if (data.len() as u64) < header.data_size() {
+        return Err(Error::Decoding("image data is too short".to_string()));
+    }
+
+    let mut samples = vec![0_u16; width * height * channel];
+    let mut offset = 0;
+    for &(x0, y0, dx, dy) in passes.iter() {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        // Empty passes of small images have no scanlines at all
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let row_size = header.row_size(pass_width);
+        let mut previous = vec![0_u8; row_size];
+        let mut current = vec![0_u8; row_size];
+        for row in 0..pass_height {
+            current.copy_from_slice(&data[offset + 1..offset + 1 + row_size]);
+            unfilter(data[offset], bpp, &previous, &mut current)?;
+            offset += row_size + 1;
+
+            let y = y0 + row * dy;
+            for column in 0..pass_width {
+                let start = ((y * width) + x0 + column * dx) * channel;
+                for c in 0..channel {
+                    samples[start + c] = read_sample(&current, column * channel + c, header.depth);
+                }
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+    }
+
+    Ok(samples)
+}
+
+// Samples below 8 bits are packed from the most significant bit
+fn read_sample(row: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << depth) - 1)
+        }
+    }
+}
+
+// Indexed color becomes RGB, or RGBA if there is a tRNS chunk
+fn expand_palette(
+    header: &Header,
+    samples: &[u16],
+    palette: &[u8],
+    transparency: Option<&[u8]>,
+) -> Result<DynamicImage, Error> {
+    let channel = if transparency.is_some() { 4 } else { 3 };
+    let mut data = Vec::with_capacity(samples.len() * channel);
+    for &index in samples.iter() {
+        let index = index as usize;
+        if index * 3 >= palette.len() {
+            return Err(Error::Decoding(format!(
+                "palette index {} out of range",
+                index
+            )));
+        }
+        data.extend(
+            palette[index * 3..index * 3 + 3]
+                .iter()
+                .map(|&value| value as u16),
+        );
+        // Entries without alpha are opaque
+        if let Some(alpha) = transparency {
+            data.push(*alpha.get(index).unwrap_or(&255) as u16);
+        }
+    }
+
+    build(header, channel, false, data)
+}
+
+// Gray below 8 bits is scaled to the full 8-bit range
+// A tRNS color key turns into an alpha channel
+fn expand_samples(
+    header: &Header,
+    samples: &[u16],
+    transparency: Option<&[u8]>,
+) -> Result<DynamicImage, Error> {
+    let channel = header.channel();
+    let color_key = match (header.color_type, transparency) {
+        (0, Some(data)) | (2, Some(data)) => {
+            if data.len() != channel * 2 {
+                return Err(Error::Decoding("invalid tRNS chunk".to_string()));
+            }
+            let key: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(key)
+        }
+        _ => None,
+    };
+    let sixteen_bit = header.depth == 16;
+    let scale = if header.depth < 8 {
+        255 / ((1 << header.depth) - 1)
+    } else {
+        1
+    };
+    let opaque = if sixteen_bit {
+        u16::MAX
+    } else {
+        u8::MAX as u16
+    };
+
+    let out_channel = channel + color_key.is_some() as usize;
+    let mut data = Vec::with_capacity(samples.len() / channel * out_channel);
+    for pixel in samples.chunks_exact(channel) {
+        data.extend(pixel.iter().map(|sample| sample * scale));
+        if let Some(key) = &color_key {
+            data.push(if pixel == &key[..] { 0 } else { opaque });
+        }
+    }
+
+    build(header, out_channel, sixteen_bit, data)
+}
+
+fn build(
+    header: &Header,
+    channel: usize,
+    sixteen_bit: bool,
+    data: Vec<u16>,
+) -> Result<DynamicImage, Error> {
+    let (width, height) = (header.width, header.height);
+    let image = match (channel, sixteen_bit) {
+        (1, false) => {
+            ImageBuffer::from_channels(width, height, narrow(&data)).map(DynamicImage::Luma8)
+        }
+        (2, false) => {
+            ImageBuffer::from_channels(width, height, narrow(&data)).map(DynamicImage::LumaA8)
+        }
+        (3, false) => {
+            ImageBuffer::from_channels(width, height, narrow(&data)).map(DynamicImage::Rgb8)
+        }
+        (4, false) => {
+            ImageBuffer::from_channels(width, height, narrow(&data)).map(DynamicImage::Rgba8)
+        }
+        (1, true) => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Luma16),
+        (2, true) => ImageBuffer::from_channels(width, height, data).map(DynamicImage::LumaA16),
+        (3, true) => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Rgb16),
+        (4, true) => ImageBuffer::from_channels(width, height, data).map(DynamicImage::Rgba16),
+        _ => None,
+    };
+
+    image.ok_or_else(|| Error::Decoding("image data does not match the header".to_string()))
+}
+
+fn narrow(data: &[u16]) -> Vec<u8> {
+    data.iter().map(|&sample| sample as u8).collect()
+}
@@ -0,0 +1,785 @@
+use super::inflate::{CODE_LENGTH_ORDER, DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+use crate::checksum::{Adler32, Checksum};
+
+const WINDOW_SIZE: usize = 1 << 15;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const HASH_BITS: u32 = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// Short matches far away cost more bits than the literals they replace
+const TOO_FAR: usize = 4096;
+// Tokens collected before a block is closed and its Huffman codes are built
+const BLOCK_TOKENS: usize = 1 << 14;
+/// Largest block size, the most a stored block can hold
+pub const MAX_BLOCK_SIZE: usize = u16::MAX as usize;
+/// Smallest block size, room for a few of the longest matches
+pub const MIN_BLOCK_SIZE: usize = 1024;
+const END_OF_BLOCK: usize = 256;
+
+// Search effort of each level, the same trade-offs as zlib
+struct Config {
+    // Stop following the hash chain after this many candidates
+    max_chain: usize,
+    // Stop searching once a match is this long
+    nice_length: usize,
+    // Try the next position for a longer match, only if the current one is shorter than this
+    // Zero for greedy matching
+    max_lazy: usize,
+}
+
+const CONFIGS: [Config; 9] = [
+    Config {
+        max_chain: 4,
+        nice_length: 8,
+        max_lazy: 0,
+    },
+    Config {
+        max_chain: 8,
+        nice_length: 16,
+        max_lazy: 0,
+    },
+    Config {
+        max_chain: 32,
+        nice_length: 32,
+        max_lazy: 0,
+    },
+    Config {
+        max_chain: 16,
+        nice_length: 16,
+        max_lazy: 4,
+    },
+    Config {
+        max_chain: 32,
+        nice_length: 32,
+        max_lazy: 16,
+    },
+    Config {
+        max_chain: 128,
+        nice_length: 128,
+        max_lazy: 16,
+    },
+    Config {
+        max_chain: 256,
+        nice_length: 128,
+        max_lazy: 32,
+    },
+    Config {
+        max_chain: 1024,
+        nice_length: 258,
+        max_lazy: 128,
+    },
+    Config {
+        max_chain: 4096,
+        nice_length: 258,
+        max_lazy: 258,
+    },
+];
+
+/// Compress into a zlib stream, level 0 (stored) to 9 (smallest).
+/// Panic if `level` is larger than 9.
+pub fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    compress_with_block_size(data, level, MAX_BLOCK_SIZE)
+}
+
+/// Compress with every deflate block covering at most `block_size` bytes of `data`.
+/// Panic if `block_size` is out of `MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE`.
+pub fn compress_with_block_size(data: &[u8], level: u8, block_size: usize) -> Vec<u8> {
+    assert!((MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size));
+    let mut writer = BitWriter::new();
+    // 32K window, with the level hint in the upper bits of the flags
+    let cmf: u16 = 0x78;
+    let level_hint: u16 = match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let flg = level_hint << 6;
+    let flg = flg + 31 - (cmf * 256 + flg) % 31;
+    writer.write_bytes(&[cmf as u8, flg as u8]);
+
+    if level == 0 {
+        write_stored(&mut writer, data, block_size, true);
+    } else {
+        Compressor::new(data, &CONFIGS[level as usize - 1], block_size).run(&mut writer);
+    }
+
+    writer.write_bytes(&Adler32::checksum(data).to_be_bytes());
+    writer.finish()
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+struct Compressor<'a> {
+    data: &'a [u8],
+    config: &'a Config,
+    // Latest position of each hash plus one, zero for none
+    head: Vec<u32>,
+    // Previous position with the same hash, indexed by position within the window
+    prev: Vec<u32>,
+    tokens: Vec<Token>,
+    // First byte not yet covered by a closed block
+    block_start: usize,
+    block_size: usize,
+}
+
+impl<'a> Compressor<'a> {
+    fn new(data: &'a [u8], config: &'a Config, block_size: usize) -> Self {
+        Self {
+            data,
+            config,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW_SIZE],
+            tokens: Vec::with_capacity(BLOCK_TOKENS),
+            block_start: 0,
+            block_size,
+        }
+    }
+
+    fn run(mut self, writer: &mut BitWriter) {
+        let length = self.data.len();
+        let mut position = 0;
+        // Match found at the previous position, waiting to see if the current one is longer
+        let mut pending: Option<(usize, usize)> = None;
+        // Bytes covered by the collected tokens
+        let mut covered = self.block_start;
+
+        while position < length {
+            let pending_length = pending.map_or(0, |(length, _)| length);
+            let (match_length, distance) =
+                if pending.is_none() || pending_length < self.config.max_lazy {
+                    self.longest_match(position, pending_length)
+                } else {
+                    (0, 0)
+                };
+            self.insert(position);
+
+            match pending {
+                // The match here is longer, so the previous byte goes out alone
+                Some(_) if match_length > pending_length => {
+                    self.push(
+                        Token::Literal(self.data[position - 1]),
+                        &mut covered,
+                        writer,
+                    );
+                    pending = Some((match_length, distance));
+                    position += 1;
+                }
+                Some((pending_length, pending_distance)) => {
+                    self.push_match(pending_length, pending_distance, &mut covered, writer);
+                    // The match started one byte before, whose hash is already inserted
+                    let end = position - 1 + pending_length;
+                    for skipped in position + 1..end {
+                        self.insert(skipped);
+                    }
+                    pending = None;
+                    position = end;
+                }
+                None if match_length >= MIN_MATCH => {
+                    if self.config.max_lazy > 0 {
+                        pending = Some((match_length, distance));
+                        position += 1;
+                    } else {
+                        self.push_match(match_length, distance, &mut covered, writer);
+                        for skipped in position + 1..position + match_length {
+                            self.insert(skipped);
+                        }
+                        position += match_length;
+                    }
+                }
+                None => {
+                    self.push(Token::Literal(self.data[position]), &mut covered, writer);
+                    position += 1;
+                }
+            }
+        }
+        if let Some((pending_length, pending_distance)) = pending {
+            self.push_match(pending_length, pending_distance, &mut covered, writer);
+        }
+
+        self.flush_block(covered, true, writer);
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.data[position..position + MIN_MATCH];
+        let value = (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16;
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let hash = self.hash(position);
+        self.prev[position & WINDOW_MASK] = self.head[hash];
+        self.head[hash] = position as u32 + 1;
+    }
+
+    // Follow the hash chain for a match longer than `previous_length`
+    fn longest_match(&self, position: usize, previous_length: usize) -> (usize, usize) {
+        if position + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+        let max_length = MAX_MATCH.min(self.data.len() - position);
+        if previous_length >= max_length {
+            return (0, 0);
+        }
+        let current = &self.data[position..position + max_length];
+        let mut best_length = previous_length.max(MIN_MATCH - 1);
+        let mut best_distance = 0;
+
+        let mut candidate = self.head[self.hash(position)];
+        let mut chain = self.config.max_chain;
+        while candidate != 0 && chain > 0 {
+            let start = candidate as usize - 1;
+            let distance = position - start;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+
+            let earlier = &self.data[start..start + max_length];
+            // Only a longer match is interesting, so check its last byte first
+            if earlier[best_length] == current[best_length] {
+                let length = earlier
+                    .iter()
+                    .zip(current)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = distance;
+                    if length >= self.config.nice_length || length == max_length {
+                        break;
+                    }
+                }
+            }
+
+            // Entries overwritten by newer positions point forward, which ends the chain
+            let next = self.prev[start & WINDOW_MASK];
+            if next as usize >= candidate as usize {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        if best_distance == 0 || (best_length == MIN_MATCH && best_distance > TOO_FAR) {
+            return (0, 0);
+        }
+        (best_length, best_distance)
+    }
+
+    fn push_match(
+        &mut self,
+        length: usize,
+        distance: usize,
+        covered: &mut usize,
+        writer: &mut BitWriter,
+    ) {
+        let token = Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        };
+        self.push(token, covered, writer);
+    }
+
+    fn push(&mut self, token: Token, covered: &mut usize, writer: &mut BitWriter) {
+        let length = match token {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => length as usize,
+        };
+        // Close the block before it would cover more than the block size
+        if *covered + length - self.block_start > self.block_size {
+            self.flush_block(*covered, false, writer);
+        }
+        *covered += length;
+        self.tokens.push(token);
+        if self.tokens.len() >= BLOCK_TOKENS {
+            self.flush_block(*covered, false, writer);
+        }
+    }
+
+    // Write the collected tokens as whichever block type is smallest
+    fn flush_block(&mut self, end: usize, is_last: bool, writer: &mut BitWriter) {
+        let raw = &self.data[self.block_start..end];
+        let mut literal_frequencies = [0_u32; 286];
+        let mut distance_frequencies = [0_u32; 30];
+        for token in self.tokens.iter() {
+            match *token {
+                Token::Literal(byte) => literal_frequencies[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    literal_frequencies[257 + length_index(length)] += 1;
+                    distance_frequencies[distance_index(distance)] += 1;
+                }
+            }
+        }
+        literal_frequencies[END_OF_BLOCK] = 1;
+
+        let dynamic = DynamicHeader::new(&literal_frequencies, &distance_frequencies);
+        let fixed = fixed_lengths();
+        let dynamic_size = 3
+            + dynamic.size()
+            + data_size(
+                &literal_frequencies,
+                &distance_frequencies,
+                &dynamic.literal,
+                &dynamic.distance,
+            );
+        let fixed_size = 3 + data_size(
+            &literal_frequencies,
+            &distance_frequencies,
+            &fixed.0,
+            &fixed.1,
+        );
+        // The header rounded up to a byte, plus 4 bytes of lengths
+        let stored_size = 8 + 32 + 8 * raw.len() as u64;
+
+        if stored_size <= dynamic_size.min(fixed_size) {
+            write_stored(writer, raw, self.block_size, is_last);
+        } else if fixed_size <= dynamic_size {
+            writer.write_bits(is_last as u32, 1);
+            writer.write_bits(1, 2);
+            self.write_tokens(writer, &Codes::new(&fixed.0), &Codes::new(&fixed.1));
+        } else {
+            writer.write_bits(is_last as u32, 1);
+            writer.write_bits(2, 2);
+            dynamic.write(writer);
+            self.write_tokens(
+                writer,
+                &Codes::new(&dynamic.literal),
+                &Codes::new(&dynamic.distance),
+            );
+        }
+
+        self.tokens.clear();
+        self.block_start = end;
+    }
+
+    fn write_tokens(&self, writer: &mut BitWriter, literal: &Codes, distance: &Codes) {
+        for token in self.tokens.iter() {
+            match *token {
+                Token::Literal(byte) => literal.write(writer, byte as usize),
+                Token::Match {
+                    length,
+                    distance: match_distance,
+                } => {
+                    let index = length_index(length);
+                    literal.write(writer, 257 + index);
+                    writer.write_bits(
+                        (length - LENGTH_BASE[index]) as u32,
+                        LENGTH_EXTRA[index] as u32,
+                    );
+                    let index = distance_index(match_distance);
+                    distance.write(writer, index);
+                    writer.write_bits(
+                        (match_distance - DISTANCE_BASE[index]) as u32,
+                        DISTANCE_EXTRA[index] as u32,
+                    );
+                }
+            }
+        }
+        literal.write(writer, END_OF_BLOCK);
+    }
+}
+
+fn length_index(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn distance_index(distance: u16) -> usize {
+    DISTANCE_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+// Bits taken by the tokens of a block, extra bits included
+fn data_size(
+    literal_frequencies: &[u32],
+    distance_frequencies: &[u32],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) -> u64 {
+    let mut size = 0;
+    for (symbol, &frequency) in literal_frequencies.iter().enumerate() {
+        let extra = if symbol > END_OF_BLOCK {
+            LENGTH_EXTRA[symbol - 257]
+        } else {
+            0
+        };
+        size += frequency as u64 * (literal_lengths[symbol] + extra) as u64;
+    }
+    for (symbol, &frequency) in distance_frequencies.iter().enumerate() {
+        size += frequency as u64 * (distance_lengths[symbol] + DISTANCE_EXTRA[symbol]) as u64;
+    }
+    size
+}
+
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut literal = [0_u8; 288];
+    for (symbol, length) in literal.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (literal, [5; 30])
+}
+
+fn write_stored(writer: &mut BitWriter, data: &[u8], block_size: usize, is_last: bool) {
+    let mut chunks = data.chunks(block_size).peekable();
+    // Even empty data needs one block
+    if chunks.peek().is_none() {
+        writer.write_bits(is_last as u32, 1);
+        writer.write_bits(0, 2);
+        writer.align();
+        writer.write_bytes(&[0, 0, 0xff, 0xff]);
+        return;
+    }
+    while let Some(chunk) = chunks.next() {
+        let last_chunk = is_last && chunks.peek().is_none();
+        writer.write_bits(last_chunk as u32, 1);
+        writer.write_bits(0, 2);
+        writer.align();
+        let length = chunk.len() as u16;
+        writer.write_bytes(&length.to_le_bytes());
+        writer.write_bytes(&(!length).to_le_bytes());
+        writer.write_bytes(chunk);
+    }
+}
+
+// Code lengths of a dynamic block, and how they are stored in its header
+struct DynamicHeader {
+    literal: Vec<u8>,
+    distance: Vec<u8>,
+    code_length_lengths: Vec<u8>,
+    // Run-length coded sequence of code lengths, with extra bits
+    runs: Vec<(u8, u8)>,
+    code_length_num: usize,
+}
+
+impl DynamicHeader {
+    fn new(literal_frequencies: &[u32], distance_frequencies: &[u32]) -> Self {
+        let literal = huffman_lengths(literal_frequencies, 15);
+        let mut distance = huffman_lengths(distance_frequencies, 15);
+        // Some decoders reject a block without any distance code
+        if distance.iter().all(|&length| length == 0) {
+            distance[0] = 1;
+        }
+
+        let literal_num = 257.max(last_used(&literal));
+        let distance_num = 1.max(last_used(&distance));
+        let mut lengths = literal[..literal_num].to_vec();
+        lengths.extend_from_slice(&distance[..distance_num]);
+        let runs = run_lengths(&lengths);
+
+        let mut frequencies = [0_u32; 19];
+        for &(symbol, _) in runs.iter() {
+            frequencies[symbol as usize] += 1;
+        }
+        let code_length_lengths = huffman_lengths(&frequencies, 7);
+        let code_length_num = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&symbol| code_length_lengths[symbol] != 0)
+                .map_or(0, |index| index + 1),
+        );
+
+        Self {
+            literal: resize(literal, 286),
+            distance: resize(distance, 30),
+            code_length_lengths,
+            runs,
+            code_length_num,
+        }
+    }
+
+    // Bits of the header after the block type
+    fn size(&self) -> u64 {
+        let mut size = 5 + 5 + 4 + 3 * self.code_length_num as u64;
+        for &(symbol, _) in self.runs.iter() {
+            size += self.code_length_lengths[symbol as usize] as u64
+                + match symbol {
+                    16 => 2,
+                    17 => 3,
+                    18 => 7,
+                    _ => 0,
+                };
+        }
+        size
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        let literal_num = 257.max(last_used(&self.literal));
+        let distance_num = 1.max(last_used(&self.distance));
+        writer.write_bits((literal_num - 257) as u32, 5);
+        writer.write_bits((distance_num - 1) as u32, 5);
+        writer.write_bits((self.code_length_num - 4) as u32, 4);
+        for &symbol in CODE_LENGTH_ORDER.iter().take(self.code_length_num) {
+            writer.write_bits(self.code_length_lengths[symbol] as u32, 3);
+        }
+
+        let codes = Codes::new(&self.code_length_lengths);
+        for &(symbol, extra) in self.runs.iter() {
+            codes.write(writer, symbol as usize);
+            match symbol {
+                16 => writer.write_bits(extra as u32, 2),
+                17 => writer.write_bits(extra as u32, 3),
+                18 => writer.write_bits(extra as u32, 7),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn last_used(lengths: &[u8]) -> usize {
+    lengths
+        .iter()
+        .rposition(|&length| length != 0)
+        .map_or(0, |index| index + 1)
+}
+
+fn resize(mut lengths: Vec<u8>, size: usize) -> Vec<u8> {
+    lengths.resize(size, 0);
+    lengths
+}
+
+// Code 16 repeats the previous length 3-6 times, 17 and 18 repeat zero 3-10 and 11-138 times
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut index = 0;
+    while index < lengths.len() {
+        let length = lengths[index];
+        let mut count = 1;
+        while index + count < lengths.len() && lengths[index + count] == length {
+            count += 1;
+        }
+        index += count;
+
+        if length == 0 {
+            while count >= 11 {
+                let repeat = count.min(138);
+                runs.push((18, (repeat - 11) as u8));
+                count -= repeat;
+            }
+            if count >= 3 {
+                runs.push((17, (count - 3) as u8));
+                count = 0;
+            }
+        } else {
+            runs.push((length, 0));
+            count -= 1;
+            while count >= 3 {
+                let repeat = count.min(6);
+                runs.push((16, (repeat - 3) as u8));
+                count -= repeat;
+            }
+        }
+        runs.extend(std::iter::repeat_n((length, 0), count));
+    }
+    runs
+}
+
+// Huffman code lengths no longer than `max_bits`
+// Unused symbols get zero, a single used symbol gets one bit
+fn huffman_lengths(frequencies: &[u32], max_bits: usize) -> Vec<u8> {
+    let mut lengths = vec![0_u8; frequencies.len()];
+    let mut symbols: Vec<usize> = (0..frequencies.len())
+        .filter(|&symbol| frequencies[symbol] > 0)
+        .collect();
+    match symbols.len() {
+        0 => return lengths,
+        1 => {
+            lengths[symbols[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+    symbols.sort_by_key(|&symbol| frequencies[symbol]);
+
+    // Merged nodes are created in increasing weight, so two sorted queues
+    // of leaves and merged nodes are enough to always find the lightest two
+    let leaf_num = symbols.len();
+    let mut weights: Vec<u64> = symbols
+        .iter()
+        .map(|&symbol| frequencies[symbol] as u64)
+        .collect();
+    let mut parents = vec![0_usize; 2 * leaf_num - 1];
+    let (mut next_leaf, mut next_node) = (0, leaf_num);
+    for node in leaf_num..2 * leaf_num - 1 {
+        let mut children = [0; 2];
+        for child in children.iter_mut() {
+            if next_leaf < leaf_num
+                && (next_node >= node || weights[next_leaf] <= weights[next_node])
+            {
+                *child = next_leaf;
+                next_leaf += 1;
+            } else {
+                *child = next_node;
+                next_node += 1;
+            }
+        }
+        weights.push(weights[children[0]] + weights[children[1]]);
+        parents[children[0]] = node;
+        parents[children[1]] = node;
+    }
+
+    // Depth of every node, from the root down
+    let mut depths = vec![0_usize; 2 * leaf_num - 1];
+    for node in (0..2 * leaf_num - 2).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    // Clamp to `max_bits`, then lengthen shorter codes until the code is complete again
+    let mut counts = vec![0_usize; max_bits + 1];
+    for &depth in depths[..leaf_num].iter() {
+        counts[depth.min(max_bits)] += 1;
+    }
+    let mut total: usize = (1..=max_bits)
+        .map(|bits| counts[bits] << (max_bits - bits))
+        .sum();
+    while total > 1 << max_bits {
+        counts[max_bits] -= 1;
+        for bits in (1..max_bits).rev() {
+            if counts[bits] > 0 {
+                counts[bits] -= 1;
+                counts[bits + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    // Rarest symbols get the longest codes
+    let mut rarest = symbols.iter();
+    for bits in (1..=max_bits).rev() {
+        for _ in 0..counts[bits] {
+            lengths[*rarest.next().unwrap()] = bits as u8;
+        }
+    }
+    lengths
+}
+
+// Canonical codes, bit reversed since deflate packs them from the most significant bit
+struct Codes {
+    codes: Vec<u16>,
+    lengths: Vec<u8>,
+}
+
+impl Codes {
+    fn new(lengths: &[u8]) -> Self {
+        let mut count = [0_u16; 16];
+        for &length in lengths.iter() {
+            count[length as usize] += 1;
+        }
+        count[0] = 0;
+        let mut next_code = [0_u16; 16];
+        let mut code = 0;
+        for length in 1..16 {
+            code = (code + count[length - 1]) << 1;
+            next_code[length] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&length| {
+                if length == 0 {
+                    return 0;
+                }
+                let code = next_code[length as usize];
+                next_code[length as usize] += 1;
+                code.reverse_bits() >> (16 - length)
+            })
+            .collect();
+
+        Self {
+            codes,
+            lengths: lengths.to_vec(),
+        }
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        writer.write_bits(self.codes[symbol] as u32, self.lengths[symbol] as u32);
+    }
+}
+
+// Pack bits starting from the least significant bit of each byte
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            output: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Pad with zeros to the next byte boundary
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+    }
+
+    // Must be aligned before
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.align();
+        self.output.extend_from_slice(bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::inflate::decompress;
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let mut data: Vec<u8> = (0..100_000_u64).map(|i| (i * i / 7 % 251) as u8).collect();
+        data.extend(std::iter::repeat_n(42, 70_000));
+        for level in 0..=9 {
+            let compressed = compress(&data, level);
+            assert_eq!(data, decompress(&compressed, data.len()).unwrap(), "level {}", level);
+            let compressed = compress_with_block_size(&data, level, MIN_BLOCK_SIZE);
+            assert_eq!(data, decompress(&compressed, data.len()).unwrap(), "level {}", level);
+        }
+        assert_eq!(Vec::<u8>::new(), decompress(&compress(&[], 6), 0).unwrap());
+    }
+
+    #[test]
+    fn test_huffman_lengths_limit() {
+        // Fibonacci frequencies give the deepest possible tree
+        let mut frequencies = vec![1_u32, 1];
+        while frequencies.len() < 30 {
+            let next = frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2];
+            frequencies.push(next);
+        }
+        let lengths = huffman_lengths(&frequencies, 15);
+        assert!(lengths.iter().all(|&length| (1..=15).contains(&length)));
+        let kraft: u32 = lengths.iter().map(|&length| 1 << (15 - length)).sum();
+        assert_eq!(1 << 15, kraft);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::Error;
+
+/// Prediction subtracted from every byte of a scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+const FILTER_TYPES: [FilterType; 5] = [
+    FilterType::None,
+    FilterType::Sub,
+    FilterType::Up,
+    FilterType::Average,
+    FilterType::Paeth,
+];
+
+impl FilterType {
+    fn from_u8(value: u8) -> Option<Self> {
+        FILTER_TYPES.get(value as usize).copied()
+    }
+}
+
+/// How the encoder chooses the filter type of each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// The same filter type for every scanline
+    Fixed(FilterType),
+    /// Per scanline, the type with the minimum sum of absolute differences
+    /// Indexed and sub-byte images always use `None`
+    Adaptive,
+    /// Compress the image with every fixed type and with `Adaptive`, keep the smallest
+    BruteForce,
+}
+
+// Filter every scanline, each prefixed by its filter type
+// `BruteForce` is left to the caller, here it falls back to `Adaptive`
+pub fn filter_image(data: &[u8], row_size: usize, bpp: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / row_size.max(1));
+    let zeros = vec![0_u8; row_size];
+    let mut previous: &[u8] = &zeros;
+    for current in data.chunks_exact(row_size) {
+        let filter = match strategy {
+            FilterStrategy::Fixed(filter) => filter,
+            _ => adaptive(bpp, previous, current),
+        };
+        filter_row(filter, bpp, previous, current, &mut output);
+        previous = current;
+    }
+    output
+}
+
+// Pick the filter whose output bytes, read as signed, are closest to zero
+fn adaptive(bpp: usize, previous: &[u8], current: &[u8]) -> FilterType {
+    let mut best = (FilterType::None, u64::MAX);
+    for &filter in FILTER_TYPES.iter() {
+        let mut sum = 0;
+        for i in 0..current.len() {
+            let residual = current[i].wrapping_sub(predict(filter, bpp, previous, current, i));
+            sum += (residual as i8).unsigned_abs() as u64;
+        }
+        if sum < best.1 {
+            best = (filter, sum);
+        }
+    }
+    best.0
+}
+
+fn filter_row(
+    filter: FilterType,
+    bpp: usize,
+    previous: &[u8],
+    current: &[u8],
+    output: &mut Vec<u8>,
+) {
+    output.push(filter as u8);
+    for i in 0..current.len() {
+        output.push(current[i].wrapping_sub(predict(filter, bpp, previous, current, i)));
+    }
+}
+
+// Prediction of byte `i` from the unfiltered bytes around it
+fn predict(filter: FilterType, bpp: usize, previous: &[u8], current: &[u8], i: usize) -> u8 {
+    let (left, upper_left) = if i >= bpp {
+        (current[i - bpp], previous[i - bpp])
+    } else {
+        (0, 0)
+    };
+    let above = previous[i];
+    match filter {
+        FilterType::None => 0,
+        FilterType::Sub => left,
+        FilterType::Up => above,
+        FilterType::Average => ((left as u16 + above as u16) / 2) as u8,
+        FilterType::Paeth => paeth(left, above, upper_left),
+    }
+}
+
+// Reverse the filter of one scanline in place
+// `bpp` is the number of bytes per complete pixel, at least 1
+// `previous` is all zeros for the first scanline
+pub fn unfilter(filter: u8, bpp: usize, previous: &[u8], current: &mut [u8]) -> Result<(), Error> {
+    let filter = FilterType::from_u8(filter)
+        .ok_or_else(|| Error::Decoding(format!("unknown PNG filter type {}", filter)))?;
+    match filter {
+        FilterType::None => {}
+        FilterType::Sub => {
+            for i in bpp..current.len() {
+                current[i] = current[i].wrapping_add(current[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for (byte, above) in current.iter_mut().zip(previous) {
+                *byte = byte.wrapping_add(*above);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..current.len() {
+                let left = if i >= bpp { current[i - bpp] } else { 0 };
+                let average = (left as u16 + previous[i] as u16) / 2;
+                current[i] = current[i].wrapping_add(average as u8);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..current.len() {
+                let (left, upper_left) = if i >= bpp {
+                    (current[i - bpp], previous[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                current[i] = current[i].wrapping_add(paeth(left, previous[i], upper_left));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Pick whichever neighbour is closest to `left + above - upper_left`
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_above = (estimate - above as i16).abs();
+    let distance_upper_left = (estimate - upper_left as i16).abs();
+    if distance_left <= distance_above && distance_left <= distance_upper_left {
+        left
+    } else if distance_above <= distance_upper_left {
+        above
+    } else {
+        upper_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(10, paeth(10, 20, 20));
+        assert_eq!(20, paeth(10, 20, 10));
+        assert_eq!(30, paeth(10, 30, 0));
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let data: Vec<u8> = (0..60_u32).map(|i| (i * i % 251) as u8).collect();
+        for &filter in FILTER_TYPES.iter() {
+            let filtered = filter_image(&data, 12, 3, FilterStrategy::Fixed(filter));
+            let mut previous = vec![0; 12];
+            for (row, expected) in filtered.chunks_exact(13).zip(data.chunks_exact(12)) {
+                assert_eq!(filter as u8, row[0]);
+                let mut current = row[1..].to_vec();
+                unfilter(row[0], 3, &previous, &mut current).unwrap();
+                assert_eq!(expected, &current[..]);
+                previous = current;
+            }
+        }
+    }
+
+    #[test]
+    fn test_unfilter_sub() {
+        let mut row = [1, 2, 3, 4, 5, 6];
+        unfilter(1, 2, &[0; 6], &mut row).unwrap();
+        assert_eq!([1, 2, 4, 6, 9, 12], row);
+    }
+}
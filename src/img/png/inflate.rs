@@ -0,0 +1,355 @@
+use crate::checksum::{Adler32, Checksum};
+use crate::Error;
+
+// Base lengths and extra bits of length symbols 257..=285
+pub const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// Base distances and extra bits of distance symbols 0..=29
+pub const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Order in which the code lengths of the code length alphabet are stored
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MAX_CODE_LENGTH: usize = 15;
+
+/// Decompress a zlib stream, checking its header and Adler-32 checksum.
+/// Fail as soon as the output grows beyond `limit` bytes.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    if data.len() < 2 {
+        return Err(corrupt("zlib stream is too short"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+        return Err(corrupt("zlib stream is not deflate compressed"));
+    }
+    if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(corrupt("zlib header check failed"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(corrupt("zlib preset dictionary is not allowed"));
+    }
+
+    let (output, consumed) = inflate(&data[2..], limit)?;
+    let trailer = &data[2 + consumed..];
+    if trailer.len() < 4 {
+        return Err(corrupt("missing Adler-32 checksum"));
+    }
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if Adler32::checksum(&output) != expected {
+        return Err(corrupt("Adler-32 checksum mismatch"));
+    }
+
+    Ok(output)
+}
+
+/// Inflate a raw deflate stream of at most `limit` bytes.
+/// Return the data and the number of bytes the stream takes up.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), Error> {
+    let mut reader = BitReader::new(data);
+    let mut output = Vec::with_capacity(data.len().saturating_mul(2).min(limit));
+
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut output, limit)?,
+            1 => {
+                let (literal, distance) = fixed_huffman()?;
+                inflate_huffman(&mut reader, &mut output, limit, &literal, &distance)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_huffman(&mut reader)?;
+                inflate_huffman(&mut reader, &mut output, limit, &literal, &distance)?;
+            }
+            _ => return Err(corrupt("invalid deflate block type")),
+        }
+        if is_last {
+            break;
+        }
+    }
+
+    reader.align();
+    Ok((output, reader.position()))
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>, limit: usize) -> Result<(), Error> {
+    reader.align();
+    let length = reader.bits(16)?;
+    let complement = reader.bits(16)?;
+    if length != !complement & 0xffff {
+        return Err(corrupt("stored block length check failed"));
+    }
+    if output.len() + length as usize > limit {
+        return Err(too_long(limit));
+    }
+    reader.read_bytes(length as usize, output)
+}
+
+fn inflate_huffman(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+    literal: &Huffman,
+    distance: &Huffman,
+) -> Result<(), Error> {
+    loop {
+        let symbol = literal.decode(reader)? as usize;
+        if symbol < 256 {
+            if output.len() == limit {
+                return Err(too_long(limit));
+            }
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(corrupt("invalid length symbol"));
+        }
+        let length =
+            LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distance.decode(reader)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(corrupt("invalid distance symbol"));
+        }
+        let distance =
+            DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > output.len() {
+            return Err(corrupt("distance goes beyond the start of the stream"));
+        }
+        if output.len() + length > limit {
+            return Err(too_long(limit));
+        }
+
+        // Copy byte by byte since the source may overlap the destination
+        let start = output.len() - distance;
+        for i in 0..length {
+            output.push(output[start + i]);
+        }
+    }
+}
+
+fn fixed_huffman() -> Result<(Huffman, Huffman), Error> {
+    let mut lengths = [0_u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_huffman(reader: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literal_num = reader.bits(5)? as usize + 257;
+    let distance_num = reader.bits(5)? as usize + 1;
+    let code_length_num = reader.bits(4)? as usize + 4;
+    if literal_num > 286 || distance_num > 30 {
+        return Err(corrupt("too many Huffman codes"));
+    }
+
+    let mut code_lengths = [0_u8; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(code_length_num) {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_huffman = Huffman::new(&code_lengths)?;
+
+    // Literal and distance lengths are one run-length coded sequence
+    let mut lengths = vec![0_u8; literal_num + distance_num];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_huffman.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(corrupt("repeated code length without a previous one"));
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(corrupt("code lengths overflow the alphabet"));
+        }
+        lengths[index..index + repeat]
+            .iter_mut()
+            .for_each(|length| *length = value);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("missing end of block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_num])?,
+        Huffman::new(&lengths[literal_num..])?,
+    ))
+}
+
+// Canonical Huffman code decoded by one table lookup
+// Each entry packs the symbol and the length of its code
+struct Huffman {
+    table: Vec<u16>,
+    bits: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut count = [0_u16; MAX_CODE_LENGTH + 1];
+        for &length in lengths.iter() {
+            count[length as usize] += 1;
+        }
+        count[0] = 0;
+
+        // Incomplete codes are allowed, e.g. a single distance code
+        let mut left: i32 = 1;
+        for &number in count.iter().skip(1) {
+            left = (left << 1) - number as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut next_code = [0_u32; MAX_CODE_LENGTH + 1];
+        let mut code = 0;
+        for length in 1..=MAX_CODE_LENGTH {
+            code = (code + count[length - 1] as u32) << 1;
+            next_code[length] = code;
+        }
+
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut table = vec![0_u16; 1 << bits];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            // Codes are packed starting from their most significant bit
+            let reversed = code.reverse_bits() >> (32 - length as u32);
+            let entry = (symbol << 4) as u16 | length as u16;
+            for index in (reversed as usize..table.len()).step_by(1 << length) {
+                table[index] = entry;
+            }
+        }
+
+        Ok(Self { table, bits })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Error> {
+        let entry = self.table[reader.peek(self.bits) as usize];
+        let length = entry & 15;
+        if length == 0 {
+            return Err(corrupt("invalid Huffman code"));
+        }
+        reader.consume(length as u32)?;
+        Ok(entry >> 4)
+    }
+}
+
+// Read bits starting from the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 && self.position < self.data.len() {
+            self.buffer |= (self.data[self.position] as u64) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+    }
+
+    // Missing bits past the end read as zero, `consume` reports them
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.refill();
+        }
+        (self.buffer & ((1 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) -> Result<(), Error> {
+        if n > self.count {
+            return Err(corrupt("unexpected end of deflate stream"));
+        }
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, Error> {
+        let value = self.peek(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    // Skip to the next byte boundary
+    fn align(&mut self) {
+        let skipped = self.count % 8;
+        self.buffer >>= skipped;
+        self.count -= skipped;
+    }
+
+    // Must be aligned before
+    fn read_bytes(&mut self, mut length: usize, output: &mut Vec<u8>) -> Result<(), Error> {
+        while length > 0 && self.count > 0 {
+            output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+            length -= 1;
+        }
+        if self.position + length > self.data.len() {
+            return Err(corrupt("unexpected end of stored block"));
+        }
+        output.extend_from_slice(&self.data[self.position..self.position + length]);
+        self.position += length;
+        Ok(())
+    }
+
+    // Bytes consumed so far, once aligned
+    fn position(&self) -> usize {
+        self.position - (self.count / 8) as usize
+    }
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::Decoding(message.to_string())
+}
+
+fn too_long(limit: usize) -> Error {
+    Error::Decoding(format!("inflated data is longer than {} bytes", limit))
+}
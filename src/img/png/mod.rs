@@ -1,25 +1,67 @@
-mod adler;
-mod crc;
+mod ancillary;
+mod apng;
+mod decoder;
+mod deflate;
+mod filter;
+mod inflate;
+mod options;
+#[allow(clippy::module_inception)]
 mod png;
 
-use super::{Image, Serializable};
-use png::PNG;
+use super::{DynamicImage, Image, ImageBuffer, Serializable};
+use crate::color::{Luma, LumaA, Pixel, Rgb, Rgba};
+use crate::Error;
 
-use std::error::Error;
+pub use ancillary::{AncillaryChunk, PhysicalUnit, RenderingIntent};
+pub use apng::{AnimatedPngEncoder, BlendOp, DisposeOp, FrameControl};
+pub use filter::{FilterStrategy, FilterType};
+pub use options::PngOptions;
+pub use png::PNG;
+
+use std::fs::File;
+use std::io::{BufReader, Read};
 
 pub fn save_png<const WIDTH: usize, const HEIGHT: usize, const CHANNEL: usize>(
     path: &str,
     data: [[[u8; CHANNEL]; WIDTH]; HEIGHT],
-) -> Result<(), Box<dyn Error>> {
-    let data = data.iter().cloned().flatten().flatten().collect::<Vec<_>>();
-    let has_alpha = if CHANNEL == 3 { false } else { true };
-    PNG::new(WIDTH as u32, HEIGHT as u32, has_alpha, &data).dump(path)
+) -> Result<(), Error> {
+    let image = ImageBuffer::from(data);
+    match CHANNEL {
+        1 => save_png_buffer(path, &image.map(|pixel| Luma::from_slice(pixel))),
+        2 => save_png_buffer(path, &image.map(|pixel| LumaA::from_slice(pixel))),
+        3 => save_png_buffer(path, &image.map(|pixel| Rgb::from_slice(pixel))),
+        4 => save_png_buffer(path, &image.map(|pixel| Rgba::from_slice(pixel))),
+        _ => Err(Error::Unsupported(format!("PNG with {} channels", CHANNEL))),
+    }
 }
 
-pub trait ChecksumIterator {
-    fn new() -> Self;
+// Color type and bit depth are decided by the pixel type
+pub fn save_png_buffer<P: Pixel>(
+    path: &str,
+    image: &ImageBuffer<P>,
+) -> Result<(), Error> {
+    PNG::from_buffer(image)?.dump(path)
+}
 
-    fn iter(&mut self, bytes: &Vec<u8>);
+pub fn save_png_buffer_with_options<P: Pixel>(
+    path: &str,
+    image: &ImageBuffer<P>,
+    options: &PngOptions,
+) -> Result<(), Error> {
+    PNG::from_buffer_with_options(image, options)?.dump(path)
+}
+
+/// Decode a PNG file of any color type, bit depth and interlace method.
+///
+/// Palettes are expanded to `Rgb8`, or `Rgba8` when there is a tRNS chunk.
+/// Gray below 8 bits is scaled to `Luma8` and 16-bit files keep their depth.
+pub fn load_png(path: &str) -> Result<DynamicImage, Error> {
+    decode_png(&mut BufReader::new(File::open(path)?))
+}
 
-    fn get(&self) -> u32;
+/// Decode a PNG file from any reader.
+pub fn decode_png<R: Read>(reader: &mut R) -> Result<DynamicImage, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    decoder::decode(&bytes)
 }
@@ -0,0 +1,94 @@
+use super::deflate::{MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use super::png::MAX_SIZE;
+use super::{AncillaryChunk, FilterStrategy};
+use crate::Error;
+
+/// Settings of the PNG encoder, start from `PngOptions::default()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PngOptions {
+    /// Deflate level, from 0 (stored, fastest) to 9 (smallest)
+    pub level: u8,
+    /// Most bytes of filtered scanlines one deflate block covers, from 1024 to 65535
+    pub block_size: usize,
+    /// Most bytes of compressed data in one IDAT chunk, the rest goes to the following ones
+    pub chunk_size: u32,
+    /// Filter type of each scanline, which decides how well it compresses
+    /// `Adaptive` leaves indexed and sub-byte images unfiltered, where filtering rarely pays off
+    pub filter: FilterStrategy,
+    /// Bits per sample, `None` for the depth of the pixel type
+    /// Gray and indexed images can go down to 1, 2 or 4 bits
+    pub bit_depth: Option<u8>,
+    /// Store the distinct colors in a PLTE chunk and each pixel as an index
+    pub indexed: bool,
+    /// Store the pixels in seven Adam7 passes, so that viewers can show the image progressively
+    pub interlace: bool,
+    /// Metadata chunks written before the image data, in this order
+    pub chunks: Vec<AncillaryChunk>,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            block_size: MAX_BLOCK_SIZE,
+            chunk_size: 1 << 16,
+            filter: FilterStrategy::Adaptive,
+            bit_depth: None,
+            indexed: false,
+            interlace: false,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+impl PngOptions {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.level > 9 {
+            return Err(Error::Unsupported(format!(
+                "PNG compression level {}",
+                self.level
+            )));
+        }
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.block_size) {
+            return Err(Error::Unsupported(format!(
+                "deflate block size {}",
+                self.block_size
+            )));
+        }
+        if self.chunk_size == 0 || self.chunk_size > MAX_SIZE {
+            return Err(Error::Unsupported(format!(
+                "PNG chunk size {}",
+                self.chunk_size
+            )));
+        }
+        if let Some(depth) = self.bit_depth {
+            if ![1, 2, 4, 8, 16].contains(&depth) {
+                return Err(Error::Unsupported(format!("PNG bit depth {}", depth)));
+            }
+        }
+
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let name = chunk.name();
+            let others = &self.chunks[..index];
+            if !chunk.is_repeatable() && others.iter().any(|other| other.name() == name) {
+                return Err(Error::Unsupported(format!(
+                    "more than one PNG {} chunk",
+                    String::from_utf8_lossy(&name)
+                )));
+            }
+            // Either the profile or sRGB describes the color space
+            let conflict = match &name {
+                b"sRGB" => b"iCCP",
+                b"iCCP" => b"sRGB",
+                _ => continue,
+            };
+            if others.iter().any(|other| &other.name() == conflict) {
+                return Err(Error::Unsupported(
+                    "PNG with both sRGB and iCCP chunks".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,85 +1,497 @@
-use super::{Image, Serializable};
+// Chunk structs are named after their types, e.g. `IHDR`
+#![allow(clippy::upper_case_acronyms)]
 
-use super::{adler::AdlerIterator, crc, ChecksumIterator};
+use super::{Image, ImageBuffer, PngOptions, Serializable};
+use crate::color::{ColorType, Luma, LumaA, Pixel, Primitive, Rgb, Rgba};
+use crate::img::{check_data_size, check_dimensions};
+use crate::Error;
+
+use super::filter::{filter_image, FilterStrategy, FilterType};
+use super::deflate;
+use crate::checksum::{ChecksumWriter, Crc32};
+
+use std::collections::HashMap;
+use std::io::Write;
 
 pub struct PNG {
     magic_number: [u8; 8],
     ihdr_chunk: Chunk<IHDR>,
-    idat_chunk: Chunk<IDAT>,
+    ancillary_chunks: Vec<Chunk<Ancillary>>,
+    plte_chunk: Option<Chunk<PLTE>>,
+    trns_chunk: Option<Chunk<TRNS>>,
+    idat_chunks: Vec<Chunk<IDAT>>,
     iend_chunk: Chunk<IEND>,
 }
 
-struct Chunk<T: Serializable> {
-    length: u32,
-    name: [u8; 4],
-    payload: T,
+pub(super) struct Chunk<T: Serializable> {
+    pub(super) length: u32,
+    pub(super) name: [u8; 4],
+    pub(super) payload: T,
     // crc: u32,
 }
 
-struct IHDR {
+pub(super) struct IHDR {
     width: u32,
     height: u32,
     depth: u8,
-    alpha: u8,
+    color_type: u8,
     compression: u8,
     filter: u8,
     interlace: u8,
 }
 
-struct IDAT {
-    compression_prefix: [u8; 2],
-    data_blocks: Vec<DataBlock>,
-    // adler: u32,
+// RGB of each palette entry
+struct PLTE {
+    entries: Vec<u8>,
+}
+
+// Alpha of the leading palette entries, the rest are opaque
+struct TRNS {
+    alpha: Vec<u8>,
+}
+
+// Data of a metadata chunk, already encoded
+pub(super) struct Ancillary {
+    data: Vec<u8>,
+}
+
+// Part of the zlib stream of the filtered scanlines
+pub(super) struct IDAT {
+    pub(super) data: Vec<u8>,
 }
 
-struct DataBlock {
-    is_last: bool,
-    block_size: u16,
-    filter_prefix: [u8; 1],
+pub(super) struct IEND {}
+
+// Scanlines before filtering, samples packed at the bit depth of the file
+pub(super) struct Scanlines {
+    pub(super) color_type: u8,
+    pub(super) depth: u8,
+    row_size: usize,
     data: Vec<u8>,
+    palette: Option<Vec<Rgba<u8>>>,
 }
 
-struct IEND {}
+pub const SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+pub const MAX_SIZE: u32 = (1 << 31) - 1;
+
+// Starting column and row, then column and row step of each Adam7 pass
+pub const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
 
 impl PNG {
-    pub fn new(width: u32, height: u32, alpha: bool, data: &Vec<u8>) -> Self {
-        let idat_length = if alpha {
-            2 + height * (5 + width * 4 + 1) + 4
-        } else {
-            2 + height * (5 + width * 3 + 1) + 4
+    pub fn new(width: u32, height: u32, color_type: ColorType, data: &[u8]) -> Result<Self, Error> {
+        Self::with_options(width, height, color_type, data, &PngOptions::default())
+    }
+
+    /// Encode 8-bit channels, as gray or indexed with a lower bit depth if the options ask so.
+    pub fn with_options(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        data: &[u8],
+        options: &PngOptions,
+    ) -> Result<Self, Error> {
+        check_size(width, height)?;
+        options.validate()?;
+        check_data_size(width, height, color_type.channel_count(), data)?;
+
+        let scanlines = Scanlines::new(width, color_type, data, options)?;
+        Self::encode(width, height, scanlines, options)
+    }
+
+    pub fn from_buffer<P: Pixel>(image: &ImageBuffer<P>) -> Result<Self, Error> {
+        Self::from_buffer_with_options(image, &PngOptions::default())
+    }
+
+    /// Pixels with 16-bit channels are stored with bit depth 16.
+    pub fn from_buffer_with_options<P: Pixel>(
+        image: &ImageBuffer<P>,
+        options: &PngOptions,
+    ) -> Result<Self, Error> {
+        let (width, height) = image.dimensions();
+        check_size(width, height)?;
+        options.validate()?;
+        let scanlines = Scanlines::from_buffer(image, options)?;
+        Self::encode(width, height, scanlines, options)
+    }
+
+    fn encode(
+        width: u32,
+        height: u32,
+        scanlines: Scanlines,
+        options: &PngOptions,
+    ) -> Result<Self, Error> {
+        let data = compress_image(&scanlines, width, height, options);
+        let ancillary_chunks = ancillary_chunks(options)?;
+
+        let (plte_chunk, trns_chunk) = match &scanlines.palette {
+            Some(palette) => {
+                let entries: Vec<u8> = palette
+                    .iter()
+                    .flat_map(|color| color.0[..3].to_vec())
+                    .collect();
+                let alpha: Vec<u8> = palette
+                    .iter()
+                    .map(|color| color[3])
+                    .take_while(|&alpha| alpha != u8::MAX)
+                    .collect();
+                let plte_chunk = Chunk {
+                    length: entries.len() as u32,
+                    name: *b"PLTE",
+                    payload: PLTE { entries },
+                };
+                let trns_chunk = if alpha.is_empty() {
+                    None
+                } else {
+                    Some(Chunk {
+                        length: alpha.len() as u32,
+                        name: *b"tRNS",
+                        payload: TRNS { alpha },
+                    })
+                };
+                (Some(plte_chunk), trns_chunk)
+            }
+            None => (None, None),
         };
 
-        Self {
-            magic_number: [b'\x89', b'P', b'N', b'G', b'\r', b'\n', b'\x1a', b'\n'],
+        Ok(Self {
+            magic_number: SIGNATURE,
             ihdr_chunk: Chunk {
                 length: 13,
                 name: *b"IHDR",
-                payload: IHDR::new(width, height, alpha),
-            },
-            idat_chunk: Chunk {
-                length: idat_length,
-                name: *b"IDAT",
-                payload: IDAT::new(width, height, alpha, data),
+                payload: IHDR::new(
+                    width,
+                    height,
+                    scanlines.depth,
+                    scanlines.color_type,
+                    options.interlace,
+                ),
             },
+            ancillary_chunks,
+            plte_chunk,
+            trns_chunk,
+            idat_chunks: IDAT::split(&data, options.chunk_size),
             iend_chunk: Chunk {
                 length: 0,
                 name: *b"IEND",
                 payload: IEND {},
             },
+        })
+    }
+}
+
+pub(super) fn ancillary_chunks(options: &PngOptions) -> Result<Vec<Chunk<Ancillary>>, Error> {
+    let mut chunks = Vec::with_capacity(options.chunks.len());
+    for chunk in options.chunks.iter() {
+        let data = chunk.encode()?;
+        if data.len() > MAX_SIZE as usize {
+            return Err(Error::FormatLimit(format!(
+                "PNG {} chunk of {} bytes",
+                String::from_utf8_lossy(&chunk.name()),
+                data.len()
+            )));
         }
+        chunks.push(Chunk {
+            length: data.len() as u32,
+            name: chunk.name(),
+            payload: Ancillary { data },
+        });
     }
+    Ok(chunks)
 }
 
-impl Serializable for PNG {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+pub(super) fn check_size(width: u32, height: u32) -> Result<(), Error> {
+    check_dimensions(width, height)?;
+    // Size fields of PNG are limited to 31 bits
+    if width > MAX_SIZE || height > MAX_SIZE {
+        return Err(Error::FormatLimit(format!(
+            "PNG can not be larger than {} pixels",
+            MAX_SIZE
+        )));
+    }
+
+    Ok(())
+}
+
+fn color_type_code(color_type: ColorType) -> u8 {
+    match color_type {
+        ColorType::Luma => 0,
+        ColorType::Rgb => 2,
+        ColorType::LumaA => 4,
+        ColorType::Rgba => 6,
+    }
+}
+
+impl Scanlines {
+    fn new(
+        width: u32,
+        color_type: ColorType,
+        data: &[u8],
+        options: &PngOptions,
+    ) -> Result<Self, Error> {
+        if options.indexed {
+            Self::indexed(width, color_type, data, options.bit_depth)
+        } else {
+            Self::direct(width, color_type, data, options.bit_depth)
+        }
+    }
+
+    // Pixels with 16-bit channels keep bit depth 16
+    pub(super) fn from_buffer<P: Pixel>(
+        image: &ImageBuffer<P>,
+        options: &PngOptions,
+    ) -> Result<Self, Error> {
+        let width = image.width();
+        let channels = image.pixels().flat_map(|pixel| pixel.channels().iter());
+        if P::Subpixel::BITS != 16 {
+            let data: Vec<u8> = channels.map(|channel| channel.to_f64() as u8).collect();
+            return Self::new(width, P::COLOR_TYPE, &data, options);
+        }
+
+        if options.indexed || options.bit_depth.is_some_and(|depth| depth != 16) {
+            return Err(Error::Unsupported(
+                "PNG with 16-bit pixels must keep bit depth 16".to_string(),
+            ));
+        }
+        let data: Vec<u8> = channels
+            .flat_map(|channel| (channel.to_f64() as u16).to_be_bytes())
+            .collect();
+        Ok(Self {
+            color_type: color_type_code(P::COLOR_TYPE),
+            depth: 16,
+            row_size: width as usize * P::CHANNEL_COUNT * 2,
+            data,
+            palette: None,
+        })
+    }
+
+    // Gray may go below 8 bits, every other color type stays at 8
+    fn direct(
+        width: u32,
+        color_type: ColorType,
+        data: &[u8],
+        depth: Option<u8>,
+    ) -> Result<Self, Error> {
+        let width = width as usize;
+        let (depth, data) = match (depth.unwrap_or(8), color_type) {
+            (8, _) => (8, data.to_vec()),
+            (depth @ 1..=4, ColorType::Luma) => {
+                // Round to the nearest level the depth can express
+                let max = (1 << depth) - 1;
+                let levels: Vec<u8> = data
+                    .iter()
+                    .map(|&value| ((value as u32 * max + 127) / 255) as u8)
+                    .collect();
+                (depth, pack(&levels, width, depth))
+            }
+            (depth, _) => {
+                return Err(Error::Unsupported(format!(
+                    "{}-bit PNG from 8-bit {:?} pixels",
+                    depth, color_type
+                )))
+            }
+        };
+
+        Ok(Self {
+            color_type: color_type_code(color_type),
+            depth,
+            row_size: (width * color_type.channel_count() * depth as usize).div_ceil(8),
+            data,
+            palette: None,
+        })
+    }
+
+    // Collect the distinct colors into a palette
+    // Without an explicit depth, take the smallest one the palette fits in
+    fn indexed(
+        width: u32,
+        color_type: ColorType,
+        data: &[u8],
+        depth: Option<u8>,
+    ) -> Result<Self, Error> {
+        let width = width as usize;
+        let (palette, indices) = build_palette(color_type, data)?;
+        let depth = match depth {
+            Some(depth @ (1 | 2 | 4 | 8)) => depth,
+            Some(depth) => return Err(Error::Unsupported(format!("{}-bit indexed PNG", depth))),
+            None => [1, 2, 4, 8]
+                .iter()
+                .copied()
+                .find(|&depth| palette.len() <= 1 << depth)
+                .unwrap_or(8),
+        };
+        if palette.len() > 1 << depth {
+            return Err(Error::FormatLimit(format!(
+                "{} colors do not fit in a {}-bit palette",
+                palette.len(),
+                depth
+            )));
+        }
+
+        Ok(Self {
+            color_type: 3,
+            depth,
+            row_size: (width * depth as usize).div_ceil(8),
+            data: if depth == 8 {
+                indices
+            } else {
+                pack(&indices, width, depth)
+            },
+            palette: Some(palette),
+        })
+    }
+
+    // Filters work on whole bytes, at least one
+    fn bpp(&self) -> usize {
+        (self.pixel_bits() / 8).max(1)
+    }
+
+    fn pixel_bits(&self) -> usize {
+        let channel = match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        };
+        channel * self.depth as usize
+    }
+
+    // Reduced image of each non-empty Adam7 pass, packed the same way
+    fn interlace(&self, width: usize, height: usize) -> Vec<Self> {
+        let bits = self.pixel_bits();
+        let mut passes = Vec::new();
+        for &(x0, y0, dx, dy) in ADAM7.iter() {
+            let pass_width = (width + dx - 1 - x0) / dx;
+            let pass_height = (height + dy - 1 - y0) / dy;
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+
+            let row_size = (pass_width * bits).div_ceil(8);
+            let mut data = vec![0_u8; row_size * pass_height];
+            for (row, pass_row) in data.chunks_exact_mut(row_size).enumerate() {
+                let start = (y0 + row * dy) * self.row_size;
+                let source = &self.data[start..start + self.row_size];
+                for column in 0..pass_width {
+                    let x = x0 + column * dx;
+                    if bits >= 8 {
+                        let size = bits / 8;
+                        pass_row[column * size..(column + 1) * size]
+                            .copy_from_slice(&source[x * size..(x + 1) * size]);
+                    } else {
+                        // Sub-byte pixels are packed from the most significant bit
+                        let (from, to) = (x * bits, column * bits);
+                        let value = (source[from / 8] >> (8 - bits - from % 8)) & ((1 << bits) - 1);
+                        pass_row[to / 8] |= value << (8 - bits - to % 8);
+                    }
+                }
+            }
+            passes.push(Self {
+                color_type: self.color_type,
+                depth: self.depth,
+                row_size,
+                data,
+                palette: None,
+            });
+        }
+        passes
+    }
+}
 
-        bytes.extend(self.magic_number);
-        bytes.extend(self.ihdr_chunk.get_bytes());
-        bytes.extend(self.idat_chunk.get_bytes());
-        bytes.extend(self.iend_chunk.get_bytes());
+// Distinct colors as RGBA, and the index of every pixel
+// Translucent entries come first, so that tRNS can leave out the opaque ones
+fn build_palette(color_type: ColorType, data: &[u8]) -> Result<(Vec<Rgba<u8>>, Vec<u8>), Error> {
+    let channel = color_type.channel_count();
+    let mut lookup: HashMap<Rgba<u8>, usize> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(data.len() / channel);
+    for pixel in data.chunks_exact(channel) {
+        let color = match color_type {
+            ColorType::Luma => Luma::from_slice(pixel).to_rgba(),
+            ColorType::LumaA => LumaA::from_slice(pixel).to_rgba(),
+            ColorType::Rgb => Rgb::from_slice(pixel).to_rgba(),
+            ColorType::Rgba => Rgba::from_slice(pixel),
+        };
+        let index = match lookup.get(&color) {
+            Some(&index) => index,
+            None => {
+                if palette.len() == 256 {
+                    return Err(Error::FormatLimit(
+                        "more than 256 colors do not fit in a PNG palette".to_string(),
+                    ));
+                }
+                lookup.insert(color, palette.len());
+                palette.push(color);
+                palette.len() - 1
+            }
+        };
+        indices.push(index as u8);
+    }
 
-        bytes
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by_key(|&index| palette[index][3] == u8::MAX);
+    let mut remap = vec![0_u8; palette.len()];
+    for (new_index, &old_index) in order.iter().enumerate() {
+        remap[old_index] = new_index as u8;
+    }
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+    let palette = order.iter().map(|&index| palette[index]).collect();
+
+    Ok((palette, indices))
+}
+
+// Pack samples below 8 bits from the most significant bit
+// Every row starts on a new byte
+fn pack(samples: &[u8], width: usize, depth: u8) -> Vec<u8> {
+    let depth = depth as usize;
+    let row_size = (width * depth).div_ceil(8);
+    let mut data = vec![0_u8; samples.len() / width * row_size];
+    for (row, packed) in samples
+        .chunks_exact(width)
+        .zip(data.chunks_exact_mut(row_size))
+    {
+        for (x, &sample) in row.iter().enumerate() {
+            let bit = x * depth;
+            packed[bit / 8] |= sample << (8 - depth - bit % 8);
+        }
+    }
+    data
+}
+
+impl Serializable for PNG {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.magic_number)?;
+        self.ihdr_chunk.serialize(writer)?;
+        // Written first, so chunks required before PLTE and IDAT are in place
+        for chunk in self.ancillary_chunks.iter() {
+            if !chunk.follows_palette() {
+                chunk.serialize(writer)?;
+            }
+        }
+        if let Some(plte_chunk) = &self.plte_chunk {
+            plte_chunk.serialize(writer)?;
+        }
+        if let Some(trns_chunk) = &self.trns_chunk {
+            trns_chunk.serialize(writer)?;
+        }
+        for chunk in self.ancillary_chunks.iter() {
+            if chunk.follows_palette() {
+                chunk.serialize(writer)?;
+            }
+        }
+        for chunk in self.idat_chunks.iter() {
+            chunk.serialize(writer)?;
+        }
+        self.iend_chunk.serialize(writer)
     }
 }
 
@@ -89,126 +501,152 @@ impl<T> Serializable for Chunk<T>
 where
     T: Serializable,
 {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut crc_bytes = Vec::new();
-        crc_bytes.extend(self.name);
-        crc_bytes.extend(self.payload.get_bytes());
-        // Calculate CRC checksum
-        let crc = crc::calc(&crc_bytes).to_be_bytes();
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        // Calculate CRC checksum of name and payload on the fly
+        let mut crc_writer = ChecksumWriter::<_, Crc32>::new(writer);
+        crc_writer.write_all(&self.name)?;
+        self.payload.serialize(&mut crc_writer)?;
+        let crc = crc_writer.value();
+
+        writer.write_all(&crc.to_be_bytes())?;
 
-        [&self.length.to_be_bytes(), &crc_bytes[..], &crc].concat()
+        Ok(())
     }
 }
 
 impl IHDR {
-    fn new(width: u32, height: u32, alpha: bool) -> Self {
+    pub(super) fn new(width: u32, height: u32, depth: u8, color_type: u8, interlace: bool) -> Self {
         Self {
             width,
             height,
-            depth: 8,
-            alpha: if alpha { 6 } else { 2 },
+            depth,
+            color_type,
             compression: b'\0',
             filter: b'\0',
-            interlace: b'\0',
+            interlace: interlace as u8,
         }
     }
 }
 
 impl Serializable for IHDR {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(13);
-
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         // 32 bit for big-endian
-        bytes.extend(self.width.to_be_bytes());
-        bytes.extend(self.height.to_be_bytes());
+        writer.write_all(&self.width.to_be_bytes())?;
+        writer.write_all(&self.height.to_be_bytes())?;
         // others for little-endian
-        bytes.extend(self.depth.to_le_bytes());
-        bytes.extend(self.alpha.to_le_bytes());
-        bytes.extend(self.compression.to_le_bytes());
-        bytes.extend(self.filter.to_le_bytes());
-        bytes.extend(self.interlace.to_le_bytes());
+        writer.write_all(&[
+            self.depth,
+            self.color_type,
+            self.compression,
+            self.filter,
+            self.interlace,
+        ])?;
 
-        bytes
+        Ok(())
     }
 }
 
-impl IDAT {
-    fn new(width: u32, height: u32, alpha: bool, data: &Vec<u8>) -> Self {
-        // Get number and size of data block
-        let block_num = height;
-        let block_size: u16 = if alpha {
-            (width * 4) as u16
-        } else {
-            (width * 3) as u16
-        };
-        // Build data blocks
-        let mut data_blocks: Vec<DataBlock> = Vec::with_capacity(block_num as usize);
-        for i in 0..block_num {
-            let start_pos = (i * block_size as u32) as usize;
-            let end_pos = ((i + 1) * block_size as u32) as usize;
-            if i == block_num - 1 {
-                data_blocks.push(DataBlock {
-                    is_last: true,
-                    block_size,
-                    filter_prefix: *b"\0",
-                    data: data[start_pos..end_pos].iter().cloned().collect(),
-                });
-            } else {
-                data_blocks.push(DataBlock {
-                    is_last: false,
-                    block_size,
-                    filter_prefix: *b"\0",
-                    data: data[start_pos..end_pos].iter().cloned().collect(),
-                });
-            }
-        }
+impl Serializable for PLTE {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.entries)?;
 
-        Self {
-            compression_prefix: *b"\x78\x01",
-            data_blocks,
-        }
+        Ok(())
     }
 }
 
-impl Serializable for IDAT {
-    fn get_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        // Compression prefix
-        bytes.extend(self.compression_prefix);
-        // Data block and calculate adler checksum
-        let mut adler_iterator = AdlerIterator::new();
-        for data_block in self.data_blocks.iter() {
-            let data_block_bytes = data_block.get_bytes_with_adler(&mut adler_iterator);
-            bytes.extend(data_block_bytes);
-        }
-        // Adler checksum
-        bytes.extend(adler_iterator.get().to_be_bytes());
+impl Serializable for TRNS {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.alpha)?;
+
+        Ok(())
+    }
+}
+
+impl Chunk<Ancillary> {
+    // Background and histogram refer to the palette, so they go between PLTE and IDAT
+    fn follows_palette(&self) -> bool {
+        self.name == *b"bKGD" || self.name == *b"hIST"
+    }
+}
 
-        bytes
+impl Serializable for Ancillary {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.data)?;
+
+        Ok(())
     }
 }
 
-impl DataBlock {
-    // The adler checksum is from the part of data block
-    fn get_bytes_with_adler(&self, adler_iterator: &mut AdlerIterator) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity((6 + self.block_size) as usize);
+// Zlib stream of the whole image, passes are filtered on their own
+pub(super) fn compress_image(
+    scanlines: &Scanlines,
+    width: u32,
+    height: u32,
+    options: &PngOptions,
+) -> Vec<u8> {
+    let interlaced;
+    let passes = if options.interlace {
+        interlaced = scanlines.interlace(width as usize, height as usize);
+        &interlaced[..]
+    } else {
+        std::slice::from_ref(scanlines)
+    };
+    let compress = |strategy| {
+        let filtered: Vec<u8> = passes
+            .iter()
+            .flat_map(|pass| filter_image(&pass.data, pass.row_size, pass.bpp(), strategy))
+            .collect();
+        deflate::compress_with_block_size(&filtered, options.level, options.block_size)
+    };
 
-        bytes.push(if self.is_last { b'\x01' } else { b'\x00' });
-        bytes.extend((self.block_size + 1).to_le_bytes());
-        bytes.extend((!(self.block_size + 1)).to_le_bytes());
-        // Prepare the adler bytes and calculate the checksum
-        let mut adler_bytes = Vec::with_capacity((1 + self.block_size) as usize);
-        adler_bytes.extend(&self.filter_prefix);
-        adler_bytes.extend(&self.data);
-        adler_iterator.iter(&adler_bytes);
-        bytes.extend(adler_bytes);
+    match options.filter {
+        // Keep whichever candidate compresses best
+        FilterStrategy::BruteForce => [
+            FilterStrategy::Fixed(FilterType::None),
+            FilterStrategy::Fixed(FilterType::Sub),
+            FilterStrategy::Fixed(FilterType::Up),
+            FilterStrategy::Fixed(FilterType::Average),
+            FilterStrategy::Fixed(FilterType::Paeth),
+            FilterStrategy::Adaptive,
+        ]
+        .iter()
+        .map(|&strategy| compress(strategy))
+        .min_by_key(|compressed| compressed.len())
+        .unwrap(),
+        // Indexed and sub-byte samples rarely gain from filtering
+        FilterStrategy::Adaptive if scanlines.color_type == 3 || scanlines.depth < 8 => {
+            compress(FilterStrategy::Fixed(FilterType::None))
+        }
+        strategy => compress(strategy),
+    }
+}
+
+impl IDAT {
+    // Consecutive chunks of at most `chunk_size` bytes each
+    pub(super) fn split(data: &[u8], chunk_size: u32) -> Vec<Chunk<IDAT>> {
+        data.chunks(chunk_size as usize)
+            .map(|data| Chunk {
+                length: data.len() as u32,
+                name: *b"IDAT",
+                payload: IDAT {
+                    data: data.to_vec(),
+                },
+            })
+            .collect()
+    }
+}
+
+impl Serializable for IDAT {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.data)?;
 
-        bytes
+        Ok(())
     }
 }
 
 impl Serializable for IEND {
-    fn get_bytes(&self) -> Vec<u8> {
-        Vec::new()
+    fn serialize<W: Write>(&self, _writer: &mut W) -> Result<(), Error> {
+        Ok(())
     }
 }
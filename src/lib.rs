@@ -1,5 +1,9 @@
+pub mod checksum;
 pub mod color;
 pub mod img;
 
+mod error;
+
+pub use error::Error;
 pub use img::*;
 
@@ -1,9 +1,12 @@
 mod helper;
 
-use szimg::bmp::save_bmp;
+use szimg::bmp::{save_bmp, save_bmp_buffer};
+use szimg::color::{Luma, Rgb};
+use szimg::ImageBuffer;
 use helper::diff_file;
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn test_save_png_rgb() {
     let mut bmp_array = [[[0_u8; 3]; 255]; 255];
     for outer_index in 0..255 {
@@ -16,4 +19,26 @@ fn test_save_png_rgb() {
     save_bmp("./tests/output/rgb.bmp", bmp_array).unwrap();
 
     assert!(diff_file("./tests/output/rgb.bmp", "./tests/templates/rgb.bmp"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_save_bmp_buffer() {
+    let image = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    save_bmp_buffer("./tests/output/rgb_buffer.bmp", &image).unwrap();
+
+    assert!(diff_file("./tests/output/rgb_buffer.bmp", "./tests/templates/rgb.bmp"));
+}
+
+#[test]
+fn test_save_bmp_gray() {
+    let image = ImageBuffer::from_fn(3, 2, |x, y| Luma([(x + y) as u8]));
+    save_bmp_buffer("./tests/output/gray.bmp", &image).unwrap();
+
+    let bytes = std::fs::read("./tests/output/gray.bmp").unwrap();
+    // 14 bytes header, 40 bytes DIB and 256 colors palette
+    assert_eq!(1078, u32::from_le_bytes([bytes[10], bytes[11], bytes[12], bytes[13]]));
+    // 8 bits depth
+    assert_eq!(8, u16::from_le_bytes([bytes[28], bytes[29]]));
+    // Rows are stored from bottom to top and padded to 4 bytes
+    assert_eq!(&[1, 2, 3, 0, 0, 1, 2, 0], &bytes[1078..]);
+}
@@ -0,0 +1,68 @@
+use szimg::color::{ColorType, Luma, LumaA, Pixel, Rgb, Rgba};
+use szimg::ImageBuffer;
+
+#[test]
+fn test_from_fn() {
+    let image = ImageBuffer::from_fn(3, 2, |x, y| [x as u8, y as u8, 0]);
+
+    assert_eq!((3, 2), image.dimensions());
+    assert_eq!([2, 1, 0], image.get_pixel(2, 1));
+    assert_eq!(6, image.pixels().count());
+}
+
+#[test]
+fn test_from_raw() {
+    let image = ImageBuffer::from_raw(2, 2, vec![0_u8, 1, 2, 3]).unwrap();
+    assert_eq!(2, image.get_pixel(0, 1));
+    assert_eq!(vec![&[0, 1][..], &[2, 3][..]], image.rows().collect::<Vec<_>>());
+
+    // Length does not match the size
+    assert!(ImageBuffer::from_raw(2, 2, vec![0_u8; 3]).is_none());
+}
+
+#[test]
+fn test_put_pixel() {
+    let mut image = ImageBuffer::<[u8; 4]>::new(4, 4);
+    image.put_pixel(3, 0, [255, 0, 0, 255]);
+    image[(0, 3)] = [0, 255, 0, 255];
+
+    assert_eq!([255, 0, 0, 255], image.get_pixel(3, 0));
+    assert_eq!([0, 255, 0, 255], image[(0, 3)]);
+    assert_eq!([0, 0, 0, 0], image.get_pixel(1, 1));
+}
+
+#[test]
+#[should_panic]
+fn test_get_pixel_out_of_bounds() {
+    let image = ImageBuffer::<u8>::new(4, 4);
+    image.get_pixel(4, 0);
+}
+
+#[test]
+fn test_from_array() {
+    let image = ImageBuffer::from([[1_u8, 2, 3], [4, 5, 6]]);
+
+    assert_eq!((3, 2), image.dimensions());
+    assert_eq!(&[1, 2, 3, 4, 5, 6], image.as_raw());
+}
+
+#[test]
+fn test_from_channels() {
+    let image = ImageBuffer::<Rgb<u8>>::from_channels(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap();
+
+    assert_eq!(Rgb([4, 5, 6]), image.get_pixel(1, 0));
+    assert_eq!(ColorType::Rgb, image.color_type());
+    assert_eq!(vec![1, 2, 3, 4, 5, 6], image.to_channels());
+    assert!(ImageBuffer::<Rgba<u8>>::from_channels(2, 1, vec![0; 6]).is_none());
+}
+
+#[test]
+fn test_convert_pixel() {
+    let image = ImageBuffer::from_pixel(2, 2, Rgb([255_u8, 255, 255]));
+
+    assert_eq!(Rgba([255, 255, 255, 255]), image.map(Pixel::to_rgba).get_pixel(0, 0));
+    assert_eq!(Luma([255]), image.map(Pixel::to_luma).get_pixel(1, 1));
+    assert_eq!(Luma([76]), Rgb([255_u8, 0, 0]).to_luma());
+    assert_eq!(LumaA([1000, 7]), Rgba([1000_u16, 1000, 1000, 7]).to_luma_alpha());
+    assert_eq!(Rgb([9, 9, 9]), LumaA([9_u8, 0]).to_rgb());
+}
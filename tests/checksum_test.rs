@@ -0,0 +1,106 @@
+use szimg::checksum::{Adler32, Checksum, ChecksumWriter, Crc32};
+
+use std::io::Write;
+
+// Bit by bit definitions to check the table-driven versions against
+fn naive_crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn naive_adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in bytes.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn sample_data(length: usize) -> Vec<u8> {
+    (0..length as u64)
+        .map(|i| (i * i * 31 + i / 7) as u8)
+        .collect()
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(0, Crc32::checksum(b""));
+    assert_eq!(0xcbf43926, Crc32::checksum(b"123456789"));
+    assert_eq!(
+        0x414fa339,
+        Crc32::checksum(b"The quick brown fox jumps over the lazy dog")
+    );
+    for length in [1, 7, 8, 9, 15, 16, 17, 1000, 65537].iter() {
+        let data = sample_data(*length);
+        assert_eq!(naive_crc32(&data), Crc32::checksum(&data));
+    }
+}
+
+#[test]
+fn test_adler32() {
+    assert_eq!(1, Adler32::checksum(b""));
+    assert_eq!(0x11e60398, Adler32::checksum(b"Wikipedia"));
+    // All 0xff is the worst case for the deferred modulo
+    for data in [sample_data(100_000), vec![0xff; 100_000]].iter() {
+        for &length in [5551, 5552, 5553, 11104, 100_000].iter() {
+            assert_eq!(
+                naive_adler32(&data[..length]),
+                Adler32::checksum(&data[..length])
+            );
+        }
+    }
+}
+
+#[test]
+fn test_checksum_streaming() {
+    let data = sample_data(50_000);
+    let mut crc = Crc32::new();
+    let mut adler = Adler32::new();
+    let mut start = 0;
+    for step in 0.. {
+        let end = (start + step * 37 % 9000).min(data.len());
+        crc.update(&data[start..end]);
+        adler.update(&data[start..end]);
+        start = end;
+        if start == data.len() {
+            break;
+        }
+    }
+    assert_eq!(Crc32::checksum(&data), crc.value());
+    assert_eq!(Adler32::checksum(&data), adler.value());
+
+    let mut output = Vec::new();
+    let mut writer = ChecksumWriter::<_, Crc32>::new(&mut output);
+    writer.write_all(&data[..123]).unwrap();
+    writer.write_all(&data[123..]).unwrap();
+    assert_eq!(Crc32::checksum(&data), writer.value());
+    assert_eq!(data, output);
+}
+
+#[test]
+fn test_checksum_combine() {
+    let data = sample_data(200_000);
+    for &split in [0, 1, 8, 5552, 65521, 65522, 131_073, 200_000].iter() {
+        let (first, second) = data.split_at(split);
+        let length = second.len() as u64;
+        assert_eq!(
+            Crc32::checksum(&data),
+            Crc32::combine(Crc32::checksum(first), Crc32::checksum(second), length)
+        );
+        assert_eq!(
+            Adler32::checksum(&data),
+            Adler32::combine(Adler32::checksum(first), Adler32::checksum(second), length)
+        );
+    }
+}
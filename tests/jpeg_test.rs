@@ -1,6 +1,12 @@
 mod helper;
 
-use szimg::jpg::{ save_jpg_gray, save_jpg_rgb };
+use szimg::color::{Cmyk, Luma, LumaA, Pixel, Rgb, Rgba};
+use szimg::jpg::{ save_jpg_gray, save_jpg_rgb, save_jpg_buffer, decode_jpg, load_jpg };
+use szimg::jpg::{
+    AlphaMode, Background, ColorSpace, DctMethod, Density, DensityUnit, JpegOptions,
+    LosslessOptions, MetadataSegment, ProgressiveScan, Subsampling, JPEG,
+};
+use szimg::{DynamicImage, Error, Image, ImageBuffer};
 use helper::diff_file;
 
 #[test]
@@ -21,6 +27,7 @@ fn test_save_jpg_gray() {
 }
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn test_save_jpg_rgb() {
     let mut data = [[[0_u8; 3]; 255]; 255];
     for outer_index in 0..255 {
@@ -35,5 +42,1062 @@ fn test_save_jpg_rgb() {
     assert!(diff_file("./tests/output/rgb.jpg", "./tests/templates/rgb.jpg"));
 }
 
-// JPEG does not support alpha channel
-// Think about how to convert to YCbCr when encontering transparent
\ No newline at end of file
+#[test]
+fn test_save_jpg_buffer() {
+    let image = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    save_jpg_buffer("./tests/output/rgb_buffer.jpg", &image).unwrap();
+
+    assert!(diff_file("./tests/output/rgb_buffer.jpg", "./tests/templates/rgb.jpg"));
+}
+
+#[test]
+fn test_save_jpg_too_large() {
+    let image = ImageBuffer::<Luma<u8>>::new(70000, 1);
+    let result = save_jpg_buffer("./tests/output/too_large.jpg", &image);
+
+    assert!(matches!(result, Err(Error::FormatLimit(_))));
+}
+
+// Tables of the DQT segments, in zig-zag order as stored
+fn quant_tables(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let start = bytes.windows(2).position(|w| w == [0xff, 0xdb]).unwrap() + 2;
+    let length = u16::from_be_bytes([bytes[start], bytes[start + 1]]) as usize;
+    bytes[start + 2..start + length]
+        .chunks(65)
+        .map(|table| table[1..].to_vec())
+        .collect()
+}
+
+fn encode_jpg(image: &ImageBuffer<Rgb<u8>>, options: &JpegOptions) -> Vec<u8> {
+    JPEG::from_buffer_with_options(image, options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap()
+}
+
+#[test]
+fn test_save_jpg_quality() {
+    let image = ImageBuffer::from_fn(64, 48, |x, y| {
+        Rgb([(x * 4) as u8, (y * 5) as u8, (x ^ y) as u8])
+    });
+
+    // Quality 50 writes the tables of Annex K, first entries 16 and 17
+    let tables = quant_tables(&encode_jpg(
+        &image,
+        &JpegOptions {
+            quality: 50,
+            ..JpegOptions::default()
+        },
+    ));
+    assert_eq!(tables.len(), 2);
+    assert_eq!(&tables[0][..8], &[16, 11, 12, 14, 12, 10, 16, 14]);
+    assert_eq!(&tables[1][..8], &[17, 18, 18, 24, 21, 24, 47, 26]);
+
+    let tables = quant_tables(&encode_jpg(
+        &image,
+        &JpegOptions {
+            quality: 100,
+            ..JpegOptions::default()
+        },
+    ));
+    assert!(tables.iter().flatten().all(|&value| value == 1));
+
+    // Quality 10 scales by 5 and clamps to 255
+    let tables = quant_tables(&encode_jpg(
+        &image,
+        &JpegOptions {
+            quality: 10,
+            ..JpegOptions::default()
+        },
+    ));
+    assert_eq!(tables[0][0], 80);
+    assert_eq!(tables[1][63], 255);
+
+    let sizes = [10, 50, 90, 100]
+        .iter()
+        .map(|&quality| {
+            encode_jpg(
+                &image,
+                &JpegOptions {
+                    quality,
+                    ..JpegOptions::default()
+                },
+            )
+            .len()
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        sizes.windows(2).all(|pair| pair[0] < pair[1]),
+        "{:?}",
+        sizes
+    );
+
+    // Gray images only carry the luminance table
+    let gray = ImageBuffer::from_fn(16, 16, |x, y| Luma([(x + y) as u8]));
+    let bytes = JPEG::from_buffer(&gray).unwrap().encode_to_vec().unwrap();
+    assert_eq!(quant_tables(&bytes).len(), 1);
+}
+
+#[test]
+fn test_save_jpg_quant_tables() {
+    let image = ImageBuffer::from_fn(32, 32, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 64]));
+    // Row-major tables, growing to the right and downwards
+    let mut luminance = [0; 64];
+    let mut chrominance = [0; 64];
+    for (index, (l, c)) in luminance.iter_mut().zip(chrominance.iter_mut()).enumerate() {
+        *l = (1 + index % 8 + index / 8) as u8;
+        *c = (10 + index) as u8;
+    }
+    let options = JpegOptions {
+        quant_tables: Some([luminance, chrominance]),
+        ..JpegOptions::default()
+    };
+    let tables = quant_tables(&encode_jpg(&image, &options));
+    // Zig-zag order: (0,0), (1,0), (0,1), (0,2), (1,1), (2,0)
+    assert_eq!(&tables[0][..6], &[1, 2, 2, 3, 3, 3]);
+    assert_eq!(&tables[1][..6], &[10, 11, 18, 26, 19, 12]);
+    assert_eq!(tables[1][63], 73);
+}
+
+#[test]
+fn test_save_jpg_subsampling() {
+    // A plot with thin colored lines on white
+    let image = ImageBuffer::from_fn(40, 30, |x, y| match (x, y) {
+        (13, _) => Rgb([255, 0, 0]),
+        (_, 21) => Rgb([0, 0, 255]),
+        _ => Rgb([255, 255, 255]),
+    });
+
+    let mut errors = Vec::new();
+    for (subsampling, factors) in [
+        (Subsampling::S444, 0x11),
+        (Subsampling::S422, 0x21),
+        (Subsampling::S420, 0x22),
+        (Subsampling::S440, 0x12),
+    ] {
+        let options = JpegOptions {
+            quality: 90,
+            subsampling,
+            ..JpegOptions::default()
+        };
+        let bytes = encode_jpg(&image, &options);
+        let sof = bytes.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+        assert_eq!(
+            &bytes[sof + 10..sof + 19],
+            &[1, factors, 0, 2, 0x11, 1, 3, 0x11, 1]
+        );
+
+        let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (40, 30));
+        // Largest error of red and green along the red line
+        errors.push(
+            (0..30)
+                .filter(|&y| y != 21)
+                .map(|y| {
+                    255 - decoded.get_pixel(13, y).0[0] as i32
+                        + decoded.get_pixel(13, y).0[1] as i32
+                })
+                .max()
+                .unwrap(),
+        );
+    }
+    // Only halving the columns bleeds a vertical line
+    assert!(errors[0] < 24 && errors[3] < 32, "{:?}", errors);
+    assert!(errors[1] > 100 && errors[2] > 100, "{:?}", errors);
+}
+
+// Smooth gradients with a ripple, to give every coefficient something to do
+fn wave_image(width: u32, height: u32) -> ImageBuffer<Rgb<u8>> {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let wave = ((x as f64 / 7.).sin() * (y as f64 / 5.).cos() * 60.) as i32;
+        Rgb([(x * 2) as u8, (128 + wave) as u8, (y * 3) as u8])
+    })
+}
+
+#[test]
+fn test_save_jpg_optimize_huffman() {
+    let image = wave_image(96, 80);
+    let standard = encode_jpg(&image, &JpegOptions::default());
+    let optimized = encode_jpg(
+        &image,
+        &JpegOptions {
+            optimize_huffman: true,
+            ..JpegOptions::default()
+        },
+    );
+    assert!(optimized.len() < standard.len());
+
+    // Only the codes differ, the coefficients are the same
+    let standard = decode_jpg(&mut &standard[..]).unwrap();
+    let optimized = decode_jpg(&mut &optimized[..]).unwrap();
+    assert_eq!(standard, optimized);
+
+    // Gray images have a single pair of tables
+    let gray = standard.to_luma8();
+    let options = JpegOptions {
+        optimize_huffman: true,
+        ..JpegOptions::default()
+    };
+    let bytes = JPEG::from_buffer_with_options(&gray, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    let dht = bytes.windows(2).filter(|w| w == &[0xff, 0xc4]).count();
+    assert_eq!(dht, 1);
+    let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_luma8();
+    assert_eq!(decoded.dimensions(), gray.dimensions());
+}
+
+#[test]
+fn test_save_jpg_dct_method() {
+    let image = wave_image(96, 80);
+    for quality in [50, 100] {
+        let float = encode_jpg(
+            &image,
+            &JpegOptions {
+                quality,
+                ..JpegOptions::default()
+            },
+        );
+        let integer = encode_jpg(
+            &image,
+            &JpegOptions {
+                quality,
+                dct_method: DctMethod::Integer,
+                ..JpegOptions::default()
+            },
+        );
+
+        // Both round the same exact coefficients, only a few ties may land
+        // on the other side, each one a quantization step
+        let float = decode_jpg(&mut &float[..]).unwrap().to_rgb8();
+        let integer = decode_jpg(&mut &integer[..]).unwrap().to_rgb8();
+        let (mean, largest) = difference(
+            float
+                .pixels()
+                .zip(integer.pixels())
+                .flat_map(|(a, b)| (0..3).map(move |c| (a.0[c], b.0[c]))),
+        );
+        assert!(
+            mean < 0.1 && largest <= 8,
+            "{} {} {}",
+            quality,
+            mean,
+            largest
+        );
+    }
+}
+
+// Entropy-coded data between the SOS header and EOI
+fn scan_data(bytes: &[u8]) -> &[u8] {
+    let sos = bytes.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+    let start = sos + 2 + u16::from_be_bytes([bytes[sos + 2], bytes[sos + 3]]) as usize;
+    &bytes[start..bytes.len() - 2]
+}
+
+#[test]
+fn test_save_jpg_byte_stuffing() {
+    // Noise at the best quality codes plenty of 0xFF bytes
+    let image = ImageBuffer::from_fn(64, 48, |x, y| {
+        let noise = (x * 7919 + y * 104729) ^ (x * y * 31);
+        Rgb([noise as u8, (noise >> 8) as u8, (noise >> 4) as u8])
+    });
+    let options = JpegOptions {
+        quality: 100,
+        ..JpegOptions::default()
+    };
+    let bytes = encode_jpg(&image, &options);
+    let data = scan_data(&bytes);
+    let stuffed = data.windows(2).filter(|w| w == &[0xff, 0x00]).count();
+    assert!(stuffed > 0);
+    assert_eq!(data.iter().filter(|&&byte| byte == 0xff).count(), stuffed);
+
+    let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), (64, 48));
+}
+
+#[test]
+fn test_save_jpg_restart_interval() {
+    let image = wave_image(96, 80);
+    // 4:2:0 has 6x5 MCUs and 4:4:4 has 12x10, enough to wrap around RST7
+    for (subsampling, mcus) in [(Subsampling::S420, 30), (Subsampling::S444, 120)] {
+        for optimize_huffman in [false, true] {
+            let options = JpegOptions {
+                subsampling,
+                optimize_huffman,
+                ..JpegOptions::default()
+            };
+            let plain = encode_jpg(&image, &options);
+            let restarted = encode_jpg(
+                &image,
+                &JpegOptions {
+                    restart_interval: 3,
+                    ..options
+                },
+            );
+
+            let dri = restarted
+                .windows(2)
+                .position(|w| w == [0xff, 0xdd])
+                .unwrap();
+            assert_eq!(restarted[dri + 2..dri + 6], [0x00, 0x04, 0x00, 0x03]);
+            let markers = scan_data(&restarted)
+                .windows(2)
+                .filter(|w| w[0] == 0xff && w[1] != 0x00)
+                .map(|w| w[1])
+                .collect::<Vec<_>>();
+            assert_eq!(markers.len(), mcus / 3 - 1);
+            for (index, marker) in markers.iter().enumerate() {
+                assert_eq!(*marker, 0xd0 + (index % 8) as u8);
+            }
+
+            // Only the framing differs, the coefficients are the same
+            let plain = decode_jpg(&mut &plain[..]).unwrap();
+            let restarted = decode_jpg(&mut &restarted[..]).unwrap();
+            assert_eq!(plain, restarted);
+        }
+    }
+
+    // Every block of a gray image is an MCU
+    let gray = image.map(|pixel| Luma([pixel.0[1]]));
+    let options = JpegOptions {
+        restart_interval: 1,
+        ..JpegOptions::default()
+    };
+    let bytes = JPEG::from_buffer_with_options(&gray, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    let markers = scan_data(&bytes)
+        .windows(2)
+        .filter(|w| w[0] == 0xff && w[1] != 0x00)
+        .count();
+    assert_eq!(markers, 12 * 10 - 1);
+    let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_luma8();
+    let plain = JPEG::from_buffer(&gray).unwrap().encode_to_vec().unwrap();
+    assert_eq!(decoded, decode_jpg(&mut &plain[..]).unwrap().to_luma8());
+}
+
+#[test]
+fn test_save_jpg_progressive() {
+    // Odd sizes leave partial MCUs, which scans of one component skip
+    let image = wave_image(101, 77);
+    let gray = image.map(|pixel| Luma([pixel.0[1]]));
+    for quality in [30, 100] {
+        for subsampling in [Subsampling::S420, Subsampling::S422, Subsampling::S444] {
+            for restart_interval in [0, 5] {
+                let baseline = JpegOptions {
+                    quality,
+                    subsampling,
+                    restart_interval,
+                    ..JpegOptions::default()
+                };
+                let progressive = JpegOptions {
+                    progressive: true,
+                    ..baseline.clone()
+                };
+
+                // The scans add up to the same coefficients
+                let expected = decode_jpg(&mut &encode_jpg(&image, &baseline)[..]).unwrap();
+                let bytes = encode_jpg(&image, &progressive);
+                assert!(bytes.windows(2).any(|w| w == [0xff, 0xc2]));
+                let sos = bytes.windows(2).filter(|w| w == &[0xff, 0xda]).count();
+                assert_eq!(sos, 10);
+                assert_eq!(decode_jpg(&mut &bytes[..]).unwrap(), expected);
+
+                let encode_gray = |options: &JpegOptions| {
+                    let bytes = JPEG::from_buffer_with_options(&gray, options)
+                        .unwrap()
+                        .encode_to_vec()
+                        .unwrap();
+                    decode_jpg(&mut &bytes[..]).unwrap()
+                };
+                assert_eq!(encode_gray(&progressive), encode_gray(&baseline));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_save_jpg_scan_script() {
+    let image = wave_image(64, 40);
+    let scan = |components: &[u8], start, end, high, low| ProgressiveScan {
+        components: components.to_vec(),
+        start,
+        end,
+        high,
+        low,
+    };
+    // Spectral selection only, with the DC of each component in its own scan
+    let script = vec![
+        scan(&[0], 0, 0, 0, 0),
+        scan(&[1], 0, 0, 0, 0),
+        scan(&[2], 0, 0, 0, 0),
+        scan(&[0], 1, 9, 0, 0),
+        scan(&[0], 10, 63, 0, 0),
+        scan(&[1], 1, 63, 0, 0),
+        scan(&[2], 1, 63, 0, 0),
+    ];
+    let options = JpegOptions {
+        progressive: true,
+        scan_script: Some(script),
+        ..JpegOptions::default()
+    };
+    let expected = decode_jpg(&mut &encode_jpg(&image, &JpegOptions::default())[..]).unwrap();
+    let bytes = encode_jpg(&image, &options);
+    assert_eq!(bytes.windows(2).filter(|w| w == &[0xff, 0xda]).count(), 7);
+    assert_eq!(decode_jpg(&mut &bytes[..]).unwrap(), expected);
+
+    let invalid = [
+        // AC before DC, DC and AC in one scan, two components in an AC scan
+        vec![scan(&[0], 1, 63, 0, 0)],
+        vec![scan(&[0], 0, 63, 0, 0)],
+        vec![scan(&[0, 1, 2], 0, 0, 0, 0), scan(&[0, 1], 1, 63, 0, 0)],
+        // Refining two bits at once, or bits that were never coded
+        vec![scan(&[0, 1, 2], 0, 0, 0, 2), scan(&[0, 1, 2], 0, 0, 2, 0)],
+        vec![scan(&[0, 1, 2], 0, 0, 1, 0)],
+        // Components out of order
+        vec![scan(&[1, 0, 2], 0, 0, 0, 0)],
+        // Chroma AC never coded
+        vec![scan(&[0, 1, 2], 0, 0, 0, 0), scan(&[0], 1, 63, 0, 0)],
+    ];
+    for script in invalid {
+        let options = JpegOptions {
+            progressive: true,
+            scan_script: Some(script.clone()),
+            ..JpegOptions::default()
+        };
+        let result = JPEG::from_buffer_with_options(&image, &options);
+        assert!(matches!(result, Err(Error::Unsupported(_))), "{:?}", script);
+    }
+    let gray = image.map(|pixel| Luma([pixel.0[0]]));
+    let options = JpegOptions {
+        progressive: true,
+        scan_script: Some(ProgressiveScan::standard_script(3)),
+        ..JpegOptions::default()
+    };
+    let result = JPEG::from_buffer_with_options(&gray, &options);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+// Marker and payload of the segments before the scan
+fn segments(bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut segments = Vec::new();
+    let mut position = 2;
+    while bytes[position + 1] != 0xda {
+        let length = u16::from_be_bytes([bytes[position + 2], bytes[position + 3]]) as usize;
+        let payload = bytes[position + 4..position + 2 + length].to_vec();
+        segments.push((bytes[position + 1], payload));
+        position += 2 + length;
+    }
+    segments
+}
+
+#[test]
+fn test_save_jpg_metadata() {
+    let image = ImageBuffer::from_fn(40, 30, |x, y| Rgb([x as u8 * 6, y as u8 * 8, 128]));
+    // Large enough for three APP2 segments
+    let profile = (0..150_000)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect::<Vec<_>>();
+    let options = JpegOptions {
+        density: Density::from_dpi(300),
+        segments: vec![
+            MetadataSegment::Exif {
+                orientation: Some(6),
+                software: Some("szimg".to_string()),
+                date_time: Some("2021:12:31 23:59:58".to_string()),
+            },
+            MetadataSegment::IccProfile(profile.clone()),
+            MetadataSegment::Comment("max_iteration = 255".to_string()),
+        ],
+        ..JpegOptions::default()
+    };
+    let bytes = encode_jpg(&image, &options);
+    let found = segments(&bytes);
+    let markers = found.iter().map(|(marker, _)| *marker).collect::<Vec<_>>();
+    assert_eq!(markers[..6], [0xe0, 0xe1, 0xe2, 0xe2, 0xe2, 0xfe]);
+
+    // JFIF 1.02 at 300 dots per inch, without thumbnail
+    assert_eq!(
+        found[0].1,
+        [b'J', b'F', b'I', b'F', 0, 1, 2, 1, 0x01, 0x2c, 0x01, 0x2c, 0, 0]
+    );
+
+    // Orientation inline, then the offsets of the two strings after the IFD
+    let exif = &found[1].1;
+    assert_eq!(exif[..14], *b"Exif\0\0MM\0\x2a\0\0\0\x08");
+    assert_eq!(exif[14..16], [0, 3]);
+    assert_eq!(exif[16..28], [0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+    assert_eq!(exif[28..40], [0x01, 0x31, 0, 2, 0, 0, 0, 6, 0, 0, 0, 50]);
+    assert_eq!(exif[40..52], [0x01, 0x32, 0, 2, 0, 0, 0, 20, 0, 0, 0, 56]);
+    assert_eq!(exif[52..56], [0; 4]);
+    assert_eq!(exif[56..62], *b"szimg\0");
+    assert_eq!(exif[62..], *b"2021:12:31 23:59:58\0");
+
+    // The profile comes back from the chunks in sequence
+    let mut joined = Vec::new();
+    for (index, (_, data)) in found[2..5].iter().enumerate() {
+        assert_eq!(data[..12], *b"ICC_PROFILE\0");
+        assert_eq!(data[12..14], [index as u8 + 1, 3]);
+        joined.extend_from_slice(&data[14..]);
+    }
+    assert_eq!(joined, profile);
+    assert_eq!(found[5].1, b"max_iteration = 255");
+
+    let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), (40, 30));
+
+    // Every file starts with JFIF, by default without a physical size
+    let bytes = encode_jpg(&image, &JpegOptions::default());
+    let found = segments(&bytes);
+    assert_eq!(found[0].1[7..12], [DensityUnit::None as u8, 0, 1, 0, 1]);
+    assert_eq!(found[1].0, 0xdb);
+}
+
+#[test]
+fn test_save_jpg_metadata_invalid() {
+    let image = ImageBuffer::from_fn(8, 8, |x, y| Rgb([x as u8, y as u8, 0]));
+    let exif = |orientation, software: &str, date_time: &str| MetadataSegment::Exif {
+        orientation: Some(orientation),
+        software: Some(software.to_string()),
+        date_time: Some(date_time.to_string()),
+    };
+    let cases = [
+        (exif(9, "szimg", "2021:12:31 23:59:58"), false),
+        (exif(1, "sz\u{e9}img", "2021:12:31 23:59:58"), false),
+        (exif(1, "szimg", "2021-12-31 23:59:58"), false),
+        (MetadataSegment::IccProfile(Vec::new()), true),
+        (MetadataSegment::IccProfile(vec![0; 256 * 65519]), true),
+        (MetadataSegment::Comment("x".repeat(65534)), true),
+    ];
+    for (segment, limit) in cases {
+        let options = JpegOptions {
+            segments: vec![segment],
+            ..JpegOptions::default()
+        };
+        let result = JPEG::from_buffer_with_options(&image, &options);
+        if limit {
+            assert!(matches!(result, Err(Error::FormatLimit(_))));
+        } else {
+            assert!(matches!(result, Err(Error::Unsupported(_))));
+        }
+    }
+}
+
+// Flat areas at the best quality decode to almost exactly the blended color
+fn decode_alpha<P: Pixel<Subpixel = u8>>(
+    image: &ImageBuffer<P>,
+    options: &JpegOptions,
+) -> DynamicImage {
+    let options = JpegOptions {
+        quality: 100,
+        subsampling: Subsampling::S444,
+        ..options.clone()
+    };
+    let bytes = JPEG::from_buffer_with_options(image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    decode_jpg(&mut &bytes[..]).unwrap()
+}
+
+fn assert_close(result: [u8; 3], expected: [u8; 3]) {
+    let close = result
+        .iter()
+        .zip(expected.iter())
+        .all(|(&a, &b)| (a as i32 - b as i32).abs() <= 2);
+    assert!(close, "{:?} {:?}", result, expected);
+}
+
+#[test]
+fn test_save_jpg_alpha() {
+    // Opaque pixels encode exactly like the same image without alpha
+    let rgb = wave_image(40, 24);
+    let opaque = rgb.map(Pixel::to_rgba);
+    assert_eq!(
+        JPEG::from_buffer(&opaque).unwrap().encode_to_vec().unwrap(),
+        JPEG::from_buffer(&rgb).unwrap().encode_to_vec().unwrap()
+    );
+
+    // Left half transparent with a leftover color, right half half-covered red
+    let image = ImageBuffer::from_fn(32, 16, |x, _| match x < 16 {
+        true => Rgba([12, 200, 34, 0]),
+        false => Rgba([255, 0, 0, 128]),
+    });
+    let decoded = decode_alpha(&image, &JpegOptions::default()).to_rgb8();
+    assert_close(decoded.get_pixel(4, 8).0, [255, 255, 255]);
+    assert_close(decoded.get_pixel(28, 8).0, [255, 127, 127]);
+
+    let options = JpegOptions {
+        background: Background::Color(Rgb([0, 0, 255])),
+        ..JpegOptions::default()
+    };
+    let decoded = decode_alpha(&image, &options).to_rgb8();
+    assert_close(decoded.get_pixel(4, 8).0, [0, 0, 255]);
+    assert_close(decoded.get_pixel(28, 8).0, [128, 0, 127]);
+
+    // The same red, already multiplied by its alpha
+    let image = ImageBuffer::from_pixel(16, 16, Rgba([128, 0, 0, 128]));
+    let options = JpegOptions {
+        alpha_mode: AlphaMode::Premultiplied,
+        ..JpegOptions::default()
+    };
+    let decoded = decode_alpha(&image, &options).to_rgb8();
+    assert_close(decoded.get_pixel(8, 8).0, [255, 127, 127]);
+
+    // Gray images with alpha take the luma of the blended color
+    let image = ImageBuffer::from_pixel(16, 16, LumaA([200, 0]));
+    let options = JpegOptions {
+        background: Background::Color(Rgb([255, 0, 0])),
+        ..JpegOptions::default()
+    };
+    let decoded = decode_alpha(&image, &options).to_luma8();
+    assert!((decoded.get_pixel(8, 8).0[0] as i32 - 76).abs() <= 2);
+}
+
+#[test]
+fn test_save_jpg_checkerboard() {
+    let image = ImageBuffer::from_pixel(32, 32, Rgba([0, 0, 0, 0]));
+    let colors = [Rgb([204, 204, 204]), Rgb([102, 102, 102])];
+    let options = JpegOptions {
+        background: Background::Checkerboard { size: 8, colors },
+        ..JpegOptions::default()
+    };
+    let decoded = decode_alpha(&image, &options).to_rgb8();
+    for y in 0..4 {
+        for x in 0..4 {
+            let expected = colors[((x + y) % 2) as usize].0;
+            assert_close(decoded.get_pixel(x * 8 + 4, y * 8 + 4).0, expected);
+        }
+    }
+
+    let options = JpegOptions {
+        background: Background::Checkerboard { size: 0, colors },
+        ..JpegOptions::default()
+    };
+    let result = JPEG::from_buffer_with_options(&image, &options);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+#[test]
+fn test_cmyk_under_color_removal() {
+    let cmyk = |rgb: [u8; 3], under_color_removal| Cmyk::from_rgb(Rgb(rgb), under_color_removal).0;
+    assert_eq!(cmyk([255, 255, 255], 1.), [0, 0, 0, 0]);
+    assert_eq!(cmyk([0, 0, 0], 1.), [0, 0, 0, 255]);
+    assert_eq!(cmyk([0, 0, 0], 0.), [255, 255, 255, 0]);
+    assert_eq!(cmyk([255, 0, 0], 1.), [0, 255, 255, 0]);
+    // Half the gray goes to black, the colored inks cover the rest
+    assert_eq!(cmyk([127, 127, 127], 0.5), [85, 85, 85, 64]);
+
+    for under_color_removal in [0., 0.5, 1.] {
+        for rgb in [[12, 200, 34], [255, 128, 0], [90, 90, 91], [3, 2, 1]] {
+            let result = Cmyk::from_rgb(Rgb(rgb), under_color_removal).to_rgb();
+            assert_close(result.0, rgb);
+        }
+    }
+}
+
+#[test]
+fn test_save_jpg_cmyk() {
+    let image = wave_image(40, 24);
+    // CMYK is never subsampled
+    let cases = [
+        (ColorSpace::Cmyk, 0, Subsampling::S444),
+        (ColorSpace::Ycck, 2, Subsampling::S420),
+    ];
+    for (color_space, transform, frame_subsampling) in cases {
+        let options = JpegOptions {
+            quality: 95,
+            color_space,
+            under_color_removal: 60,
+            ..JpegOptions::default()
+        };
+        let bytes = encode_jpg(&image, &options);
+        let found = segments(&bytes);
+        // An Adobe segment takes the place of the JFIF header
+        assert!(found.iter().all(|(marker, _)| *marker != 0xe0));
+        let (_, adobe) = found.iter().find(|(marker, _)| *marker == 0xee).unwrap();
+        assert_eq!(
+            adobe[..],
+            [b"Adobe".as_slice(), &[0, 100, 0, 0, 0, 0, transform]].concat()
+        );
+        let (_, frame) = found.iter().find(|(marker, _)| *marker == 0xc0).unwrap();
+        let expected = if color_space == ColorSpace::Cmyk {
+            [1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0, 4, 0x11, 0]
+        } else {
+            [1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1, 4, 0x22, 0]
+        };
+        assert_eq!(frame[5..], [&[4], &expected[..]].concat());
+        let tables = found.iter().filter(|(marker, _)| *marker == 0xdb).count();
+        assert_eq!(tables, 1);
+
+        // About as close as YCbCr with the same subsampling
+        let error = |bytes: &[u8]| {
+            let decoded = decode_jpg(&mut &bytes[..]).unwrap().to_rgb8();
+            let samples = decoded.to_channels().into_iter().zip(image.to_channels());
+            (difference(samples).0, decoded)
+        };
+        let (mean, decoded) = error(&bytes);
+        let ycbcr = JpegOptions {
+            color_space: ColorSpace::YCbCr,
+            subsampling: frame_subsampling,
+            ..options.clone()
+        };
+        let (expected, _) = error(&encode_jpg(&image, &ycbcr));
+        assert!(
+            mean < expected + 0.5,
+            "{:?} {} {}",
+            color_space,
+            mean,
+            expected
+        );
+
+        // Progressive scans of four components decode to the same pixels
+        let progressive = JpegOptions {
+            progressive: true,
+            ..options.clone()
+        };
+        let bytes = encode_jpg(&image, &progressive);
+        assert_eq!(decode_jpg(&mut &bytes[..]).unwrap().to_rgb8(), decoded);
+    }
+
+    // Inks given directly
+    let inks = vec![0, 0, 0, 255, 255, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0, 0];
+    let options = JpegOptions {
+        quality: 100,
+        color_space: ColorSpace::Cmyk,
+        ..JpegOptions::default()
+    };
+    let bytes = JPEG::with_options(4, 1, 4, &inks, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert!(decode_jpg(&mut &bytes[..]).is_ok());
+    let result = JPEG::with_options(4, 1, 4, &inks, &JpegOptions::default());
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+    let result = JPEG::with_options(2, 2, 3, &inks, &options);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+
+    let options = JpegOptions {
+        color_space: ColorSpace::Cmyk,
+        under_color_removal: 101,
+        ..JpegOptions::default()
+    };
+    let result = JPEG::from_buffer_with_options(&image, &options);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+// Samples come back unchanged with every predictor, the smallest file is returned
+fn check_lossless<P: Pixel>(
+    image: &ImageBuffer<P>,
+    expected: &DynamicImage,
+    options: &LosslessOptions,
+) -> Vec<u8> {
+    let mut smallest = Vec::new();
+    for predictor in 1..=7 {
+        let options = LosslessOptions {
+            predictor,
+            ..options.clone()
+        };
+        let bytes = JPEG::from_buffer_lossless(image, &options)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap();
+        let decoded = decode_jpg(&mut &bytes[..]).unwrap();
+        assert!(decoded == *expected, "predictor {}", predictor);
+        if smallest.is_empty() || bytes.len() < smallest.len() {
+            smallest = bytes;
+        }
+    }
+    smallest
+}
+
+#[test]
+fn test_save_jpg_lossless() {
+    // 12-bit microscopy-like data: a smooth background with noise and spots
+    let mut seed = 7_u32;
+    let image = ImageBuffer::from_fn(61, 47, |x, y| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let spot = if (x / 10 + y / 10) % 3 == 0 { 1500 } else { 0 };
+        Luma([(500 + x * 10 + y * 5 + spot + (seed >> 16) % 32) as u16])
+    });
+    let options = LosslessOptions {
+        precision: Some(12),
+        ..LosslessOptions::default()
+    };
+    let expected = DynamicImage::Luma16(image.clone());
+    let bytes = check_lossless(&image, &expected, &options);
+    // About 6 bits of noise per sample, well under 12
+    assert!(bytes.len() < 61 * 47 * 9 / 8, "{}", bytes.len());
+    let found = segments(&bytes);
+    let (_, frame) = found.iter().find(|(marker, _)| *marker == 0xc3).unwrap();
+    assert_eq!(frame[..], [12, 0, 47, 0, 61, 1, 1, 0x11, 0]);
+    assert!(found.iter().all(|(marker, _)| *marker != 0xdb));
+
+    // Full 16-bit range, where differences wrap around modulo 2^16
+    let extremes = [0, 65535, 32768, 1, 65534, 32767];
+    let image = ImageBuffer::from_fn(13, 9, |x, y| Luma([extremes[((x * y + x) % 6) as usize]]));
+    let options = LosslessOptions {
+        restart_interval: 2,
+        ..LosslessOptions::default()
+    };
+    check_lossless(&image, &DynamicImage::Luma16(image.clone()), &options);
+
+    // 8-bit RGB, and 2-bit gray in an 8-bit buffer
+    let image = wave_image(40, 24);
+    let options = LosslessOptions::default();
+    check_lossless(&image, &DynamicImage::Rgb8(image.clone()), &options);
+    let image = ImageBuffer::from_fn(17, 5, |x, y| Luma([((x + y) % 4) as u8]));
+    let options = LosslessOptions {
+        precision: Some(2),
+        restart_interval: 1,
+        ..LosslessOptions::default()
+    };
+    check_lossless(&image, &DynamicImage::Luma8(image.clone()), &options);
+}
+
+#[test]
+fn test_save_jpg_lossless_invalid() {
+    let image = ImageBuffer::from_fn(8, 8, |x, y| Luma([(x * 40 + y) as u16]));
+    let cases = [
+        (0, None),
+        (8, None),
+        (1, Some(1)),
+        (1, Some(17)),
+        (1, Some(8)),
+    ];
+    for (predictor, precision) in cases {
+        let options = LosslessOptions {
+            predictor,
+            precision,
+            ..LosslessOptions::default()
+        };
+        let result = JPEG::from_buffer_lossless(&image, &options);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    let image = ImageBuffer::from_pixel(8, 8, LumaA([0_u8, 255]));
+    let result = JPEG::from_buffer_lossless(&image, &LosslessOptions::default());
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+
+    let image = ImageBuffer::from_pixel(1000, 8, Luma([0_u8]));
+    let options = LosslessOptions {
+        restart_interval: 70,
+        ..LosslessOptions::default()
+    };
+    let result = JPEG::from_buffer_lossless(&image, &options);
+    assert!(matches!(result, Err(Error::FormatLimit(_))));
+}
+
+#[test]
+fn test_save_jpg_options_invalid() {
+    let image = ImageBuffer::from_fn(8, 8, |x, y| Rgb([x as u8, y as u8, 0]));
+    for quality in [0, 101] {
+        let options = JpegOptions {
+            quality,
+            ..JpegOptions::default()
+        };
+        let result = JPEG::from_buffer_with_options(&image, &options);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    let mut luminance = [1; 64];
+    luminance[10] = 0;
+    let options = JpegOptions {
+        quant_tables: Some([luminance, [1; 64]]),
+        ..JpegOptions::default()
+    };
+    let result = JPEG::from_buffer_with_options(&image, &options);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+// Templates under `jpg/` are written by `script/jpeg_fixtures.py`, a separate encoder in
+// Python, so the decoded pixels are compared with the source image within a tolerance
+fn template_rgb(x: u32, y: u32) -> [u8; 3] {
+    [(x * 255 / 36) as u8, (y * 255 / 28) as u8, ((x + y) * 255 / 64) as u8]
+}
+
+fn template_gray(x: u32, y: u32) -> u8 {
+    (x * 3 + y * 2) as u8
+}
+
+// Mean and largest absolute difference of all samples
+fn difference<I: Iterator<Item = (u8, u8)>>(samples: I) -> (f64, u8) {
+    let (mut total, mut count, mut largest) = (0_u64, 0_u64, 0);
+    for (a, b) in samples {
+        let difference = (a as i32 - b as i32).unsigned_abs() as u8;
+        total += difference as u64;
+        count += 1;
+        largest = largest.max(difference);
+    }
+    (total as f64 / count as f64, largest)
+}
+
+fn check_rgb(path: &str) {
+    let image = match load_jpg(path).unwrap() {
+        DynamicImage::Rgb8(image) => image,
+        _ => panic!("{} should decode to Rgb8", path),
+    };
+    assert_eq!(image.dimensions(), (37, 29));
+    let (mean, largest) = difference(image.rows().enumerate().flat_map(|(y, row)| {
+        row.iter().enumerate().flat_map(move |(x, pixel)| {
+            let expected = template_rgb(x as u32, y as u32);
+            (0..3).map(move |c| (pixel.0[c], expected[c]))
+        })
+    }));
+    assert!(mean < 2.5 && largest <= 12, "{}: mean {}, largest {}", path, mean, largest);
+}
+
+fn check_gray(path: &str, width: u32, height: u32, tolerance: u8) {
+    let image = match load_jpg(path).unwrap() {
+        DynamicImage::Luma8(image) => image,
+        _ => panic!("{} should decode to Luma8", path),
+    };
+    assert_eq!(image.dimensions(), (width, height));
+    let (_, largest) = difference(image.rows().enumerate().flat_map(|(y, row)| {
+        row.iter()
+            .enumerate()
+            .map(move |(x, pixel)| (pixel.0[0], template_gray(x as u32, y as u32)))
+    }));
+    assert!(largest <= tolerance, "{}: largest {}", path, largest);
+}
+
+#[test]
+fn test_load_jpg_subsampling() {
+    check_rgb("./tests/templates/jpg/ycbcr_444.jpg");
+    check_rgb("./tests/templates/jpg/ycbcr_422.jpg");
+    check_rgb("./tests/templates/jpg/ycbcr_420.jpg");
+    check_rgb("./tests/templates/jpg/ycbcr_440.jpg");
+}
+
+#[test]
+fn test_load_jpg_scans() {
+    // Restart markers every two MCUs, with fill bytes before the scan
+    check_rgb("./tests/templates/jpg/ycbcr_420_restart.jpg");
+    // One scan per component
+    check_rgb("./tests/templates/jpg/ycbcr_420_separate.jpg");
+}
+
+#[test]
+fn test_load_jpg_gray() {
+    check_gray("./tests/templates/jpg/gray.jpg", 37, 29, 2);
+    check_gray("./tests/templates/jpg/gray_restart.jpg", 37, 29, 2);
+    check_gray("./tests/templates/jpg/gray_q100.jpg", 16, 16, 1);
+}
+
+// Progressive templates quantize the same coefficients as their baseline ones
+#[test]
+fn test_load_jpg_progressive() {
+    for (progressive, baseline) in [
+        ("progressive_420", "ycbcr_420"),
+        ("progressive_420_restart", "ycbcr_420"),
+        ("progressive_444", "ycbcr_444"),
+        ("progressive_gray", "gray"),
+        ("progressive_gray_q100", "gray_q100"),
+        // Long EOB runs and zero runs inside refinement scans
+        ("progressive_gray_large", "gray_large"),
+    ] {
+        let progressive = load_jpg(&format!("./tests/templates/jpg/{}.jpg", progressive)).unwrap();
+        let baseline = load_jpg(&format!("./tests/templates/jpg/{}.jpg", baseline)).unwrap();
+        assert_eq!(progressive, baseline);
+    }
+    check_rgb("./tests/templates/jpg/progressive_420.jpg");
+}
+
+#[test]
+fn test_load_jpg_invalid() {
+    let bytes = std::fs::read("./tests/templates/jpg/ycbcr_420.jpg").unwrap();
+
+    let result = decode_jpg(&mut &bytes[1..]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+
+    let result = decode_jpg(&mut &bytes[..bytes.len() - 100]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+
+    // Rewrite SOF0 as SOF9, which is arithmetic coded
+    let mut arithmetic = bytes.clone();
+    let sof = arithmetic.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+    arithmetic[sof + 1] = 0xc9;
+    let result = decode_jpg(&mut &arithmetic[..]);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+
+    // A baseline scan in a progressive frame
+    let mut progressive = bytes.clone();
+    progressive[sof + 1] = 0xc2;
+    let result = decode_jpg(&mut &progressive[..]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+
+    // Frame sizes beyond what the data could hold are rejected before allocating
+    let mut large = bytes.clone();
+    large[sof + 5..sof + 9].copy_from_slice(&[0x0f, 0xa0, 0x0f, 0xa0]);
+    let result = decode_jpg(&mut &large[..]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+    large[sof + 5..sof + 9].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    let result = decode_jpg(&mut &large[..]);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+#[test]
+fn test_load_jpg_dc_overflow() {
+    // A progressive 1600x8 gray frame whose DC scan, shifted by 13 bits, adds the
+    // largest difference to the predictor for every block
+    let mut bytes = vec![0xff, 0xd8, 0xff, 0xdb, 0, 67, 0];
+    bytes.extend_from_slice(&[1; 64]);
+    bytes.extend_from_slice(&[0xff, 0xc4, 0, 20, 0x00, 1]);
+    bytes.extend_from_slice(&[0; 15]);
+    bytes.push(11);
+    bytes.extend_from_slice(&[0xff, 0xc2, 0, 11, 8, 0, 8, 0x06, 0x40, 1, 1, 0x11, 0]);
+    bytes.extend_from_slice(&[0xff, 0xda, 0, 8, 1, 1, 0x00, 0, 0, 0x0d]);
+
+    // Code 0 for size 11, then 2047, for each of the 200 blocks
+    let bits: Vec<bool> = (0..200).flat_map(|_| (0..12).map(|i| i > 0)).collect();
+    for byte in bits.chunks(8) {
+        let byte = byte.iter().fold(0_u8, |byte, &bit| byte << 1 | bit as u8);
+        bytes.push(byte);
+        if byte == 0xff {
+            bytes.push(0);
+        }
+    }
+    bytes.extend_from_slice(&[0xff, 0xd9]);
+
+    let result = decode_jpg(&mut &bytes[..]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+}
+
+#[test]
+fn test_load_jpg_adobe_rgb() {
+    let bytes = std::fs::read("./tests/templates/jpg/ycbcr_444.jpg").unwrap();
+    let ycbcr = load_jpg("./tests/templates/jpg/ycbcr_444.jpg").unwrap().to_rgb8();
+
+    // Adobe transform 0 keeps the three components as they are
+    let mut adobe = bytes[..2].to_vec();
+    adobe.extend_from_slice(&[0xff, 0xee, 0, 14]);
+    adobe.extend_from_slice(b"Adobe\0\x64\0\0\0\0\0");
+    adobe.extend_from_slice(&bytes[2..]);
+    let raw = decode_jpg(&mut &adobe[..]).unwrap().to_rgb8();
+    for (raw, pixel) in raw.pixels().zip(ycbcr.pixels()) {
+        let [r, g, b] = pixel.0.map(|c| c as f64);
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        assert!((raw.0[0] as f64 - luma).abs() <= 2., "{:?} {:?}", raw, pixel);
+    }
+}
+
+#[test]
+fn test_load_jpg_encoded() {
+    let data = [
+        [52, 55, 61, 66, 70, 61, 64, 73],
+        [63, 59, 55, 90, 109, 85, 69, 72],
+        [62, 59, 68, 113, 144, 104, 66, 73],
+        [63, 58, 71, 122, 154, 106, 70, 69],
+        [67, 61, 68, 104, 126, 88, 68, 70],
+        [79, 65, 60, 70, 77, 68, 58, 75],
+        [85, 71, 64, 59, 55, 61, 65, 83],
+        [87, 79, 69, 68, 65, 76, 78, 94],
+    ];
+    let image = load_jpg("./tests/templates/gray.jpg").unwrap().to_luma8();
+    let (mean, largest) = difference(
+        image.pixels().zip(data.iter().flatten()).map(|(pixel, &sample)| (pixel.0[0], sample)),
+    );
+    assert!(mean < 4. && largest <= 12, "mean {}, largest {}", mean, largest);
+}
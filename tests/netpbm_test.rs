@@ -1,6 +1,8 @@
 mod helper;
 
-use szimg::netpbm::{ save_pbm, save_pgm, save_ppm, Mode };
+use szimg::color::{LumaA, Rgb};
+use szimg::netpbm::{ save_pam_2d, save_pbm, save_pgm, save_ppm, Mode, TupleType, PAM, PPM };
+use szimg::{Error, Image, ImageBuffer};
 use helper::diff_file;
 
 #[test]
@@ -50,4 +52,41 @@ fn test_save_ppm() {
 }
 
 
-// Unfortunately our OS does not support .pam file
\ No newline at end of file
+// Unfortunately our OS does not support .pam file
+#[test]
+fn test_encode_ppm_to_vec() {
+    let image = ImageBuffer::from_fn(3, 2, |x, y| {
+        let colors = [
+            [[255, 0, 0], [0, 0, 255], [0, 0, 255]],
+            [[255, 255, 0], [255, 255, 255], [0, 0, 0]],
+        ];
+        Rgb(colors[y as usize][x as usize])
+    });
+    let bytes = PPM::from_buffer(&image, 255, Mode::Ascii).unwrap().encode_to_vec().unwrap();
+
+    assert_eq!(std::fs::read("./tests/templates/6_colors.ppm").unwrap(), bytes);
+}
+
+#[test]
+fn test_encode_pam_to_vec() {
+    let image = ImageBuffer::from_pixel(2, 1, LumaA([7_u8, 255]));
+    let bytes = PAM::from_buffer(&image).unwrap().encode_to_vec().unwrap();
+    let header = String::from_utf8_lossy(&bytes[..bytes.len() - 4]);
+
+    assert!(header.starts_with("P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\n"));
+    assert!(header.ends_with("TUPLETYPE GRAYSCALE_ALPHA\nENDHDR\n"));
+    assert_eq!(&[7, 255, 7, 255], &bytes[bytes.len() - 4..]);
+}
+
+#[test]
+fn test_save_netpbm_invalid() {
+    let result = save_pam_2d("./tests/output/invalid.pam", [[0_u8; 2]; 2], TupleType::RGB);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+
+    // PBM only has 0 and 1
+    let result = save_pbm("./tests/output/invalid.pbm", [[0_u8, 2]], Mode::Ascii);
+    assert!(matches!(result, Err(Error::FormatLimit(_))));
+
+    let result = save_pgm("./tests/output/invalid.pgm", [[0_u8, 16]], 15, Mode::Ascii);
+    assert!(matches!(result, Err(Error::FormatLimit(_))));
+}
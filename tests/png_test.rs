@@ -1,9 +1,14 @@
-mod helper;
-
-use szimg::png::save_png;
-use helper::diff_file;
+use szimg::png::{
+    decode_png, load_png, save_png, save_png_buffer, save_png_buffer_with_options, AncillaryChunk,
+    AnimatedPngEncoder, BlendOp, DisposeOp, FilterStrategy, FilterType, FrameControl, PhysicalUnit,
+    PngOptions, RenderingIntent, PNG,
+};
+use szimg::checksum::{Checksum, Crc32};
+use szimg::color::{Luma, LumaA, Rgb, Rgba};
+use szimg::{DynamicImage, Error, Image, ImageBuffer};
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn test_save_png_rgb() {
     let mut png_array = [[[0_u8; 3]; 255]; 255];
     for outer_index in 0..255 {
@@ -15,10 +20,15 @@ fn test_save_png_rgb() {
     }
     save_png("./tests/output/rgb.png", png_array).unwrap();
 
-    assert!(diff_file("./tests/output/rgb.png", "./tests/templates/rgb.png"));
+    let expected = ImageBuffer::from(png_array).map(|pixel| Rgb(*pixel));
+    assert_eq!(
+        DynamicImage::Rgb8(expected),
+        load_png("./tests/output/rgb.png").unwrap()
+    );
 }
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn test_save_png_rgba() {
     // Prepare data
     let mut png_array = [[[0_u8; 4]; 255]; 255];
@@ -34,5 +44,1060 @@ fn test_save_png_rgba() {
 
     save_png("./tests/output/rgba.png", png_array).unwrap();
 
-    assert!(diff_file("./tests/output/rgba.png", "./tests/templates/rgba.png"));
-}
\ No newline at end of file
+    let expected = ImageBuffer::from(png_array).map(|pixel| Rgba(*pixel));
+    assert_eq!(
+        DynamicImage::Rgba8(expected),
+        load_png("./tests/output/rgba.png").unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_buffer() {
+    let image = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    save_png_buffer("./tests/output/rgb_buffer.png", &image).unwrap();
+
+    assert_eq!(
+        DynamicImage::Rgb8(image),
+        load_png("./tests/output/rgb_buffer.png").unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_color_type() {
+    let image = ImageBuffer::from_fn(4, 4, |x, y| LumaA([(x * y) as u8, 255]));
+    save_png_buffer("./tests/output/gray_alpha.png", &image).unwrap();
+
+    let bytes = std::fs::read("./tests/output/gray_alpha.png").unwrap();
+    // Bit depth and color type in IHDR
+    assert_eq!(&[8, 4], &bytes[24..26]);
+    assert_eq!(
+        DynamicImage::LumaA8(image),
+        load_png("./tests/output/gray_alpha.png").unwrap()
+    );
+}
+
+#[test]
+fn test_encode_png_to_writer() {
+    let image = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    let png = PNG::from_buffer(&image).unwrap();
+
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    png.encode_to(&mut cursor).unwrap();
+    assert_eq!(png.encode_to_vec().unwrap(), cursor.get_ref()[..]);
+
+    cursor.set_position(0);
+    assert_eq!(DynamicImage::Rgb8(image), decode_png(&mut cursor).unwrap());
+}
+
+// Files written by earlier versions still decode to the same pixels
+#[test]
+fn test_load_png_template() {
+    let rgb = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    assert_eq!(
+        DynamicImage::Rgb8(rgb),
+        load_png("./tests/templates/rgb.png").unwrap()
+    );
+
+    let rgba = ImageBuffer::from_fn(255, 255, |x, y| {
+        Rgba([y as u8, x as u8, 128, ((x + y) / 2) as u8])
+    });
+    assert_eq!(
+        DynamicImage::Rgba8(rgba),
+        load_png("./tests/templates/rgba.png").unwrap()
+    );
+}
+
+// Formula the files in tests/templates/png were generated with
+// Their scanlines cycle through all five filter types
+fn sample(x: u32, y: u32, channel: u32, depth: u32) -> u16 {
+    (((x * 31 + y * 17 + channel * 59) * 2731 + x * y) % (1 << depth)) as u16
+}
+
+fn load_fixture(name: &str) -> DynamicImage {
+    let image = load_png(&format!("./tests/templates/png/{}.png", name)).unwrap();
+    // Interlacing must not change the pixels
+    let interlaced = format!("./tests/templates/png/{}_adam7.png", name);
+    if std::path::Path::new(&interlaced).exists() {
+        assert_eq!(image, load_png(&interlaced).unwrap(), "{}", name);
+    }
+    image
+}
+
+#[test]
+fn test_load_png_gray() {
+    for &depth in [1, 2, 4, 8].iter() {
+        let expected = ImageBuffer::from_fn(13, 11, |x, y| {
+            Luma([(sample(x, y, 0, depth) * 255 / ((1 << depth) - 1)) as u8])
+        });
+        let image = load_fixture(&format!("gray_{}", depth));
+        assert_eq!(DynamicImage::Luma8(expected), image, "depth {}", depth);
+    }
+
+    let expected = ImageBuffer::from_fn(13, 11, |x, y| Luma([sample(x, y, 0, 16)]));
+    assert_eq!(DynamicImage::Luma16(expected), load_fixture("gray_16"));
+}
+
+#[test]
+fn test_load_png_truecolor() {
+    let gray_alpha = ImageBuffer::from_fn(13, 11, |x, y| {
+        LumaA([sample(x, y, 0, 8) as u8, sample(x, y, 1, 8) as u8])
+    });
+    assert_eq!(
+        DynamicImage::LumaA8(gray_alpha),
+        load_fixture("gray_alpha_8")
+    );
+    let gray_alpha = ImageBuffer::from_fn(13, 11, |x, y| {
+        LumaA([sample(x, y, 0, 16), sample(x, y, 1, 16)])
+    });
+    assert_eq!(
+        DynamicImage::LumaA16(gray_alpha),
+        load_fixture("gray_alpha_16")
+    );
+
+    let rgb = ImageBuffer::from_fn(13, 11, |x, y| {
+        Rgb([0, 1, 2].map(|c| sample(x, y, c, 8) as u8))
+    });
+    assert_eq!(DynamicImage::Rgb8(rgb), load_fixture("rgb_8"));
+    let rgb = ImageBuffer::from_fn(13, 11, |x, y| Rgb([0, 1, 2].map(|c| sample(x, y, c, 16))));
+    assert_eq!(DynamicImage::Rgb16(rgb), load_fixture("rgb_16"));
+
+    let rgba = ImageBuffer::from_fn(13, 11, |x, y| {
+        Rgba([0, 1, 2, 3].map(|c| sample(x, y, c, 8) as u8))
+    });
+    assert_eq!(DynamicImage::Rgba8(rgba), load_fixture("rgba_8"));
+    let rgba = ImageBuffer::from_fn(13, 11, |x, y| {
+        Rgba([0, 1, 2, 3].map(|c| sample(x, y, c, 16)))
+    });
+    assert_eq!(DynamicImage::Rgba16(rgba), load_fixture("rgba_16"));
+}
+
+#[test]
+fn test_load_png_palette() {
+    let color = |index: u32| {
+        [
+            (index * 37 % 256) as u8,
+            (index * 91 % 256) as u8,
+            (255 - index) as u8,
+        ]
+    };
+    // Only the first half of the entries have alpha in tRNS
+    let alpha = |index: u32, depth: u32| {
+        if index < (1 << depth) / 2 {
+            (index * 53 % 256) as u8
+        } else {
+            255
+        }
+    };
+
+    for &depth in [1, 4].iter() {
+        let expected =
+            ImageBuffer::from_fn(13, 11, |x, y| Rgb(color(sample(x, y, 0, depth) as u32)));
+        let image = load_fixture(&format!("palette_{}", depth));
+        assert_eq!(DynamicImage::Rgb8(expected), image, "depth {}", depth);
+    }
+    for &depth in [2, 8].iter() {
+        let expected = ImageBuffer::from_fn(13, 11, |x, y| {
+            let index = sample(x, y, 0, depth) as u32;
+            let [r, g, b] = color(index);
+            Rgba([r, g, b, alpha(index, depth)])
+        });
+        let image = load_fixture(&format!("palette_{}", depth));
+        assert_eq!(DynamicImage::Rgba8(expected), image, "depth {}", depth);
+    }
+}
+
+#[test]
+fn test_load_png_color_key() {
+    let expected = ImageBuffer::from_fn(13, 11, |x, y| {
+        let value = sample(x, y, 0, 8);
+        LumaA([
+            value as u8,
+            if value == sample(0, 0, 0, 8) { 0 } else { 255 },
+        ])
+    });
+    assert_eq!(DynamicImage::LumaA8(expected), load_fixture("gray_8_trns"));
+
+    let image = load_fixture("rgb_16_trns");
+    let DynamicImage::Rgba16(image) = image else {
+        panic!("expected 16-bit RGBA, got {:?}", image.color_type());
+    };
+    assert_eq!(0, image[(0, 0)][3]);
+    assert_eq!(u16::MAX, image[(1, 0)][3]);
+}
+
+#[test]
+fn test_load_png_compression() {
+    let rgb = load_fixture("rgb_8");
+    // Stored, fixed Huffman and IDAT split into three chunks around a tEXt chunk
+    assert_eq!(rgb, load_fixture("rgb_8_stored"));
+    assert_eq!(rgb, load_fixture("rgb_8_fixed"));
+    assert_eq!(rgb, load_fixture("rgb_8_split"));
+
+    // Dynamic Huffman
+    let expected = ImageBuffer::from_fn(96, 80, |x, y| {
+        Rgb([0, 1, 2].map(|c| sample(x, y, c, 8) as u8))
+    });
+    assert_eq!(DynamicImage::Rgb8(expected), load_fixture("rgb_8_dynamic"));
+}
+
+#[test]
+fn test_load_png_invalid() {
+    let image = ImageBuffer::from_fn(16, 16, |x, y| Rgb([x as u8, y as u8, 0]));
+    let bytes = PNG::from_buffer(&image).unwrap().encode_to_vec().unwrap();
+
+    let result = decode_png(&mut &bytes[1..]);
+    assert!(matches!(result, Err(Error::Decoding(_))));
+
+    // Flip one bit of the IHDR CRC
+    let mut corrupted = bytes.clone();
+    corrupted[29] ^= 1;
+    assert!(matches!(
+        decode_png(&mut &corrupted[..]),
+        Err(Error::Decoding(_))
+    ));
+
+    // Missing IEND
+    let truncated = &bytes[..bytes.len() - 12];
+    assert!(matches!(
+        decode_png(&mut &truncated[..]),
+        Err(Error::Decoding(_))
+    ));
+
+    // Half the height in IHDR, so the image data inflates to twice its size
+    let mut shortened = bytes.clone();
+    shortened[20..24].copy_from_slice(&8_u32.to_be_bytes());
+    let crc = Crc32::checksum(&shortened[12..29]);
+    shortened[29..33].copy_from_slice(&crc.to_be_bytes());
+    assert!(matches!(
+        decode_png(&mut &shortened[..]),
+        Err(Error::Decoding(_))
+    ));
+}
+
+#[test]
+fn test_save_png_invalid() {
+    let empty = ImageBuffer::<Rgb<u8>>::new(0, 10);
+    assert!(matches!(
+        PNG::from_buffer(&empty),
+        Err(Error::InvalidDimensions {
+            width: 0,
+            height: 10
+        })
+    ));
+
+    let options = PngOptions {
+        level: 10,
+        ..PngOptions::default()
+    };
+    assert!(matches!(
+        PNG::from_buffer_with_options(&ImageBuffer::<Rgb<u8>>::new(1, 1), &options),
+        Err(Error::Unsupported(_))
+    ));
+
+    let result = save_png("./tests/output/five_channels.png", [[[0_u8; 5]; 2]; 2]);
+    assert!(matches!(result, Err(Error::Unsupported(_))));
+}
+
+#[test]
+fn test_save_png_levels() {
+    let image = ImageBuffer::from_fn(300, 200, |x, y| {
+        Rgba([(x / 10) as u8, (y / 10) as u8, ((x * y) % 7) as u8, 255])
+    });
+    let mut sizes = Vec::new();
+    for level in 0..=9 {
+        let path = format!("./tests/output/level_{}.png", level);
+        let options = PngOptions {
+            level,
+            ..PngOptions::default()
+        };
+        save_png_buffer_with_options(&path, &image, &options).unwrap();
+        assert_eq!(DynamicImage::Rgba8(image.clone()), load_png(&path).unwrap());
+        sizes.push(std::fs::metadata(&path).unwrap().len());
+    }
+    // Stored is the largest and the best level beats the fastest one
+    assert!(sizes[1..].iter().all(|&size| size < sizes[0]));
+    assert!(sizes[9] <= sizes[1]);
+}
+
+#[test]
+fn test_save_png_flat() {
+    // A mostly flat plot shrinks by orders of magnitude
+    let image = ImageBuffer::from_fn(1000, 1000, |x, y| {
+        if x == y {
+            Rgb([255, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        }
+    });
+    let bytes = PNG::from_buffer(&image).unwrap().encode_to_vec().unwrap();
+    assert!(bytes.len() < 3_000_000 / 100, "{} bytes", bytes.len());
+    assert_eq!(
+        DynamicImage::Rgb8(image),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+
+    // Rows no longer have to fit in a single deflate block
+    let wide = ImageBuffer::from_fn(30000, 2, |x, y| Rgb([x as u8, y as u8, 0]));
+    let bytes = PNG::from_buffer(&wide).unwrap().encode_to_vec().unwrap();
+    assert_eq!(
+        DynamicImage::Rgb8(wide),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_filters() {
+    // The gradient of rgb.png
+    let image = ImageBuffer::from_fn(255, 255, |x, y| Rgb([y as u8, x as u8, 128]));
+    let strategies = [
+        FilterStrategy::Fixed(FilterType::None),
+        FilterStrategy::Fixed(FilterType::Sub),
+        FilterStrategy::Fixed(FilterType::Up),
+        FilterStrategy::Fixed(FilterType::Average),
+        FilterStrategy::Fixed(FilterType::Paeth),
+        FilterStrategy::Adaptive,
+        FilterStrategy::BruteForce,
+    ];
+    let mut sizes = Vec::new();
+    for &filter in strategies.iter() {
+        let options = PngOptions {
+            filter,
+            ..PngOptions::default()
+        };
+        let png = PNG::from_buffer_with_options(&image, &options).unwrap();
+        let bytes = png.encode_to_vec().unwrap();
+        assert_eq!(
+            DynamicImage::Rgb8(image.clone()),
+            decode_png(&mut &bytes[..]).unwrap(),
+            "{:?}",
+            filter
+        );
+        sizes.push(bytes.len());
+    }
+
+    // Smooth gradients compress far better once filtered
+    assert!(sizes[5] * 4 < sizes[0], "{:?}", sizes);
+    assert_eq!(sizes.iter().min(), Some(&sizes[6]));
+}
+
+#[test]
+fn test_save_png_filters_fallback() {
+    fn encode<P: szimg::color::Pixel>(image: &ImageBuffer<P>, options: &PngOptions) -> Vec<u8> {
+        PNG::from_buffer_with_options(image, options)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap()
+    }
+    let none = FilterStrategy::Fixed(FilterType::None);
+
+    // The default adaptive filter leaves indexed and sub-byte images unfiltered
+    let colors = ImageBuffer::from_fn(60, 40, |x, y| Rgb([(x * 4) as u8, 0, (y % 3) as u8]));
+    let indexed = PngOptions {
+        indexed: true,
+        ..PngOptions::default()
+    };
+    let unfiltered = PngOptions {
+        filter: none,
+        ..indexed.clone()
+    };
+    assert_eq!(encode(&colors, &indexed), encode(&colors, &unfiltered));
+
+    let gray = ImageBuffer::from_fn(60, 40, |x, y| Luma([((x + y) % 16) as u8]));
+    let low_depth = PngOptions {
+        bit_depth: Some(4),
+        ..PngOptions::default()
+    };
+    let unfiltered = PngOptions {
+        filter: none,
+        ..low_depth.clone()
+    };
+    assert_eq!(encode(&gray, &low_depth), encode(&gray, &unfiltered));
+
+    // Whereas 8-bit samples of the same image get filtered
+    let unfiltered = PngOptions {
+        filter: none,
+        ..PngOptions::default()
+    };
+    assert_ne!(
+        encode(&gray, &PngOptions::default()),
+        encode(&gray, &unfiltered)
+    );
+}
+
+#[test]
+fn test_save_png_16bit() {
+    let gray = ImageBuffer::from_fn(40, 30, |x, y| Luma([(x * 1000 + y * 7) as u16]));
+    let bytes = PNG::from_buffer(&gray).unwrap().encode_to_vec().unwrap();
+    // Bit depth and color type in IHDR
+    assert_eq!(&[16, 0], &bytes[24..26]);
+    assert_eq!(
+        DynamicImage::Luma16(gray),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+
+    let rgb = ImageBuffer::from_fn(40, 30, |x, y| {
+        Rgb([(x * 1500) as u16, (y * 2000) as u16, 0x1234])
+    });
+    save_png_buffer("./tests/output/rgb_16.png", &rgb).unwrap();
+    assert_eq!(
+        DynamicImage::Rgb16(rgb),
+        load_png("./tests/output/rgb_16.png").unwrap()
+    );
+
+    let rgba = ImageBuffer::from_fn(40, 30, |x, y| Rgba([x as u16, y as u16, 300, 65535]));
+    let bytes = PNG::from_buffer(&rgba).unwrap().encode_to_vec().unwrap();
+    assert_eq!(
+        DynamicImage::Rgba16(rgba),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_low_depth() {
+    for &depth in [1, 2, 4].iter() {
+        let max = (1 << depth) - 1;
+        // Only levels the depth can express survive the round trip
+        let image = ImageBuffer::from_fn(37, 19, |x, y| {
+            Luma([((x + y) % (max + 1) * 255 / max) as u8])
+        });
+        let options = PngOptions {
+            bit_depth: Some(depth as u8),
+            ..PngOptions::default()
+        };
+        let bytes = PNG::from_buffer_with_options(&image, &options)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap();
+        assert_eq!(&[depth as u8, 0], &bytes[24..26]);
+        assert_eq!(
+            DynamicImage::Luma8(image),
+            decode_png(&mut &bytes[..]).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_save_png_indexed() {
+    let colors = [[255, 255, 255], [200, 30, 30], [30, 30, 200]];
+    let image = ImageBuffer::from_fn(100, 50, |x, y| Rgb(colors[((x / 7 + y) % 3) as usize]));
+    let options = PngOptions {
+        indexed: true,
+        ..PngOptions::default()
+    };
+    save_png_buffer_with_options("./tests/output/indexed.png", &image, &options).unwrap();
+    let bytes = std::fs::read("./tests/output/indexed.png").unwrap();
+    // Three colors fit in 2 bits
+    assert_eq!(&[2, 3], &bytes[24..26]);
+    assert_eq!(b"PLTE", &bytes[37..41]);
+    assert_eq!(
+        DynamicImage::Rgb8(image),
+        load_png("./tests/output/indexed.png").unwrap()
+    );
+
+    // Translucent colors go into tRNS
+    let image = ImageBuffer::from_fn(64, 64, |x, y| {
+        Rgba([x as u8 * 4, 0, 0, (y % 2) as u8 * 255])
+    });
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert_eq!(&[8, 3], &bytes[24..26]);
+    assert_eq!(
+        DynamicImage::Rgba8(image),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+
+    // Gray works too, it is expanded back to RGB
+    let image = ImageBuffer::from_fn(9, 9, |x, _| Luma([if x % 2 == 0 { 0 } else { 255 }]));
+    let options = PngOptions {
+        indexed: true,
+        bit_depth: Some(4),
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert_eq!(&[4, 3], &bytes[24..26]);
+    assert_eq!(image, decode_png(&mut &bytes[..]).unwrap().to_luma8());
+}
+
+#[test]
+fn test_save_png_depth_invalid() {
+    let many_colors = ImageBuffer::from_fn(300, 1, |x, _| Rgb([x as u8, (x / 256) as u8, 0]));
+    let options = PngOptions {
+        indexed: true,
+        ..PngOptions::default()
+    };
+    assert!(matches!(
+        PNG::from_buffer_with_options(&many_colors, &options),
+        Err(Error::FormatLimit(_))
+    ));
+    let options = PngOptions {
+        indexed: true,
+        bit_depth: Some(1),
+        ..PngOptions::default()
+    };
+    let three_colors = ImageBuffer::from_fn(3, 1, |x, _| Luma([x as u8]));
+    assert!(matches!(
+        PNG::from_buffer_with_options(&three_colors, &options),
+        Err(Error::FormatLimit(_))
+    ));
+
+    // Only gray goes below 8 bits without a palette
+    let options = PngOptions {
+        bit_depth: Some(4),
+        ..PngOptions::default()
+    };
+    assert!(matches!(
+        PNG::from_buffer_with_options(&many_colors, &options),
+        Err(Error::Unsupported(_))
+    ));
+    let options = PngOptions {
+        indexed: true,
+        ..PngOptions::default()
+    };
+    let wide = ImageBuffer::from_pixel(2, 2, Luma([0_u16]));
+    assert!(matches!(
+        PNG::from_buffer_with_options(&wide, &options),
+        Err(Error::Unsupported(_))
+    ));
+}
+
+// Name and data of each chunk in a PNG file
+fn chunks(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut chunks = Vec::new();
+    let mut position = 8;
+    while position < bytes.len() {
+        let length = u32::from_be_bytes([
+            bytes[position],
+            bytes[position + 1],
+            bytes[position + 2],
+            bytes[position + 3],
+        ]) as usize;
+        let name = String::from_utf8_lossy(&bytes[position + 4..position + 8]).to_string();
+        let data = bytes[position + 8..position + 8 + length].to_vec();
+        chunks.push((name, data));
+        position += length + 12;
+    }
+    chunks
+}
+
+#[test]
+fn test_save_png_ancillary() {
+    let image = ImageBuffer::from_fn(16, 16, |x, y| Rgb([x as u8 * 16, y as u8 * 16, 0]));
+    let options = PngOptions {
+        chunks: vec![
+            AncillaryChunk::Gamma(45455),
+            AncillaryChunk::Chromaticities {
+                white: (31270, 32900),
+                red: (64000, 33000),
+                green: (30000, 60000),
+                blue: (15000, 6000),
+            },
+            AncillaryChunk::Srgb(RenderingIntent::Perceptual),
+            AncillaryChunk::from_dpi(300.0),
+            AncillaryChunk::Time {
+                year: 2021,
+                month: 7,
+                day: 14,
+                hour: 9,
+                minute: 30,
+                second: 0,
+            },
+            AncillaryChunk::Text {
+                keyword: "Software".to_string(),
+                text: "szimg".to_string(),
+            },
+            AncillaryChunk::CompressedText {
+                keyword: "Comment".to_string(),
+                text: "max_iteration = 255, ".repeat(20),
+            },
+            AncillaryChunk::InternationalText {
+                keyword: "Title".to_string(),
+                language: "zh-CN".to_string(),
+                translated_keyword: "标题".to_string(),
+                text: "曼德博集合".to_string(),
+                compressed: true,
+            },
+            AncillaryChunk::Custom {
+                name: *b"prVn",
+                data: b"d66ce75".to_vec(),
+            },
+        ],
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+
+    let chunks = chunks(&bytes);
+    let names: Vec<&str> = chunks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        vec![
+            "IHDR", "gAMA", "cHRM", "sRGB", "pHYs", "tIME", "tEXt", "zTXt", "iTXt", "prVn", "IDAT",
+            "IEND"
+        ],
+        names
+    );
+    assert_eq!(vec![0, 0, 0xb1, 0x8f], chunks[1].1);
+    assert_eq!(vec![0], chunks[3].1);
+    // 300 DPI is 11811 pixels per meter
+    assert_eq!(vec![0, 0, 0x2e, 0x23, 0, 0, 0x2e, 0x23, 1], chunks[4].1);
+    assert_eq!(vec![0x07, 0xe5, 7, 14, 9, 30, 0], chunks[5].1);
+    assert_eq!(b"Software\0szimg".to_vec(), chunks[6].1);
+    assert!(chunks[7].1.starts_with(b"Comment\0\0"));
+    assert!(chunks[7].1.len() < 100);
+    assert!(chunks[8].1.starts_with(b"Title\0\x01\0zh-CN\0"));
+    assert_eq!(b"d66ce75".to_vec(), chunks[9].1);
+
+    // Decoders skip the metadata
+    assert_eq!(
+        DynamicImage::Rgb8(image),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_ancillary_icc() {
+    let image = ImageBuffer::from_pixel(4, 4, Luma([7_u8]));
+    let options = PngOptions {
+        chunks: vec![
+            AncillaryChunk::IccProfile {
+                name: "Display P3".to_string(),
+                profile: vec![0; 512],
+            },
+            AncillaryChunk::PhysicalSize {
+                x: 2,
+                y: 1,
+                unit: PhysicalUnit::Unknown,
+            },
+        ],
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    let chunks = chunks(&bytes);
+    assert_eq!("iCCP", chunks[1].0);
+    assert!(chunks[1].1.starts_with(b"Display P3\0\0"));
+    assert_eq!(vec![0, 0, 0, 2, 0, 0, 0, 1, 0], chunks[2].1);
+    assert_eq!(
+        DynamicImage::Luma8(image),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_ancillary_palette() {
+    let image = ImageBuffer::from_fn(4, 4, |x, _| Rgba([x as u8 * 60, 0, 0, x as u8 * 80]));
+    let options = PngOptions {
+        indexed: true,
+        chunks: vec![
+            AncillaryChunk::Custom {
+                name: *b"bKGD",
+                data: vec![0],
+            },
+            AncillaryChunk::Gamma(45455),
+        ],
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+
+    // The background is a palette index, so it follows PLTE and tRNS
+    let chunks = chunks(&bytes);
+    let names: Vec<&str> = chunks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        vec!["IHDR", "gAMA", "PLTE", "tRNS", "bKGD", "IDAT", "IEND"],
+        names
+    );
+}
+
+#[test]
+fn test_save_png_ancillary_invalid() {
+    let image = ImageBuffer::from_pixel(1, 1, Luma([0_u8]));
+    let text = |keyword: &str, text: &str| AncillaryChunk::Text {
+        keyword: keyword.to_string(),
+        text: text.to_string(),
+    };
+    let invalid = vec![
+        vec![text("", "empty keyword")],
+        vec![text(" Title", "leading space")],
+        vec![text("Double  space", "")],
+        vec![text(&"k".repeat(80), "keyword too long")],
+        vec![text("Title", "not Latin-1 ✓")],
+        vec![AncillaryChunk::Gamma(1), AncillaryChunk::Gamma(2)],
+        vec![
+            AncillaryChunk::Srgb(RenderingIntent::Saturation),
+            AncillaryChunk::IccProfile {
+                name: "Profile".to_string(),
+                profile: Vec::new(),
+            },
+        ],
+        vec![AncillaryChunk::Custom {
+            name: *b"GITH",
+            data: Vec::new(),
+        }],
+        vec![AncillaryChunk::Custom {
+            name: *b"tRNS",
+            data: vec![0],
+        }],
+        vec![AncillaryChunk::Custom {
+            name: *b"acTL",
+            data: vec![0; 8],
+        }],
+        // Typed chunks can not be repeated as custom ones, in either order
+        vec![
+            AncillaryChunk::from_dpi(72.0),
+            AncillaryChunk::Custom {
+                name: *b"pHYs",
+                data: vec![0; 9],
+            },
+        ],
+        vec![
+            AncillaryChunk::Custom {
+                name: *b"gAMA",
+                data: vec![0; 4],
+            },
+            AncillaryChunk::Gamma(45455),
+        ],
+        vec![AncillaryChunk::Time {
+            year: 2021,
+            month: 13,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }],
+    ];
+    for chunks in invalid {
+        let options = PngOptions {
+            chunks,
+            ..PngOptions::default()
+        };
+        assert!(matches!(
+            PNG::from_buffer_with_options(&image, &options),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    // Text chunks can be repeated
+    let options = PngOptions {
+        chunks: vec![
+            text("Title", "a"),
+            text("Title", "b"),
+            AncillaryChunk::time_now(),
+        ],
+        ..PngOptions::default()
+    };
+    assert!(PNG::from_buffer_with_options(&image, &options).is_ok());
+}
+
+#[test]
+fn test_save_png_interlace() {
+    // Sizes where some of the seven passes are empty
+    for &(width, height) in [(1, 1), (3, 2), (5, 9), (33, 17)].iter() {
+        let image = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgba([x as u8 * 7, y as u8 * 13, (x * y) as u8, 255 - x as u8])
+        });
+        for &filter in [FilterStrategy::Adaptive, FilterStrategy::BruteForce].iter() {
+            let options = PngOptions {
+                interlace: true,
+                filter,
+                ..PngOptions::default()
+            };
+            let bytes = PNG::from_buffer_with_options(&image, &options)
+                .unwrap()
+                .encode_to_vec()
+                .unwrap();
+            assert_eq!(1, bytes[28]);
+            assert_eq!(
+                DynamicImage::Rgba8(image.clone()),
+                decode_png(&mut &bytes[..]).unwrap()
+            );
+        }
+    }
+
+    // Sub-byte pixels are moved bit by bit
+    let image = ImageBuffer::from_fn(19, 11, |x, y| Luma([((x + y * 3) % 4) as u8 * 85]));
+    for &depth in [1, 2, 4].iter() {
+        let options = PngOptions {
+            interlace: true,
+            bit_depth: Some(depth),
+            ..PngOptions::default()
+        };
+        let bytes = PNG::from_buffer_with_options(&image, &options)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap();
+        let expected = PNG::from_buffer_with_options(
+            &image,
+            &PngOptions {
+                bit_depth: Some(depth),
+                ..PngOptions::default()
+            },
+        )
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+        assert_eq!(
+            decode_png(&mut &expected[..]).unwrap(),
+            decode_png(&mut &bytes[..]).unwrap()
+        );
+    }
+
+    let options = PngOptions {
+        interlace: true,
+        indexed: true,
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert_eq!(image, decode_png(&mut &bytes[..]).unwrap().to_luma8());
+
+    let image = ImageBuffer::from_fn(10, 7, |x, y| Rgb([x as u16 * 6553, y as u16 * 9362, 1]));
+    let options = PngOptions {
+        interlace: true,
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert_eq!(
+        DynamicImage::Rgb16(image),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+// Zlib stream of the IDAT chunk, when the image is saved on its own
+fn image_data<P: szimg::color::Pixel>(image: &ImageBuffer<P>) -> Vec<u8> {
+    let bytes = PNG::from_buffer(image).unwrap().encode_to_vec().unwrap();
+    chunks(&bytes)
+        .into_iter()
+        .find(|(name, _)| name == "IDAT")
+        .unwrap()
+        .1
+}
+
+#[test]
+fn test_save_apng() {
+    // Julia sets for a range of C values, the later frames only redraw the center
+    let julia = |size: u32, c: f64| {
+        ImageBuffer::from_fn(size, size, |x, y| {
+            let mut z = (
+                x as f64 / size as f64 * 3.0 - 1.5,
+                y as f64 / size as f64 * 3.0 - 1.5,
+            );
+            let mut i = 0;
+            while i < 32 && z.0 * z.0 + z.1 * z.1 < 4.0 {
+                z = (z.0 * z.0 - z.1 * z.1 + c, 2.0 * z.0 * z.1 + 0.3);
+                i += 1;
+            }
+            Rgba([i * 7, 0, 255 - i * 7, 255])
+        })
+    };
+    let first = julia(48, -0.8);
+    let frames = [julia(24, -0.7), julia(24, -0.6)];
+
+    let mut encoder = AnimatedPngEncoder::new(48, 48).unwrap();
+    encoder.set_num_plays(3);
+    encoder.add_frame(&first, FrameControl::default()).unwrap();
+    for frame in frames.iter() {
+        let control = FrameControl {
+            x_offset: 12,
+            y_offset: 12,
+            delay_num: 1,
+            delay_den: 30,
+            dispose: DisposeOp::Previous,
+            blend: BlendOp::Over,
+        };
+        encoder.add_frame(frame, control).unwrap();
+    }
+    encoder.dump("./tests/output/julia.apng").unwrap();
+    let bytes = encoder.encode_to_vec().unwrap();
+
+    let chunks = chunks(&bytes);
+    let names: Vec<&str> = chunks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"],
+        names
+    );
+    assert_eq!(vec![0, 0, 0, 3, 0, 0, 0, 3], chunks[1].1);
+    // Sequence number, size, offset, delay, dispose and blend
+    assert_eq!(
+        vec![0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 48, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 10, 0, 0],
+        chunks[2].1
+    );
+    assert_eq!(
+        vec![0, 0, 0, 3, 0, 0, 0, 24, 0, 0, 0, 24, 0, 0, 0, 12, 0, 0, 0, 12, 0, 1, 0, 30, 2, 1],
+        chunks[6].1
+    );
+    assert_eq!(image_data(&first), chunks[3].1);
+    assert_eq!(&[0, 0, 0, 2], &chunks[5].1[..4]);
+    assert_eq!(image_data(&frames[0]), &chunks[5].1[4..]);
+    assert_eq!(&[0, 0, 0, 4], &chunks[7].1[..4]);
+    assert_eq!(image_data(&frames[1]), &chunks[7].1[4..]);
+
+    // Viewers without APNG support show the first frame
+    assert_eq!(
+        DynamicImage::Rgba8(first),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_save_apng_invalid() {
+    let canvas = ImageBuffer::from_pixel(8, 8, Luma([0_u8]));
+    let small = ImageBuffer::from_pixel(4, 4, Luma([0_u8]));
+
+    let mut encoder = AnimatedPngEncoder::new(8, 8).unwrap();
+    assert!(matches!(
+        encoder.encode_to_vec(),
+        Err(Error::Unsupported(_))
+    ));
+    // The first frame is the default image and covers the canvas
+    assert!(matches!(
+        encoder.add_frame(&small, FrameControl::default()),
+        Err(Error::InvalidDimensions { .. })
+    ));
+    encoder.add_frame(&canvas, FrameControl::default()).unwrap();
+    let outside = FrameControl {
+        x_offset: 5,
+        ..FrameControl::default()
+    };
+    assert!(matches!(
+        encoder.add_frame(&small, outside),
+        Err(Error::InvalidDimensions { .. })
+    ));
+    let inside = FrameControl {
+        x_offset: 4,
+        y_offset: 4,
+        ..FrameControl::default()
+    };
+    assert!(encoder.add_frame(&small, inside).is_ok());
+
+    let options = PngOptions {
+        indexed: true,
+        ..PngOptions::default()
+    };
+    assert!(matches!(
+        AnimatedPngEncoder::<Luma<u8>>::with_options(8, 8, &options),
+        Err(Error::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_save_png_split() {
+    // Rows of 90000 bytes, wider than a stored deflate block
+    let image = ImageBuffer::from_fn(30000, 4, |x, y| {
+        Rgb([(x * 7 + y) as u8, (x / 256) as u8, (x ^ y) as u8])
+    });
+    let options = PngOptions {
+        level: 0,
+        block_size: 4096,
+        chunk_size: 8192,
+        ..PngOptions::default()
+    };
+    let bytes = PNG::from_buffer_with_options(&image, &options)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+
+    let idat_chunks: Vec<Vec<u8>> = chunks(&bytes)
+        .into_iter()
+        .filter(|(name, _)| name == "IDAT")
+        .map(|(_, data)| data)
+        .collect();
+    let (last, full) = idat_chunks.split_last().unwrap();
+    assert!(full.iter().all(|data| data.len() == 8192));
+    assert!(!last.is_empty() && last.len() <= 8192);
+
+    // Every stored block holds at most the block size
+    let stream = idat_chunks.concat();
+    let mut position = 2;
+    loop {
+        let header = stream[position];
+        assert_eq!(0, header & 0b110);
+        let length = u16::from_le_bytes([stream[position + 1], stream[position + 2]]);
+        assert!(length as usize <= 4096);
+        position += 5 + length as usize;
+        if header & 1 == 1 {
+            break;
+        }
+    }
+    assert_eq!(stream.len(), position + 4);
+    assert_eq!(
+        DynamicImage::Rgb8(image.clone()),
+        decode_png(&mut &bytes[..]).unwrap()
+    );
+
+    for &level in [1, 6, 9].iter() {
+        let options = PngOptions {
+            level,
+            block_size: 1024,
+            chunk_size: 1000,
+            ..PngOptions::default()
+        };
+        let bytes = PNG::from_buffer_with_options(&image, &options)
+            .unwrap()
+            .encode_to_vec()
+            .unwrap();
+        assert!(
+            chunks(&bytes)
+                .iter()
+                .filter(|(name, _)| name == "IDAT")
+                .count()
+                > 1
+        );
+        assert_eq!(
+            DynamicImage::Rgb8(image.clone()),
+            decode_png(&mut &bytes[..]).unwrap()
+        );
+    }
+
+    let tall = ImageBuffer::from_fn(3, 30000, |x, y| Luma([(x * y) as u8]));
+    save_png_buffer("./tests/output/tall.png", &tall).unwrap();
+    assert_eq!(
+        DynamicImage::Luma8(tall),
+        load_png("./tests/output/tall.png").unwrap()
+    );
+}
+
+#[test]
+fn test_save_png_split_invalid() {
+    let image = ImageBuffer::from_pixel(2, 2, Luma([0_u8]));
+    for options in [
+        PngOptions {
+            block_size: 1023,
+            ..PngOptions::default()
+        },
+        PngOptions {
+            block_size: 65536,
+            ..PngOptions::default()
+        },
+        PngOptions {
+            chunk_size: 0,
+            ..PngOptions::default()
+        },
+        PngOptions {
+            chunk_size: 1 << 31,
+            ..PngOptions::default()
+        },
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            PNG::from_buffer_with_options(&image, options),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}